@@ -1,23 +1,139 @@
 ﻿using System;
 using System.Collections;
 using System.Runtime.InteropServices;
+using System.Text;
 using UnityEngine;
 
 /// <summary>
-/// Copy RenderingInterceptor.dll from target/debug into Plugins/x86_64 and Attach this script to Main Camera
+/// Copy RenderingInterceptor.dll (libRenderingInterceptor.so on Linux) from target/debug into Plugins/x86_64 and Attach this script to Main Camera
+/// (without this script, the frames Unity presents are captured instead)
 /// </summary>
 public class NativeRenderInteceptor : MonoBehaviour
 {
     [DllImport("RenderingInterceptor")]
     private static extern IntPtr rendering_event_ptr();
     [DllImport("RenderingInterceptor")]
-    private static extern void set_render_buffer(IntPtr buf);
+    private static extern IntPtr rendering_event_and_data_ptr();
+    [DllImport("RenderingInterceptor")]
+    private static extern int plugin_event_id(int kind);
+    [DllImport("RenderingInterceptor")]
+    [return: MarshalAs(UnmanagedType.I1)]
+    private static extern bool request_screenshot(byte[] path);
+    [DllImport("RenderingInterceptor")]
+    private static extern int screenshot_status();
+    [DllImport("RenderingInterceptor")]
+    [return: MarshalAs(UnmanagedType.I1)]
+    private static extern bool capture_texture(IntPtr texture, uint id);
+    [DllImport("RenderingInterceptor")]
+    [return: MarshalAs(UnmanagedType.I1)]
+    private static extern bool set_sink_enabled(uint id, [MarshalAs(UnmanagedType.I1)] bool enabled);
+    [DllImport("RenderingInterceptor")]
+    [return: MarshalAs(UnmanagedType.I1)]
+    private static extern bool set_frames_in_flight(uint count);
+    [DllImport("RenderingInterceptor")]
+    [return: MarshalAs(UnmanagedType.I1)]
+    private static extern bool set_scaling_mode(int mode);
+    [DllImport("RenderingInterceptor")]
+    [return: MarshalAs(UnmanagedType.I1)]
+    private static extern bool set_capture_region(int x, int y, int width, int height);
+    [DllImport("RenderingInterceptor")]
+    [return: MarshalAs(UnmanagedType.I1)]
+    private static extern bool set_sink_transform(uint id, int flags);
+    [DllImport("RenderingInterceptor")]
+    private static extern int last_error_message(byte[] buffer, int capacity);
+    [DllImport("RenderingInterceptor")]
+    [return: MarshalAs(UnmanagedType.I1)]
+    private static extern bool unity_renders_offscreen();
+
+    public const uint WindowSinkId = 0;
+    public const uint ScreenshotSinkId = 1;
+    /// <summary>Target of <see cref="Capture"/> feeding every enabled output</summary>
+    public const uint AllSinks = uint.MaxValue;
+
+    /// <summary>
+    /// Parameters of one capture event (CaptureEventData in the plugin)
+    /// </summary>
+    [StructLayout(LayoutKind.Sequential)]
+    private struct CaptureEventData
+    {
+        public IntPtr renderBuffer;
+        public uint target;
+        public int regionX, regionY, regionWidth, regionHeight;
+        public ulong frameTag;
+    }
+    // the render thread reads a block after the event is issued, so each one is reused only after this many more events
+    private const int EventDataBlocks = 16;
+    private IntPtr eventData = IntPtr.Zero;
+    private int nextEventData = 0;
+
+    /// <summary>
+    /// Events the plugin handles on the render thread. Unity assigns their ids when the plugin is loaded (see <see cref="EventId"/>).
+    /// </summary>
+    public enum EventKind
+    {
+        /// <summary>Captures a render buffer for the outputs</summary>
+        Capture = 0,
+        /// <summary>Captures a render buffer for the screenshot requested by <see cref="RequestScreenshot"/> only</summary>
+        Screenshot = 1,
+        /// <summary>Turns the readback on</summary>
+        StartRecording = 2,
+        /// <summary>Turns the readback off</summary>
+        StopRecording = 3,
+        /// <summary>Waits for the frames being copied and delivers them</summary>
+        Flush = 4
+    }
+
+    public enum ScreenshotStatus
+    {
+        Failed = -1,
+        Idle = 0,
+        Pending = 1,
+        Writing = 2,
+        Completed = 3
+    }
+
+    public enum ScalingMode
+    {
+        /// <summary>Stretch over the whole window</summary>
+        Stretch = 0,
+        /// <summary>Keep the aspect ratio and fit in the window with black bars</summary>
+        Fit = 1,
+        /// <summary>Keep the aspect ratio and cover the whole window, cropping the overflow</summary>
+        Fill = 2,
+        /// <summary>Largest integer multiple that fits, without filtering (for pixel art)</summary>
+        Integer = 3,
+        /// <summary>1:1 at the center of the window</summary>
+        Center = 4
+    }
+
+    /// <summary>
+    /// One rotation combined with flips. The flips are applied before the rotation.
+    /// </summary>
+    [Flags]
+    public enum OutputTransform
+    {
+        None = 0,
+        /// <summary>Quarter turn clockwise</summary>
+        Rotate90 = 1,
+        Rotate180 = 2,
+        Rotate270 = 3,
+        /// <summary>Mirror left and right</summary>
+        FlipX = 4,
+        /// <summary>Mirror top and bottom</summary>
+        FlipY = 8
+    }
 
     // Start is called before the first frame update
     void Start()
     {
+        this.eventData = Marshal.AllocHGlobal(Marshal.SizeOf(typeof(CaptureEventData)) * EventDataBlocks);
         this.StartCoroutine(this.RenderInterceptorAtFrameTail());
     }
+    void OnDestroy()
+    {
+        if (this.eventData != IntPtr.Zero) Marshal.FreeHGlobal(this.eventData);
+        this.eventData = IntPtr.Zero;
+    }
 
     IEnumerator RenderInterceptorAtFrameTail()
     {
@@ -25,8 +141,174 @@ public class NativeRenderInteceptor : MonoBehaviour
         {
             yield return new WaitForEndOfFrame();
 
-            set_render_buffer(Graphics.activeColorBuffer.GetNativeRenderBufferPtr());
-            GL.IssuePluginEvent(rendering_event_ptr(), 1);
+            this.Capture(Graphics.activeColorBuffer, AllSinks, new RectInt(), (ulong)Time.frameCount);
+        }
+    }
+
+    /// <summary>
+    /// Captures a render buffer (e.g. the target of another camera) for one output or all of them.
+    /// The region (in pixels from the top-left corner, zero-sized for the capture region set by <see cref="SetCaptureRegion"/>)
+    /// and the tag, which readback callbacks receive with the frame, apply to this capture only.
+    /// </summary>
+    public void Capture(RenderBuffer renderBuffer, uint sinkId, RectInt region, ulong frameTag)
+    {
+        this.IssueCaptureEvent(EventKind.Capture, renderBuffer, sinkId, region, frameTag);
+    }
+    /// <summary>
+    /// Captures a render buffer for the screenshot requested by <see cref="RequestScreenshot"/>, leaving the other outputs alone
+    /// </summary>
+    public void CaptureScreenshot(RenderBuffer renderBuffer)
+    {
+        this.IssueCaptureEvent(EventKind.Screenshot, renderBuffer, ScreenshotSinkId, new RectInt(), (ulong)Time.frameCount);
+    }
+    private void IssueCaptureEvent(EventKind kind, RenderBuffer renderBuffer, uint sinkId, RectInt region, ulong frameTag)
+    {
+        var eventId = EventId(kind);
+        if (this.eventData == IntPtr.Zero || eventId < 0) return;
+        var data = new CaptureEventData
+        {
+            renderBuffer = renderBuffer.GetNativeRenderBufferPtr(),
+            target = sinkId,
+            regionX = region.x, regionY = region.y, regionWidth = region.width, regionHeight = region.height,
+            frameTag = frameTag
+        };
+        var block = new IntPtr(this.eventData.ToInt64() + Marshal.SizeOf(typeof(CaptureEventData)) * this.nextEventData);
+        this.nextEventData = (this.nextEventData + 1) % EventDataBlocks;
+        Marshal.StructureToPtr(data, block, false);
+        GL.IssuePluginEventAndData(rendering_event_and_data_ptr(), eventId, block);
+    }
+
+    /// <summary>
+    /// Turns the readback on or off, in order with the captures issued on the render thread
+    /// </summary>
+    public static void SetRecording(bool recording)
+    {
+        IssueEvent(recording ? EventKind.StartRecording : EventKind.StopRecording);
+    }
+    /// <summary>
+    /// Delivers the frames still being copied to the readback callback, once the render thread reaches this point
+    /// </summary>
+    public static void Flush()
+    {
+        IssueEvent(EventKind.Flush);
+    }
+    private static void IssueEvent(EventKind kind)
+    {
+        var eventId = EventId(kind);
+        if (eventId >= 0) GL.IssuePluginEvent(rendering_event_ptr(), eventId);
+    }
+
+    /// <summary>
+    /// The event id Unity assigned to an event kind of the plugin
+    /// </summary>
+    /// <returns>-1 if the plugin is not loaded</returns>
+    public static int EventId(EventKind kind)
+    {
+        return plugin_event_id((int)kind);
+    }
+
+    /// <summary>
+    /// Writes the next intercepted frame to a PNG file. Completion is reported through <see cref="CurrentScreenshotStatus"/>.
+    /// </summary>
+    /// <returns>false if another screenshot is still in progress</returns>
+    public bool RequestScreenshot(string path)
+    {
+        return request_screenshot(Encoding.UTF8.GetBytes(path + "\0"));
+    }
+    public ScreenshotStatus CurrentScreenshotStatus
+    {
+        get { return (ScreenshotStatus)screenshot_status(); }
+    }
+
+    /// <summary>
+    /// Sends a texture (a secondary camera target, a UI canvas texture, ...) to an output instead of the screen,
+    /// on the next captured frame only. Call it every frame to keep capturing the texture. Depth textures are not supported.
+    /// </summary>
+    /// <returns>false if the plugin is not initialized or no output has the id</returns>
+    public bool CaptureTexture(Texture texture, uint sinkId)
+    {
+        return capture_texture(texture.GetNativeTexturePtr(), sinkId);
+    }
+
+    /// <summary>
+    /// Turns an output of the intercepted frames on or off
+    /// </summary>
+    /// <returns>false if the plugin is not initialized or no output has the id</returns>
+    public bool SetOutputEnabled(uint sinkId, bool enabled)
+    {
+        return set_sink_enabled(sinkId, enabled);
+    }
+
+    /// <summary>
+    /// Flips and/or rotates the frames of an output (<see cref="OutputTransform.None"/> for the original orientation)
+    /// </summary>
+    /// <returns>false if the plugin is not initialized or the output does not support transforms</returns>
+    public bool SetOutputTransform(uint sinkId, OutputTransform transform)
+    {
+        return set_sink_transform(sinkId, (int)transform);
+    }
+
+    /// <summary>
+    /// Number of mirror frames that may be queued on the GPU before the render thread waits (1 to 8, default 2).
+    /// 1 minimizes the latency of the mirror window at the cost of a GPU round-trip every frame.
+    /// </summary>
+    /// <returns>false if the count is out of range</returns>
+    public static bool SetFramesInFlight(uint count)
+    {
+        return set_frames_in_flight(count);
+    }
+
+    /// <summary>
+    /// How the frame is placed in the mirror window when the sizes differ (default <see cref="ScalingMode.Stretch"/>)
+    /// </summary>
+    public static bool SetScalingMode(ScalingMode mode)
+    {
+        return set_scaling_mode((int)mode);
+    }
+
+    /// <summary>
+    /// Captures only a part of the frame (in pixels from the top-left corner of the render buffer) for every output.
+    /// The region is clipped to the frame size.
+    /// </summary>
+    /// <returns>false if the offset is negative or the size is not positive</returns>
+    public static bool SetCaptureRegion(int x, int y, int width, int height)
+    {
+        if (width == 0 || height == 0) return false;
+        return set_capture_region(x, y, width, height);
+    }
+    /// <summary>
+    /// Captures the whole frame again
+    /// </summary>
+    public static void ResetCaptureRegion()
+    {
+        set_capture_region(0, 0, 0, 0);
+    }
+
+    /// <summary>
+    /// true if Unity renders offscreen (RENDERING_INTERCEPTOR_OFFSCREEN=1) and the plugin's window is the only one showing the frames.
+    /// The frames then have to be captured by this script every frame.
+    /// </summary>
+    public static bool RendersOffscreen
+    {
+        get { return unity_renders_offscreen(); }
+    }
+
+    /// <summary>
+    /// Description of the last failure inside the plugin, or null if nothing has failed
+    /// </summary>
+    public static string LastErrorMessage
+    {
+        get
+        {
+            var buffer = new byte[256];
+            var length = last_error_message(buffer, buffer.Length);
+            if (length == 0) return null;
+            if (length > buffer.Length)
+            {
+                buffer = new byte[length];
+                length = Math.Min(last_error_message(buffer, buffer.Length), buffer.Length);
+            }
+            return Encoding.UTF8.GetString(buffer, 0, length);
         }
     }
 }
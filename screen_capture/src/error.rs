@@ -0,0 +1,126 @@
+//! Error Model and FFI Guards
+
+use bedrock::vk::*;
+use libc::*;
+use lazy_static::*;
+use log::*;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InterceptorError
+{
+    /// vkGetInstanceProcAddr returned null (the extension or version is not enabled)
+    MissingEntryPoint(&'static str),
+    /// Unity's instance or device has been created without the extension
+    MissingExtension(&'static str),
+    /// A Vulkan command returned an error
+    Vulkan { call: &'static str, result: VkResult },
+    /// A Unity interface is not provided by the host
+    MissingUnityInterface(&'static str),
+    /// The platform window could not be created
+    Window(String),
+    /// The queue family cannot present to the window system
+    PresentationUnsupported,
+    /// Unity did not give access to the render buffer (not set, or already released)
+    RenderBufferUnavailable,
+    /// Unity did not give access to a texture passed to `capture_texture` (destroyed, or not a color texture)
+    TextureUnavailable,
+    /// The format of the captured image cannot be handled by the operation
+    UnsupportedFormat(VkFormat),
+    /// The parameters of a capture event issued with data are unusable
+    InvalidEventData(&'static str),
+    /// Mirror frames cannot be recorded without a slot
+    NoFramesInFlight,
+    /// A panic was caught at the FFI boundary
+    Panic(String)
+}
+impl InterceptorError
+{
+    pub fn vulkan(call: &'static str, result: VkResult) -> Self { InterceptorError::Vulkan { call, result } }
+}
+impl std::fmt::Display for InterceptorError
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+    {
+        match self
+        {
+            InterceptorError::MissingEntryPoint(n) => write!(f, "Vulkan entry point {} is not available", n),
+            InterceptorError::MissingExtension(n) => write!(f, "Vulkan extension {} is not enabled", n),
+            InterceptorError::Vulkan { call, result } => write!(f, "{} failed: {}", call, result),
+            InterceptorError::MissingUnityInterface(n) => write!(f, "Unity interface {} is not available", n),
+            InterceptorError::Window(m) => write!(f, "window creation failed: {}", m),
+            InterceptorError::PresentationUnsupported => write!(f, "the graphics queue cannot present to the window system"),
+            InterceptorError::RenderBufferUnavailable => write!(f, "unable to access the render buffer texture"),
+            InterceptorError::TextureUnavailable => write!(f, "unable to access the captured texture"),
+            InterceptorError::UnsupportedFormat(x) => write!(f, "unsupported image format {}", x),
+            InterceptorError::InvalidEventData(m) => write!(f, "invalid capture event data: {}", m),
+            InterceptorError::NoFramesInFlight => write!(f, "at least one frame has to be in flight"),
+            InterceptorError::Panic(m) => write!(f, "panicked: {}", m)
+        }
+    }
+}
+impl std::error::Error for InterceptorError {}
+
+pub type Result<T> = std::result::Result<T, InterceptorError>;
+
+/// Ok for VK_SUCCESS, otherwise the error of `call`
+pub fn vk_check(call: &'static str, result: VkResult) -> Result<()>
+{
+    if result == VK_SUCCESS { Ok(()) } else { Err(InterceptorError::vulkan(call, result)) }
+}
+
+lazy_static!{
+    static ref LAST_ERROR: Mutex<Option<String>> = Mutex::new(None);
+}
+/// Records the error for `last_error_message`. Logs it unless it repeats the previous one
+/// (errors such as a missing render buffer would otherwise be logged every frame).
+pub fn set_last_error(e: &InterceptorError)
+{
+    let message = e.to_string();
+    let mut last = LAST_ERROR.lock().unwrap_or_else(|e| e.into_inner());
+    if last.as_ref() != Some(&message) { error!("Interceptor: {}", message); }
+    *last = Some(message);
+}
+pub fn last_error() -> Option<String>
+{
+    LAST_ERROR.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+pub fn clear_last_error() { *LAST_ERROR.lock().unwrap_or_else(|e| e.into_inner()) = None; }
+
+/// Runs the body of an exported function. Errors and panics never reach the caller:
+/// they are recorded as the last error and `fallback` is returned instead.
+pub fn ffi_guard<T, F: FnOnce() -> Result<T>>(fallback: T, body: F) -> T
+{
+    match catch_unwind(AssertUnwindSafe(body))
+    {
+        Ok(Ok(v)) => v,
+        Ok(Err(e)) => { set_last_error(&e); fallback },
+        Err(p) =>
+        {
+            let message = p.downcast_ref::<&str>().map(|s| s.to_string())
+                .or_else(|| p.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_owned());
+            set_last_error(&InterceptorError::Panic(message));
+            fallback
+        }
+    }
+}
+
+/// Copies the message of the last error as UTF-8 into `buffer` (truncated to `capacity` bytes, not nul-terminated).
+/// Returns the full length of the message in bytes, or 0 if no error has occurred.
+#[no_mangle]
+pub extern "system" fn last_error_message(buffer: *mut u8, capacity: c_int) -> c_int
+{
+    ffi_guard(0, ||
+    {
+        let message = match last_error() { Some(m) => m, None => return Ok(0) };
+        if !buffer.is_null() && capacity > 0
+        {
+            let n = message.len().min(capacity as usize);
+            unsafe { std::ptr::copy_nonoverlapping(message.as_ptr(), buffer, n); }
+        }
+
+        Ok(message.len() as _)
+    })
+}
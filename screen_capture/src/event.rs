@@ -0,0 +1,103 @@
+//! Plugin Event Kinds and Per-event Capture Parameters (GL.IssuePluginEventAndData)
+
+use libc::*;
+use std::cell::Cell;
+use std::sync::atomic::{AtomicI32, Ordering};
+use crate::error::InterceptorError;
+use crate::scaling::Rect;
+use crate::sink::SinkId;
+use crate::unity::UnityRenderBuffer;
+
+/// Captures the render buffer for the sinks
+pub const EVENT_CAPTURE: c_int = 0;
+/// Captures the render buffer for the screenshot requested by `request_screenshot` only
+pub const EVENT_SCREENSHOT: c_int = 1;
+/// Enables the readback, from the frames captured after this event on
+pub const EVENT_START_RECORDING: c_int = 2;
+/// Disables the readback, from the frames captured after this event on
+pub const EVENT_STOP_RECORDING: c_int = 3;
+/// Waits for the frames being copied and delivers them
+pub const EVENT_FLUSH: c_int = 4;
+/// Number of event kinds, the size of the event id range reserved at load
+pub const EVENT_KIND_COUNT: c_int = 5;
+
+/// First id of the range reserved from Unity; negative while the plugin is not loaded
+static EVENT_ID_BASE: AtomicI32 = AtomicI32::new(-1);
+pub fn set_event_id_base(base: c_int) { EVENT_ID_BASE.store(base, Ordering::SeqCst); }
+/// The event id Unity knows `kind` by, None if the kind is unknown or the plugin is not loaded
+pub fn event_id(kind: c_int) -> Option<c_int>
+{
+    let base = EVENT_ID_BASE.load(Ordering::SeqCst);
+    if base < 0 || kind < 0 || kind >= EVENT_KIND_COUNT { None } else { Some(base + kind) }
+}
+/// The kind of an event id, None if it is not one of the plugin's
+pub fn event_kind(event_id: c_int) -> Option<c_int>
+{
+    let base = EVENT_ID_BASE.load(Ordering::SeqCst);
+    if base < 0 || event_id < base || event_id - base >= EVENT_KIND_COUNT { None } else { Some(event_id - base) }
+}
+
+/// `CaptureEventData::target` feeding every enabled sink
+pub const ALL_SINKS: SinkId = SinkId::MAX;
+
+/// Parameters of one capture event issued through `rendering_event_and_data_ptr`. The layout is shared with C#.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CaptureEventData
+{
+    /// The render buffer to capture (`RenderBuffer.GetNativeRenderBufferPtr()`)
+    pub render_buffer: UnityRenderBuffer,
+    /// The sink to feed, or `ALL_SINKS`
+    pub target: SinkId,
+    /// Part of the frame captured by this event; a zero-sized region uses the capture region of the settings
+    pub region_x: c_int,
+    pub region_y: c_int,
+    pub region_width: c_int,
+    pub region_height: c_int,
+    /// Passed through to the readback as `ReadbackFrame::tag`
+    pub frame_tag: u64
+}
+impl CaptureEventData
+{
+    /// The region replacing the capture region of the settings, None to keep it
+    pub fn region(&self) -> Result<Option<Rect>, InterceptorError>
+    {
+        if self.region_width == 0 && self.region_height == 0 { return Ok(None); }
+        if self.region_x < 0 || self.region_y < 0 || self.region_width <= 0 || self.region_height <= 0
+        {
+            return Err(InterceptorError::InvalidEventData("negative offset or empty region"));
+        }
+
+        Ok(Some(Rect::new(self.region_x, self.region_y, self.region_width as _, self.region_height as _)))
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Scope
+{
+    region: Option<Rect>,
+    tag: u64
+}
+thread_local!{
+    // events are handled on the render thread, which is the only one reading them
+    static CURRENT: Cell<Option<Scope>> = Cell::new(None);
+}
+/// Applies the region and the tag of an event to the outputs it feeds, until dropped
+pub struct EventScope(Option<Scope>);
+impl EventScope
+{
+    pub fn enter(data: &CaptureEventData) -> Result<Self, InterceptorError>
+    {
+        let scope = Scope { region: data.region()?, tag: data.frame_tag };
+        Ok(EventScope(CURRENT.with(|c| c.replace(Some(scope)))))
+    }
+}
+impl Drop for EventScope
+{
+    fn drop(&mut self) { CURRENT.with(|c| c.set(self.0)); }
+}
+
+/// The region of the event being handled on this thread, if it has one
+pub fn region_override() -> Option<Rect> { CURRENT.with(|c| c.get()).and_then(|s| s.region) }
+/// The tag of the event being handled on this thread; 0 outside of events
+pub fn current_tag() -> u64 { CURRENT.with(|c| c.get()).map_or(0, |s| s.tag) }
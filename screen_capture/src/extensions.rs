@@ -0,0 +1,301 @@
+//! Extensions of Unity's Instance and Device
+//!
+//! Unity creates its VkInstance and VkDevice with the extensions it needs itself only. Their creation is wrapped
+//! through `InterceptInitialization` to append the extensions the interceptor makes use of, as far as the driver has them.
+#![allow(non_camel_case_types, non_upper_case_globals, non_snake_case)]
+
+use bedrock::vk::*;
+use lazy_static::*;
+use libc::*;
+use log::*;
+use std::ffi::{CStr, CString};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use crate::unity::*;
+
+pub const VK_STRUCTURE_TYPE_PHYSICAL_DEVICE_FEATURES_2_KHR: VkStructureType = 1000059000;
+pub const VK_STRUCTURE_TYPE_PHYSICAL_DEVICE_TIMELINE_SEMAPHORE_FEATURES_KHR: VkStructureType = 1000207000;
+const VK_STRUCTURE_TYPE_PHYSICAL_DEVICE_VULKAN_1_2_FEATURES: VkStructureType = 51;
+
+#[repr(C)]
+pub struct VkPhysicalDeviceFeatures2KHR
+{
+    pub sType: VkStructureType,
+    pub pNext: *mut c_void,
+    pub features: VkPhysicalDeviceFeatures
+}
+#[repr(C)]
+pub struct VkPhysicalDeviceTimelineSemaphoreFeaturesKHR
+{
+    pub sType: VkStructureType,
+    pub pNext: *mut c_void,
+    pub timelineSemaphore: VkBool32
+}
+pub type PFN_vkGetPhysicalDeviceFeatures2KHR = extern "system" fn(physicalDevice: VkPhysicalDevice, pFeatures: *mut VkPhysicalDeviceFeatures2KHR);
+/// The head every structure of a pNext chain starts with
+#[repr(C)]
+struct ChainedStructure
+{
+    sType: VkStructureType,
+    pNext: *const c_void
+}
+
+/// Instance extensions appended if the driver has them
+pub const INSTANCE_EXTENSIONS: &[&[u8]] = &[
+    b"VK_KHR_surface\0",
+    #[cfg(windows)] b"VK_KHR_win32_surface\0",
+    #[cfg(target_os = "linux")] b"VK_KHR_xcb_surface\0",
+    b"VK_EXT_headless_surface\0",
+    b"VK_EXT_debug_utils\0",
+    b"VK_KHR_get_physical_device_properties2\0",
+    b"VK_KHR_external_memory_capabilities\0"
+];
+/// Device extensions appended if the physical device has them
+pub const DEVICE_EXTENSIONS: &[&[u8]] = &[
+    b"VK_KHR_swapchain\0",
+    b"VK_KHR_external_memory\0",
+    #[cfg(windows)] b"VK_KHR_external_memory_win32\0",
+    #[cfg(unix)] b"VK_KHR_external_memory_fd\0",
+    b"VK_KHR_timeline_semaphore\0"
+];
+
+/// What Unity's instance and device have actually been created with, including what Unity enabled itself
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EnabledExtensions
+{
+    pub instance: Vec<String>,
+    /// empty until the device is created
+    pub device: Vec<String>,
+    /// whether the timelineSemaphore feature is enabled on the device
+    pub timeline_semaphore: bool
+}
+impl EnabledExtensions
+{
+    pub fn has_instance_extension(&self, name: &str) -> bool { self.instance.iter().any(|n| n == name) }
+    pub fn has_device_extension(&self, name: &str) -> bool { self.device.iter().any(|n| n == name) }
+}
+
+struct Interception
+{
+    /// the vkGetInstanceProcAddr Unity would have used
+    get_instance_proc_addr: PFN_vkGetInstanceProcAddr,
+    /// Unity's instance, once created
+    instance: VkInstance,
+    enabled: Option<EnabledExtensions>
+}
+unsafe impl Sync for Interception {}
+unsafe impl Send for Interception {}
+
+lazy_static!{
+    static ref STATE: RwLock<Option<Interception>> = RwLock::new(None);
+}
+fn state() -> RwLockReadGuard<'static, Option<Interception>> { STATE.read().unwrap_or_else(|e| e.into_inner()) }
+fn state_mut() -> RwLockWriteGuard<'static, Option<Interception>> { STATE.write().unwrap_or_else(|e| e.into_inner()) }
+
+/// Wraps the creation of Unity's instance and device. Has to be called before Unity initializes Vulkan,
+/// i.e. from `UnityPluginLoad`; anything recorded for an earlier initialization is forgotten.
+/// Returns false if Unity does not let the initialization be intercepted.
+pub fn install(vulkan: UnityGraphicsVulkanRef) -> bool
+{
+    *state_mut() = None;
+    let accepted = vulkan.intercept_initialization(|get_instance_proc_addr: PFN_vkGetInstanceProcAddr| -> PFN_vkGetInstanceProcAddr
+    {
+        *state_mut() = Some(Interception { get_instance_proc_addr, instance: std::ptr::null_mut(), enabled: None });
+        hook_get_instance_proc_addr
+    });
+    if !accepted { warn!("Interceptor: Vulkan initialization cannot be intercepted; only the extensions Unity enables are used"); }
+    accepted
+}
+/// The extensions Unity's instance and device have been created with.
+/// None if their creation was not intercepted, in which case nothing is known about them.
+pub fn enabled() -> Option<EnabledExtensions>
+{
+    state().as_ref().and_then(|s| s.enabled.clone())
+}
+
+fn entry_name(name: &'static [u8]) -> &'static CStr { CStr::from_bytes_with_nul(name).unwrap() }
+unsafe fn requested_extensions<'a>(names: *const *const c_char, count: u32) -> Vec<&'a CStr>
+{
+    if count == 0 { return Vec::new(); }
+    std::slice::from_raw_parts(names, count as _).iter().map(|&n| CStr::from_ptr(n)).collect()
+}
+/// Names of the extensions an enumeration function reports
+fn available_extensions<F>(mut enumerate: F) -> Vec<CString> where F: FnMut(*mut u32, *mut VkExtensionProperties) -> VkResult
+{
+    let mut count = 0;
+    if enumerate(&mut count, std::ptr::null_mut()) != VK_SUCCESS { return Vec::new(); }
+    let mut props = Vec::with_capacity(count as _);
+    unsafe { props.set_len(count as _); }
+    // VK_INCOMPLETE still returns existing ones
+    match enumerate(&mut count, props.as_mut_ptr()) { VK_SUCCESS | VK_INCOMPLETE => (), _ => return Vec::new() }
+    props.truncate(count as _);
+    props.iter().map(|p| unsafe { CStr::from_ptr(p.extensionName.as_ptr()) }.to_owned()).collect()
+}
+/// The wanted extensions that are available and not requested yet
+fn additions(wanted: &'static [&'static [u8]], requested: &[&CStr], available: &[CString]) -> Vec<&'static CStr>
+{
+    wanted.iter().map(|&n| entry_name(n))
+        .filter(|n| !requested.contains(n) && available.iter().any(|a| a.as_c_str() == *n))
+        .collect()
+}
+/// Calls `create` with the requested and the appended extensions, or with the requested ones only if the driver
+/// rejects the additions (`create` also receives whether to add anything else).
+/// Returns the result, the extensions the object has been created with and whether the additions were accepted.
+fn create_extended<'a, F>(requested: &[&'a CStr], appended: &[&'a CStr], other_additions: bool, mut create: F) -> (VkResult, Vec<String>, bool)
+    where F: FnMut(&[*const c_char], bool) -> VkResult
+{
+    let mut names: Vec<_> = requested.iter().chain(appended).map(|n| n.as_ptr()).collect();
+    let mut r = create(&names, true);
+    let mut extended = true;
+    if r != VK_SUCCESS && (!appended.is_empty() || other_additions)
+    {
+        warn!("Interceptor: creation with additional extensions failed ({}), retrying without them", r);
+        names.truncate(requested.len());
+        r = create(&names, false);
+        extended = false;
+    }
+    let enabled = requested.iter().chain(if extended { appended } else { &[] })
+        .map(|n| n.to_string_lossy().into_owned()).collect();
+    (r, enabled, extended)
+}
+
+/// Whether the physical device supports timeline semaphores (needs VK_KHR_get_physical_device_properties2)
+fn timeline_semaphore_supported(get_instance_proc_addr: PFN_vkGetInstanceProcAddr, instance: VkInstance, physical_device: VkPhysicalDevice) -> bool
+{
+    let fp_get_features: PFN_vkGetPhysicalDeviceFeatures2KHR = match get_instance_proc_addr(instance, b"vkGetPhysicalDeviceFeatures2KHR\0".as_ptr() as _)
+    {
+        Some(f) => unsafe { std::mem::transmute(f) },
+        None => return false
+    };
+    let mut timeline = VkPhysicalDeviceTimelineSemaphoreFeaturesKHR
+    {
+        sType: VK_STRUCTURE_TYPE_PHYSICAL_DEVICE_TIMELINE_SEMAPHORE_FEATURES_KHR, pNext: std::ptr::null_mut(), timelineSemaphore: 0
+    };
+    let mut features = VkPhysicalDeviceFeatures2KHR
+    {
+        sType: VK_STRUCTURE_TYPE_PHYSICAL_DEVICE_FEATURES_2_KHR, pNext: &mut timeline as *mut _ as _, features: unsafe { std::mem::zeroed() }
+    };
+    fp_get_features(physical_device, &mut features);
+    timeline.timelineSemaphore != 0
+}
+/// Whether a structure Unity chained itself enables timeline semaphores; None if there is no such structure
+unsafe fn chained_timeline_semaphore(mut next: *const c_void) -> Option<bool>
+{
+    while let Some(s) = (next as *const ChainedStructure).as_ref()
+    {
+        match s.sType
+        {
+            VK_STRUCTURE_TYPE_PHYSICAL_DEVICE_TIMELINE_SEMAPHORE_FEATURES_KHR =>
+                return Some((*(next as *const VkPhysicalDeviceTimelineSemaphoreFeaturesKHR)).timelineSemaphore != 0),
+            // the feature cannot be chained again next to it; not looked into
+            VK_STRUCTURE_TYPE_PHYSICAL_DEVICE_VULKAN_1_2_FEATURES => return Some(false),
+            _ => next = s.pNext
+        }
+    }
+    None
+}
+
+extern "system" fn hook_get_instance_proc_addr(instance: VkInstance, name: *const c_char) -> Option<PFN_vkVoidFunction>
+{
+    let original = state().as_ref()?.get_instance_proc_addr;
+    let f = original(instance, name)?;
+    unsafe
+    {
+        Some(match CStr::from_ptr(name).to_bytes()
+        {
+            b"vkGetInstanceProcAddr" => std::mem::transmute::<PFN_vkGetInstanceProcAddr, _>(hook_get_instance_proc_addr),
+            b"vkCreateInstance" => std::mem::transmute::<PFN_vkCreateInstance, _>(hook_create_instance),
+            b"vkCreateDevice" => std::mem::transmute::<PFN_vkCreateDevice, _>(hook_create_device),
+            _ => f
+        })
+    }
+}
+extern "system" fn hook_create_instance(create_info: *const VkInstanceCreateInfo, allocator: *const VkAllocationCallbacks,
+    instance: *mut VkInstance) -> VkResult
+{
+    let get_instance_proc_addr = match *state()
+    {
+        Some(ref s) => s.get_instance_proc_addr,
+        None => return VK_ERROR_INITIALIZATION_FAILED
+    };
+    let original: PFN_vkCreateInstance = match get_instance_proc_addr(std::ptr::null_mut(), b"vkCreateInstance\0".as_ptr() as _)
+    {
+        Some(f) => unsafe { std::mem::transmute(f) },
+        None => return VK_ERROR_INITIALIZATION_FAILED
+    };
+    let fp_enumerate: Option<PFN_vkEnumerateInstanceExtensionProperties> =
+        get_instance_proc_addr(std::ptr::null_mut(), b"vkEnumerateInstanceExtensionProperties\0".as_ptr() as _).map(|f| unsafe { std::mem::transmute(f) });
+
+    let info = unsafe { &*create_info };
+    let requested = unsafe { requested_extensions(info.ppEnabledExtensionNames, info.enabledExtensionCount) };
+    let available = fp_enumerate.map_or_else(Vec::new, |f| available_extensions(|count, props| f(std::ptr::null(), count, props)));
+    let appended = additions(INSTANCE_EXTENSIONS, &requested, &available);
+
+    let (r, enabled, _) = create_extended(&requested, &appended, false, |names, _| original(&VkInstanceCreateInfo
+    {
+        enabledExtensionCount: names.len() as _,
+        ppEnabledExtensionNames: names.as_ptr(),
+        .. unsafe { std::ptr::read(info) }
+    }, allocator, instance));
+    if r == VK_SUCCESS
+    {
+        info!("Interceptor: instance extensions: {}", enabled.join(", "));
+        if let Some(ref mut s) = *state_mut()
+        {
+            s.instance = unsafe { *instance };
+            s.enabled = Some(EnabledExtensions { instance: enabled, .. Default::default() });
+        }
+    }
+    r
+}
+extern "system" fn hook_create_device(physical_device: VkPhysicalDevice, create_info: *const VkDeviceCreateInfo,
+    allocator: *const VkAllocationCallbacks, device: *mut VkDevice) -> VkResult
+{
+    let (get_instance_proc_addr, instance, features2) = match *state()
+    {
+        Some(ref s) => (s.get_instance_proc_addr, s.instance,
+            s.enabled.as_ref().map_or(false, |e| e.has_instance_extension("VK_KHR_get_physical_device_properties2"))),
+        None => return VK_ERROR_INITIALIZATION_FAILED
+    };
+    let original: PFN_vkCreateDevice = match get_instance_proc_addr(instance, b"vkCreateDevice\0".as_ptr() as _)
+    {
+        Some(f) => unsafe { std::mem::transmute(f) },
+        None => return VK_ERROR_INITIALIZATION_FAILED
+    };
+    let fp_enumerate: Option<PFN_vkEnumerateDeviceExtensionProperties> =
+        get_instance_proc_addr(instance, b"vkEnumerateDeviceExtensionProperties\0".as_ptr() as _).map(|f| unsafe { std::mem::transmute(f) });
+
+    let info = unsafe { &*create_info };
+    let requested = unsafe { requested_extensions(info.ppEnabledExtensionNames, info.enabledExtensionCount) };
+    let available = fp_enumerate.map_or_else(Vec::new, |f| available_extensions(|count, props| f(physical_device, std::ptr::null(), count, props)));
+    let appended = additions(DEVICE_EXTENSIONS, &requested, &available);
+
+    // the feature is enabled along with the extension, unless Unity decided on it already
+    let chained = unsafe { chained_timeline_semaphore(info.pNext) };
+    let add_timeline_semaphore = chained.is_none() && features2
+        && requested.iter().chain(&appended).any(|n| n.to_bytes() == b"VK_KHR_timeline_semaphore")
+        && timeline_semaphore_supported(get_instance_proc_addr, instance, physical_device);
+    let timeline = VkPhysicalDeviceTimelineSemaphoreFeaturesKHR
+    {
+        sType: VK_STRUCTURE_TYPE_PHYSICAL_DEVICE_TIMELINE_SEMAPHORE_FEATURES_KHR, pNext: info.pNext as _, timelineSemaphore: 1
+    };
+
+    let (r, enabled, extended) = create_extended(&requested, &appended, add_timeline_semaphore, |names, extended| original(physical_device, &VkDeviceCreateInfo
+    {
+        pNext: if extended && add_timeline_semaphore { &timeline as *const _ as _ } else { info.pNext },
+        enabledExtensionCount: names.len() as _,
+        ppEnabledExtensionNames: names.as_ptr(),
+        .. unsafe { std::ptr::read(info) }
+    }, allocator, device));
+    if r == VK_SUCCESS
+    {
+        let timeline_semaphore = chained.unwrap_or(extended && add_timeline_semaphore);
+        info!("Interceptor: device extensions: {} (timeline semaphores: {})", enabled.join(", "), timeline_semaphore);
+        if let Some(ref mut s) = *state_mut()
+        {
+            let e = s.enabled.get_or_insert_with(Default::default);
+            e.device = enabled;
+            e.timeline_semaphore = timeline_semaphore;
+        }
+    }
+    r
+}
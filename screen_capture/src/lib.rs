@@ -3,215 +3,352 @@ use libc::*;
 use lazy_static::*;
 use log::*;
 use bedrock::vk::*;
-use std::cell::Cell;
-use std::sync::RwLock;
-use std::ptr::null_mut;
-use winapi::um::winuser::*;
-use winapi::um::libloaderapi::GetModuleHandleA;
-use winapi::shared::windef::{RECT, HWND};
-use winapi::shared::minwindef::{LRESULT, WPARAM, LPARAM, UINT};
-
-mod unity;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockWriteGuard};
+
+/// Loads a Vulkan entry point through vkGetInstanceProcAddr of the UnityVulkanInstance.
+/// Evaluates to `Err(InterceptorError::MissingEntryPoint)` if the driver does not provide it.
+macro_rules! load_instance_proc
+{
+    ($instance: expr, $name: literal) =>
+    {
+        match ($instance.get_instance_proc_addr)($instance.instance, concat!($name, "\0").as_ptr() as *const _)
+        {
+            Some(f) => Ok(unsafe { std::mem::transmute(f) }),
+            None => Err(crate::error::InterceptorError::MissingEntryPoint($name))
+        }
+    }
+}
+
+pub mod error;
+use error::{InterceptorError, vk_check, ffi_guard};
+pub mod unity;
 use unity::*;
+pub mod window;
+use window::WindowBackend;
+pub mod sink;
+use sink::{FrameSink, SinkRegistry, SinkId, SinkResult};
+pub mod readback;
+use readback::{ReadbackSink, ReadbackCallback};
+pub mod screenshot;
+use screenshot::ScreenshotSink;
+pub mod settings;
+pub mod scaling;
+use scaling::Rect;
+pub mod transform;
+use transform::{Transform, Transposer};
+pub mod resolve;
+use resolve::MsaaResolver;
+pub mod present;
+use present::PresentCapture;
+pub mod extensions;
+pub mod live_objects;
+pub mod event;
+use event::{CaptureEventData, EventScope};
+
+/// Synchronization objects and command buffer of one mirror frame in flight
+struct FrameSlot
+{
+    image_ready_order: VkSemaphore,
+    present_order: VkSemaphore,
+    last_render: VkFence,
+    has_last_render_issued: bool,
+    cmd_pool: VkCommandPool,
+    cbuf: VkCommandBuffer
+}
 
+/// Cycles through `frames_in_flight` slots so that recording a frame only waits for the submission
+/// made that many frames earlier, not for the previous one.
 pub struct RenderControl
 {
+    device: VkDevice,
+    queue_family_index: u32,
+    frames: Vec<FrameSlot>,
+    current: usize,
+    fp_create_semaphore: PFN_vkCreateSemaphore,
+    fp_destroy_semaphore: PFN_vkDestroySemaphore,
+    fp_create_fence: PFN_vkCreateFence,
+    fp_destroy_fence: PFN_vkDestroyFence,
+    fp_create_command_pool: PFN_vkCreateCommandPool,
+    fp_destroy_command_pool: PFN_vkDestroyCommandPool,
+    fp_alloc_command_buffer: PFN_vkAllocateCommandBuffers,
+    fp_reset_command_pool: PFN_vkResetCommandPool,
     fp_acquire_next_image: PFN_vkAcquireNextImageKHR,
     fp_submit_commands: PFN_vkQueueSubmit,
     fp_present: PFN_vkQueuePresentKHR,
     fp_wait_fences: PFN_vkWaitForFences,
     fp_reset_fences: PFN_vkResetFences,
-    image_ready_order: VkSemaphore,
-    present_order: VkSemaphore,
-    last_render: VkFence,
-    has_last_render_issued: bool
+    fp_device_wait_idle: PFN_vkDeviceWaitIdle
 }
 impl RenderControl
 {
-    pub fn new(instance: &UnityVulkanInstance) -> Self
+    pub fn new(instance: &UnityVulkanInstance, frames_in_flight: u32) -> Result<Self, InterceptorError>
     {
-        let fp_create_semaphore: PFN_vkCreateSemaphore = unsafe
-        {
-            std::mem::transmute((instance.get_instance_proc_addr)(instance.instance, b"vkCreateSemaphore\0".as_ptr() as *const _).unwrap())
-        };
-        let fp_create_fence: PFN_vkCreateFence = unsafe
+        let mut this = RenderControl
         {
-            std::mem::transmute((instance.get_instance_proc_addr)(instance.instance, b"vkCreateFence\0".as_ptr() as *const _).unwrap())
+            device: instance.device,
+            queue_family_index: instance.queue_family_index,
+            frames: Vec::new(), current: 0,
+            fp_create_semaphore: load_instance_proc!(instance, "vkCreateSemaphore")?,
+            fp_destroy_semaphore: load_instance_proc!(instance, "vkDestroySemaphore")?,
+            fp_create_fence: load_instance_proc!(instance, "vkCreateFence")?,
+            fp_destroy_fence: load_instance_proc!(instance, "vkDestroyFence")?,
+            fp_create_command_pool: load_instance_proc!(instance, "vkCreateCommandPool")?,
+            fp_destroy_command_pool: load_instance_proc!(instance, "vkDestroyCommandPool")?,
+            fp_alloc_command_buffer: load_instance_proc!(instance, "vkAllocateCommandBuffers")?,
+            fp_reset_command_pool: load_instance_proc!(instance, "vkResetCommandPool")?,
+            fp_acquire_next_image: load_instance_proc!(instance, "vkAcquireNextImageKHR")?,
+            fp_submit_commands: load_instance_proc!(instance, "vkQueueSubmit")?,
+            fp_present: match present::original_queue_present() { Some(f) => f, None => load_instance_proc!(instance, "vkQueuePresentKHR")? },
+            fp_wait_fences: load_instance_proc!(instance, "vkWaitForFences")?,
+            fp_reset_fences: load_instance_proc!(instance, "vkResetFences")?,
+            fp_device_wait_idle: load_instance_proc!(instance, "vkDeviceWaitIdle")?
         };
+        if frames_in_flight == 0 { return Err(InterceptorError::NoFramesInFlight); }
+        this.frames = this.create_slots(frames_in_flight)?;
 
-        let mut image_ready_order = std::mem::MaybeUninit::uninit();
-        let mut present_order = std::mem::MaybeUninit::uninit();
-        let mut last_render = std::mem::MaybeUninit::uninit();
-        fp_create_semaphore(instance.device, &Default::default(), std::ptr::null(), image_ready_order.as_mut_ptr());
-        fp_create_semaphore(instance.device, &Default::default(), std::ptr::null(), present_order.as_mut_ptr());
-        fp_create_fence(instance.device, &Default::default(), std::ptr::null(), last_render.as_mut_ptr());
-        
-        RenderControl
+        Ok(this)
+    }
+    /// Creates `count` slots, or none at all
+    fn create_slots(&self, count: u32) -> Result<Vec<FrameSlot>, InterceptorError>
+    {
+        let mut frames = Vec::with_capacity(count as _);
+        for _ in 0 .. count
         {
-            fp_acquire_next_image: unsafe
-            {
-                std::mem::transmute((instance.get_instance_proc_addr)(instance.instance, b"vkAcquireNextImageKHR\0".as_ptr() as *const _).unwrap())
-            },
-            fp_submit_commands: unsafe
+            match self.create_slot()
             {
-                std::mem::transmute((instance.get_instance_proc_addr)(instance.instance, b"vkQueueSubmit\0".as_ptr() as *const _).unwrap())
-            },
-            fp_present: unsafe
-            {
-                std::mem::transmute((instance.get_instance_proc_addr)(instance.instance, b"vkQueuePresentKHR\0".as_ptr() as *const _).unwrap())
-            },
-            fp_wait_fences: unsafe
-            {
-                std::mem::transmute((instance.get_instance_proc_addr)(instance.instance, b"vkWaitForFences\0".as_ptr() as *const _).unwrap())
-            },
-            fp_reset_fences: unsafe
-            {
-                std::mem::transmute((instance.get_instance_proc_addr)(instance.instance, b"vkResetFences\0".as_ptr() as *const _).unwrap())
-            },
-            image_ready_order: unsafe { image_ready_order.assume_init() },
-            present_order: unsafe { present_order.assume_init() },
-            last_render: unsafe { last_render.assume_init() },
-            has_last_render_issued: false
+                Ok(slot) => frames.push(slot),
+                Err(e) =>
+                {
+                    for f in frames { self.destroy_slot(f); }
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(frames)
+    }
+    fn create_slot(&self) -> Result<FrameSlot, InterceptorError>
+    {
+        let mut slot = FrameSlot
+        {
+            image_ready_order: std::ptr::null_mut(), present_order: std::ptr::null_mut(), last_render: std::ptr::null_mut(),
+            has_last_render_issued: false,
+            cmd_pool: std::ptr::null_mut(), cbuf: std::ptr::null_mut()
+        };
+        // nothing created before a failure is left behind
+        match self.init_slot(&mut slot)
+        {
+            Ok(()) => Ok(slot),
+            Err(e) => { self.destroy_slot(slot); Err(e) }
         }
     }
+    fn init_slot(&self, f: &mut FrameSlot) -> Result<(), InterceptorError>
+    {
+        vk_check("vkCreateSemaphore", (self.fp_create_semaphore)(self.device, &Default::default(), std::ptr::null(), &mut f.image_ready_order))?;
+        live_objects::created("semaphore");
+        vk_check("vkCreateSemaphore", (self.fp_create_semaphore)(self.device, &Default::default(), std::ptr::null(), &mut f.present_order))?;
+        live_objects::created("semaphore");
+        vk_check("vkCreateFence", (self.fp_create_fence)(self.device, &Default::default(), std::ptr::null(), &mut f.last_render))?;
+        live_objects::created("fence");
 
-    pub fn acquire_next_frame(&mut self, sw: VkSwapchainKHR, dev: VkDevice) -> u32
+        let cpinfo = VkCommandPoolCreateInfo
+        {
+            queueFamilyIndex: self.queue_family_index,
+            .. Default::default()
+        };
+        vk_check("vkCreateCommandPool", (self.fp_create_command_pool)(self.device, &cpinfo, std::ptr::null(), &mut f.cmd_pool))?;
+        live_objects::created("command_pool");
+        let ainfo = VkCommandBufferAllocateInfo
+        {
+            commandPool: f.cmd_pool,
+            commandBufferCount: 1,
+            level: VK_COMMAND_BUFFER_LEVEL_PRIMARY,
+            .. Default::default()
+        };
+        vk_check("vkAllocateCommandBuffers", (self.fp_alloc_command_buffer)(self.device, &ainfo, &mut f.cbuf))
+    }
+    /// Destroys the objects of a slot, skipping the ones not created
+    fn destroy_slot(&self, f: FrameSlot)
     {
-        let mut next = 0;
-        (self.fp_acquire_next_image)(dev, sw, std::u64::MAX, self.image_ready_order, std::ptr::null_mut(), &mut next);
+        if !f.cmd_pool.is_null() { (self.fp_destroy_command_pool)(self.device, f.cmd_pool, std::ptr::null()); live_objects::destroyed("command_pool"); }
+        if !f.last_render.is_null() { (self.fp_destroy_fence)(self.device, f.last_render, std::ptr::null()); live_objects::destroyed("fence"); }
+        if !f.present_order.is_null() { (self.fp_destroy_semaphore)(self.device, f.present_order, std::ptr::null()); live_objects::destroyed("semaphore"); }
+        if !f.image_ready_order.is_null() { (self.fp_destroy_semaphore)(self.device, f.image_ready_order, std::ptr::null()); live_objects::destroyed("semaphore"); }
+    }
+    fn destroy_slots(&mut self)
+    {
+        for f in std::mem::replace(&mut self.frames, Vec::new()) { self.destroy_slot(f); }
+        self.current = 0;
+    }
 
-        next
+    pub fn frames_in_flight(&self) -> u32 { self.frames.len() as _ }
+    /// Waits for the device to finish every frame in flight and recreates `count` slots.
+    /// The current slots are kept if the new ones cannot be created.
+    pub fn set_frames_in_flight(&mut self, count: u32) -> Result<(), InterceptorError>
+    {
+        if count == 0 { return Err(InterceptorError::NoFramesInFlight); }
+        info!("Interceptor: frames in flight {} -> {}", self.frames.len(), count);
+        let frames = self.create_slots(count)?;
+        (self.fp_device_wait_idle)(self.device);
+        self.destroy_slots();
+        self.frames = frames;
+
+        Ok(())
     }
-    pub fn submit_command(&mut self, command: VkCommandBuffer, sw: VkSwapchainKHR, bb_index: u32, dev: VkDevice, gq: VkQueue)
+
+    /// Waits until the current slot is no longer used by the GPU and returns its command buffer, ready for recording
+    pub fn begin_frame(&mut self) -> VkCommandBuffer
     {
-        if self.has_last_render_issued
+        let f = &mut self.frames[self.current];
+        if f.has_last_render_issued
         {
-            (self.fp_wait_fences)(dev, 1, &self.last_render, true as _, std::u64::MAX);
-            (self.fp_reset_fences)(dev, 1, &self.last_render);
-            self.has_last_render_issued = false;
+            (self.fp_wait_fences)(self.device, 1, &f.last_render, true as _, std::u64::MAX);
+            (self.fp_reset_fences)(self.device, 1, &f.last_render);
+            f.has_last_render_issued = false;
         }
+        (self.fp_reset_command_pool)(self.device, f.cmd_pool, VK_COMMAND_POOL_RESET_RELEASE_RESOURCES_BIT);
 
+        f.cbuf
+    }
+    /// Returns the acquired image index and whether the swapchain is suboptimal.
+    /// An image is still acquired (and the semaphore signaled) when suboptimal.
+    pub fn acquire_next_frame(&mut self, sw: VkSwapchainKHR) -> Result<(u32, bool), VkResult>
+    {
+        let mut next = 0;
+        match (self.fp_acquire_next_image)(self.device, sw, std::u64::MAX, self.frames[self.current].image_ready_order, std::ptr::null_mut(), &mut next)
+        {
+            VK_SUCCESS => Ok((next, false)),
+            VK_SUBOPTIMAL_KHR => Ok((next, true)),
+            r => Err(r)
+        }
+    }
+    /// Submits the command buffer of the current slot and moves on to the next slot.
+    /// Returns the result of vkQueuePresentKHR, which may ask for a swapchain rebuild
+    pub fn submit_command(&mut self, sw: VkSwapchainKHR, bb_index: u32, gq: VkQueue) -> Result<VkResult, InterceptorError>
+    {
+        let f = &mut self.frames[self.current];
         let subinfo = VkSubmitInfo
         {
             waitSemaphoreCount: 1,
-            pWaitSemaphores: &self.image_ready_order,
+            pWaitSemaphores: &f.image_ready_order,
             pWaitDstStageMask: &VK_PIPELINE_STAGE_TRANSFER_BIT,
             commandBufferCount: 1,
-            pCommandBuffers: &command,
+            pCommandBuffers: &f.cbuf,
             signalSemaphoreCount: 1,
-            pSignalSemaphores: &self.present_order,
+            pSignalSemaphores: &f.present_order,
             .. Default::default()
         };
-        (self.fp_submit_commands)(gq, 1, &subinfo, self.last_render);
-        self.has_last_render_issued = true;
+        vk_check("vkQueueSubmit", (self.fp_submit_commands)(gq, 1, &subinfo, f.last_render))?;
+        f.has_last_render_issued = true;
 
         let pinfo = VkPresentInfoKHR
         {
             waitSemaphoreCount: 1,
-            pWaitSemaphores: &self.present_order,
+            pWaitSemaphores: &f.present_order,
             swapchainCount: 1,
             pSwapchains: &sw,
             pImageIndices: &bb_index,
             .. Default::default()
         };
-        (self.fp_present)(gq, &pinfo);
+        let r = (self.fp_present)(gq, &pinfo);
+        self.current = (self.current + 1) % self.frames.len();
+
+        Ok(r)
+    }
+}
+impl Drop for RenderControl
+{
+    fn drop(&mut self)
+    {
+        (self.fp_device_wait_idle)(self.device);
+        self.destroy_slots();
     }
 }
 
 pub struct ExtRenderTarget
 {
-    handle: HWND,
+    window: Box<dyn WindowBackend>,
     instance: VkInstance,
     device: VkDevice,
     graphics_queue: VkQueue,
-    get_instance_proc_addr: PFN_vkGetInstanceProcAddr,
+    physical_device: VkPhysicalDevice,
     surface: VkSurfaceKHR,
+    surface_format: VkSurfaceFormatKHR,
+    present_mode: VkPresentModeKHR,
+    composite_alpha: VkCompositeAlphaFlagBitsKHR,
+    buffer_count: u32,
     swapchain: VkSwapchainKHR,
     extent: VkExtent2D,
     bb_images: Vec<VkImage>,
-    rc: RenderControl
+    /// false until the image has been presented once (its contents are still undefined)
+    bb_presented: Vec<bool>,
+    /// set when the swapchain no longer matches the surface
+    needs_rebuild: bool,
+    rc: RenderControl,
+    transform: Transform,
+    transposer: Transposer,
+    fp_begin_command_record: PFN_vkBeginCommandBuffer,
+    fp_end_command_record: PFN_vkEndCommandBuffer,
+    fp_cmd_blit_image: PFN_vkCmdBlitImage,
+    fp_cmd_clear_color_image: PFN_vkCmdClearColorImage,
+    fp_cmd_pipeline_barrier: PFN_vkCmdPipelineBarrier,
+    fp_get_surface_capabilities: PFN_vkGetPhysicalDeviceSurfaceCapabilitiesKHR,
+    fp_create_swapchain: PFN_vkCreateSwapchainKHR,
+    fp_destroy_swapchain: PFN_vkDestroySwapchainKHR,
+    fp_destroy_surface: PFN_vkDestroySurfaceKHR,
+    fp_get_swapchain_images: PFN_vkGetSwapchainImagesKHR,
+    fp_device_wait_idle: PFN_vkDeviceWaitIdle
 }
 impl ExtRenderTarget
 {
-    pub fn new(instance: &UnityVulkanInstance) -> Self
+    pub fn new(instance: &UnityVulkanInstance, window: Box<dyn WindowBackend>) -> Result<Self, InterceptorError>
     {
         trace!("Interceptor: ExtRenderTarget::new");
 
-        let c = WNDCLASSEXA
-        {
-            cbSize: std::mem::size_of::<WNDCLASSEXA>() as _,
-            style: CS_OWNDC,
-            lpfnWndProc: Some(Self::wev_callback),
-            cbClsExtra: 0, cbWndExtra: 0,
-            hInstance: unsafe { GetModuleHandleA(null_mut()) },
-            hCursor: unsafe { LoadCursorA(null_mut(), b"IDC_ARROW\0".as_ptr() as _) },
-            lpszClassName: b"com.cterm2.unity.render_interceptor.MainWindow\0".as_ptr() as _,
-            .. unsafe { std::mem::zeroed() }
-        };
-        if unsafe { RegisterClassExA(&c) == 0 }
+        let fp_get_physical_device_surface_support: PFN_vkGetPhysicalDeviceSurfaceSupportKHR = load_instance_proc!(instance, "vkGetPhysicalDeviceSurfaceSupportKHR")?;
+        let fp_get_physical_device_surface_formats: PFN_vkGetPhysicalDeviceSurfaceFormatsKHR = load_instance_proc!(instance, "vkGetPhysicalDeviceSurfaceFormatsKHR")?;
+        let fp_get_physical_device_surface_present_modes: PFN_vkGetPhysicalDeviceSurfacePresentModesKHR = load_instance_proc!(instance, "vkGetPhysicalDeviceSurfacePresentModesKHR")?;
+        let fp_get_physical_device_surface_capabilities: PFN_vkGetPhysicalDeviceSurfaceCapabilitiesKHR = load_instance_proc!(instance, "vkGetPhysicalDeviceSurfaceCapabilitiesKHR")?;
+        let fp_destroy_surface: PFN_vkDestroySurfaceKHR = load_instance_proc!(instance, "vkDestroySurfaceKHR")?;
+        // the loader may resolve entry points of extensions the instance has not been created with
+        if extensions::enabled().map_or(false, |e| !e.has_instance_extension(window.surface_extension()))
         {
-            panic!("RegisterClass failed");
+            return Err(InterceptorError::MissingExtension(window.surface_extension()));
         }
+        if !window.presentation_support(instance)? { return Err(InterceptorError::PresentationUnsupported); }
 
-        let ws = WS_OVERLAPPED | WS_CAPTION | WS_BORDER | WS_SYSMENU | WS_MINIMIZEBOX | WS_VISIBLE;
-        let w = 1280;
-        let mut rect = RECT
+        // entry points are loaded before the surface is created, so that a missing one leaves nothing behind
+        let fp_begin_command_record: PFN_vkBeginCommandBuffer = load_instance_proc!(instance, "vkBeginCommandBuffer")?;
+        let fp_end_command_record: PFN_vkEndCommandBuffer = load_instance_proc!(instance, "vkEndCommandBuffer")?;
+        let fp_cmd_blit_image: PFN_vkCmdBlitImage = load_instance_proc!(instance, "vkCmdBlitImage")?;
+        let fp_cmd_clear_color_image: PFN_vkCmdClearColorImage = load_instance_proc!(instance, "vkCmdClearColorImage")?;
+        let fp_cmd_pipeline_barrier: PFN_vkCmdPipelineBarrier = load_instance_proc!(instance, "vkCmdPipelineBarrier")?;
+        // not through the hooks Unity may resolve them to
+        let fp_create_swapchain: PFN_vkCreateSwapchainKHR = match present::original_create_swapchain()
         {
-            left: 0, top: 0, right: w, bottom: w * 9 / 16
+            Some(f) => f,
+            None => load_instance_proc!(instance, "vkCreateSwapchainKHR")?
         };
-        unsafe { AdjustWindowRectEx(&mut rect, ws, false as _, 0); }
-
-        let handle = unsafe
+        let fp_destroy_swapchain: PFN_vkDestroySwapchainKHR = match present::original_destroy_swapchain()
         {
-            CreateWindowExA(0, c.lpszClassName, b"RenderingInterceptorTest\0".as_ptr() as _, ws,
-                CW_USEDEFAULT, CW_USEDEFAULT, rect.right - rect.left, rect.bottom - rect.top,
-                null_mut(), null_mut(), c.hInstance, null_mut()
-            )
+            Some(f) => f,
+            None => load_instance_proc!(instance, "vkDestroySwapchainKHR")?
         };
+        let fp_get_swapchain_images: PFN_vkGetSwapchainImagesKHR = load_instance_proc!(instance, "vkGetSwapchainImagesKHR")?;
+        let fp_device_wait_idle: PFN_vkDeviceWaitIdle = load_instance_proc!(instance, "vkDeviceWaitIdle")?;
 
-        let fp_get_physical_device_presentation_support: PFN_vkGetPhysicalDeviceWin32PresentationSupportKHR = unsafe
-        {
-            std::mem::transmute((instance.get_instance_proc_addr)(instance.instance, b"vkGetPhysicalDeviceWin32PresentationSupportKHR\0".as_ptr() as *const _).unwrap())
-        };
-        let fp_get_physical_device_surface_support: PFN_vkGetPhysicalDeviceSurfaceSupportKHR = unsafe
-        {
-            std::mem::transmute((instance.get_instance_proc_addr)(instance.instance, b"vkGetPhysicalDeviceSurfaceSupportKHR\0".as_ptr() as *const _).unwrap())
-        };
-        let pres_support = fp_get_physical_device_presentation_support(instance.physical_device, instance.queue_family_index);
-        if pres_support == 0 { panic!("WindowSubsystem does not support Vulkan rendering?"); }
-
-        let fp_create_surface_khr: PFN_vkCreateWin32SurfaceKHR = unsafe
-        {
-            std::mem::transmute((instance.get_instance_proc_addr)(instance.instance, b"vkCreateWin32SurfaceKHR\0".as_ptr() as *const _).unwrap())
-        };
-        let sinfo = VkWin32SurfaceCreateInfoKHR
-        {
-            hinstance: c.hInstance, hwnd: handle,
-            .. Default::default()
-        };
-        let mut sptr = std::mem::MaybeUninit::uninit();
-        let r = fp_create_surface_khr(instance.instance, &sinfo, std::ptr::null(), sptr.as_mut_ptr());
-        if r != VK_SUCCESS { panic!("vkCreateWin32SurfaceKHR failed"); }
-        let surface = unsafe { sptr.assume_init() };
+        let surface = window.create_surface(instance)?;
+        live_objects::created("surface");
         let mut surface_supported = 0;
         fp_get_physical_device_surface_support(instance.physical_device, instance.queue_family_index, surface, &mut surface_supported);
         if surface_supported == 0
         {
-            panic!("Vulkan does not support this surface to render");
+            fp_destroy_surface(instance.instance, surface, std::ptr::null());
+            live_objects::destroyed("surface");
+            return Err(InterceptorError::PresentationUnsupported);
         }
 
-        let fp_get_physical_device_surface_formats: PFN_vkGetPhysicalDeviceSurfaceFormatsKHR = unsafe
-        {
-            std::mem::transmute((instance.get_instance_proc_addr)(instance.instance, b"vkGetPhysicalDeviceSurfaceFormatsKHR\0".as_ptr() as *const _).unwrap())
-        };
-        let fp_get_physical_device_surface_present_modes: PFN_vkGetPhysicalDeviceSurfacePresentModesKHR = unsafe
-        {
-            std::mem::transmute((instance.get_instance_proc_addr)(instance.instance, b"vkGetPhysicalDeviceSurfacePresentModesKHR\0".as_ptr() as *const _).unwrap())
-        };
-        let fp_get_physical_device_surface_capabilities: PFN_vkGetPhysicalDeviceSurfaceCapabilitiesKHR = unsafe
-        {
-            std::mem::transmute((instance.get_instance_proc_addr)(instance.instance, b"vkGetPhysicalDeviceSurfaceCapabilitiesKHR\0".as_ptr() as *const _).unwrap())
-        };
         let format = unsafe
         {
             let mut format_cnt = 0;
@@ -220,7 +357,19 @@ impl ExtRenderTarget
             fmts.set_len(format_cnt as _);
             fp_get_physical_device_surface_formats(instance.physical_device, surface, &mut format_cnt, fmts.as_mut_ptr());
             
-            fmts.into_iter().find(|f| f.format == VK_FORMAT_R8G8B8A8_SRGB || f.format == VK_FORMAT_B8G8R8A8_SRGB).unwrap()
+            // software drivers may only expose UNORM formats for headless surfaces
+            fmts.iter().find(|f| f.format == VK_FORMAT_R8G8B8A8_SRGB || f.format == VK_FORMAT_B8G8R8A8_SRGB).or(fmts.first()).cloned()
+        };
+        let format = match format
+        {
+            Some(f) => f,
+            None =>
+            {
+                // nothing can be presented to a surface without formats
+                fp_destroy_surface(instance.instance, surface, std::ptr::null());
+                live_objects::destroyed("surface");
+                return Err(InterceptorError::PresentationUnsupported);
+            }
         };
         let pres_mode = unsafe
         {
@@ -230,8 +379,7 @@ impl ExtRenderTarget
             pres_modes.set_len(pm_cnt as _);
             fp_get_physical_device_surface_present_modes(instance.physical_device, surface, &mut pm_cnt, pres_modes.as_mut_ptr());
 
-            let pm0 = pres_modes[0];
-            pres_modes.into_iter().find(|&m| m == VK_PRESENT_MODE_FIFO_KHR || m == VK_PRESENT_MODE_MAILBOX_KHR).unwrap_or(pm0)
+            window::choose_present_mode(&pres_modes, window::requested_present_mode())
         };
         let mut caps = std::mem::MaybeUninit::uninit();
         fp_get_physical_device_surface_capabilities(instance.physical_device, surface, caps.as_mut_ptr());
@@ -242,339 +390,1019 @@ impl ExtRenderTarget
         }
         else { VK_COMPOSITE_ALPHA_OPAQUE_BIT_KHR };
 
-        let fp_create_swapchain: PFN_vkCreateSwapchainKHR = unsafe
+        let buffer_count = 2.max(caps.minImageCount).min(caps.maxImageCount);
+        let (rc, transposer) = match RenderControl::new(instance, settings::current().frames_in_flight)
+            .and_then(|rc| Ok((rc, Transposer::new(instance)?)))
         {
-            std::mem::transmute((instance.get_instance_proc_addr)(instance.instance, b"vkCreateSwapchainKHR\0".as_ptr() as *const _).unwrap())
+            Ok(p) => p,
+            Err(e) =>
+            {
+                fp_destroy_surface(instance.instance, surface, std::ptr::null());
+                live_objects::destroyed("surface");
+                return Err(e);
+            }
         };
-        let mut wrect = std::mem::MaybeUninit::uninit();
-        unsafe { GetClientRect(handle, wrect.as_mut_ptr()); }
-        let wrect = unsafe { wrect.assume_init() };
-        let ew =
-            if caps.currentExtent.width == 0xffff_ffff { (wrect.right - wrect.left) as _ }
-            else { caps.currentExtent.width };
-        let eh =
-            if caps.currentExtent.height == 0xffff_ffff { (wrect.bottom - wrect.top) as _ }
-            else { caps.currentExtent.height };
-        let buffer_count = 2.max(caps.minImageCount).min(caps.maxImageCount);
-        let scinfo = VkSwapchainCreateInfoKHR
+
+        let mut this = ExtRenderTarget
         {
+            window,
+            instance: instance.instance,
+            device: instance.device,
+            graphics_queue: instance.graphics_queue,
+            physical_device: instance.physical_device,
             surface,
-            minImageCount: buffer_count,
-            imageFormat: format.format,
-            imageColorSpace: format.colorSpace,
-            imageExtent: VkExtent2D { width: ew, height: eh },
+            surface_format: format,
+            present_mode: pres_mode,
+            composite_alpha: available_composite_alpha,
+            buffer_count,
+            swapchain: std::ptr::null_mut(),
+            extent: VkExtent2D { width: 0, height: 0 },
+            bb_images: Vec::new(),
+            bb_presented: Vec::new(),
+            needs_rebuild: false,
+            rc,
+            transform: Transform::IDENTITY,
+            transposer,
+            fp_begin_command_record,
+            fp_end_command_record,
+            fp_cmd_blit_image,
+            fp_cmd_clear_color_image,
+            fp_cmd_pipeline_barrier,
+            fp_get_surface_capabilities: fp_get_physical_device_surface_capabilities,
+            fp_create_swapchain,
+            fp_destroy_swapchain,
+            fp_destroy_surface,
+            fp_get_swapchain_images,
+            fp_device_wait_idle
+        };
+        let extent = this.surface_extent();
+        if extent.width == 0 || extent.height == 0 { this.needs_rebuild = true; }
+        else { this.create_swapchain(extent)?; }
+
+        Ok(this)
+    }
+
+    /// The extent the swapchain should have now; zero while the window has no area (e.g. minimized)
+    fn surface_extent(&self) -> VkExtent2D
+    {
+        let mut caps = std::mem::MaybeUninit::uninit();
+        (self.fp_get_surface_capabilities)(self.physical_device, self.surface, caps.as_mut_ptr());
+        let caps = unsafe { caps.assume_init() };
+        if caps.currentExtent.width != 0xffff_ffff
+        {
+            return VkExtent2D { width: caps.currentExtent.width, height: caps.currentExtent.height };
+        }
+
+        // the surface lets the swapchain decide
+        let wextent = self.window.client_extent();
+        if wextent.width == 0 || wextent.height == 0 { return VkExtent2D { width: 0, height: 0 }; }
+        VkExtent2D
+        {
+            width: wextent.width.max(caps.minImageExtent.width).min(caps.maxImageExtent.width),
+            height: wextent.height.max(caps.minImageExtent.height).min(caps.maxImageExtent.height)
+        }
+    }
+    /// Creates a swapchain of `extent`, retiring the current one (if any) as `oldSwapchain`.
+    /// The retired swapchain is destroyed even on failure, as it cannot be used anymore.
+    fn create_swapchain(&mut self, extent: VkExtent2D) -> Result<(), InterceptorError>
+    {
+        let old_swapchain = std::mem::replace(&mut self.swapchain, std::ptr::null_mut());
+        let scinfo = VkSwapchainCreateInfoKHR
+        {
+            surface: self.surface,
+            minImageCount: self.buffer_count,
+            imageFormat: self.surface_format.format,
+            imageColorSpace: self.surface_format.colorSpace,
+            imageExtent: VkExtent2D { width: extent.width, height: extent.height },
             imageArrayLayers: 1,
             imageUsage: VK_IMAGE_USAGE_TRANSFER_DST_BIT,
             imageSharingMode: VK_SHARING_MODE_EXCLUSIVE,
             preTransform: VK_SURFACE_TRANSFORM_IDENTITY_BIT_KHR,
-            compositeAlpha: available_composite_alpha,
-            presentMode: pres_mode,
+            compositeAlpha: self.composite_alpha,
+            presentMode: self.present_mode,
+            oldSwapchain: old_swapchain,
             .. Default::default()
         };
         let mut swapchain = std::mem::MaybeUninit::uninit();
-        fp_create_swapchain(instance.device, &scinfo, std::ptr::null(), swapchain.as_mut_ptr());
-        let swapchain = unsafe { swapchain.assume_init() };
+        let r = (self.fp_create_swapchain)(self.device, &scinfo, std::ptr::null(), swapchain.as_mut_ptr());
+        if !old_swapchain.is_null() { (self.fp_destroy_swapchain)(self.device, old_swapchain, std::ptr::null()); live_objects::destroyed("swapchain"); }
+        self.bb_images.clear();
+        self.bb_presented.clear();
+        vk_check("vkCreateSwapchainKHR", r)?;
+        live_objects::created("swapchain");
+        self.swapchain = unsafe { swapchain.assume_init() };
 
-        let fp_get_swapchain_images: PFN_vkGetSwapchainImagesKHR = unsafe
-        {
-            std::mem::transmute((instance.get_instance_proc_addr)(instance.instance, b"vkGetSwapchainImagesKHR\0".as_ptr() as *const _).unwrap())
-        };
         let mut bb_image_count = 0;
-        fp_get_swapchain_images(instance.device, swapchain, &mut bb_image_count, std::ptr::null_mut());
-        let mut bb_images = Vec::with_capacity(bb_image_count as _);
-        unsafe { bb_images.set_len(bb_image_count as _); }
-        fp_get_swapchain_images(instance.device, swapchain, &mut bb_image_count, bb_images.as_mut_ptr());
+        (self.fp_get_swapchain_images)(self.device, self.swapchain, &mut bb_image_count, std::ptr::null_mut());
+        self.bb_images = Vec::with_capacity(bb_image_count as _);
+        unsafe { self.bb_images.set_len(bb_image_count as _); }
+        (self.fp_get_swapchain_images)(self.device, self.swapchain, &mut bb_image_count, self.bb_images.as_mut_ptr());
+        self.bb_presented = vec![false; bb_image_count as _];
+        self.extent = extent;
 
-        ExtRenderTarget
-        {
-            handle,
-            instance: instance.instance,
-            device: instance.device,
-            graphics_queue: instance.graphics_queue,
-            get_instance_proc_addr: instance.get_instance_proc_addr,
-            surface,
-            swapchain,
-            extent: scinfo.imageExtent,
-            bb_images,
-            rc: RenderControl::new(instance)
-        }
+        Ok(())
+    }
+    /// Waits for the device to finish using the swapchain and replaces it with one matching the surface.
+    /// Leaves `needs_rebuild` set while the surface has no area.
+    fn rebuild_swapchain(&mut self) -> SinkResult
+    {
+        let extent = self.surface_extent();
+        self.needs_rebuild = true;
+        if extent.width == 0 || extent.height == 0 { return Ok(()); }
+
+        info!("Interceptor: rebuilding swapchain ({}x{} -> {}x{})", self.extent.width, self.extent.height, extent.width, extent.height);
+        (self.fp_device_wait_idle)(self.device);
+        self.create_swapchain(extent)?;
+        self.needs_rebuild = false;
+
+        Ok(())
     }
 
-    pub fn wait_next_frame(&mut self) -> u32
+    /// Returns the image index and whether the swapchain is suboptimal
+    pub fn wait_next_frame(&mut self) -> Result<(u32, bool), VkResult>
     {
-        self.rc.acquire_next_frame(self.swapchain, self.device)
+        self.rc.acquire_next_frame(self.swapchain)
     }
-    pub fn submit_command(&mut self, command: VkCommandBuffer, bb_index: u32)
+    pub fn submit_command(&mut self, bb_index: u32) -> Result<VkResult, InterceptorError>
     {
-        self.rc.submit_command(command, self.swapchain, bb_index, self.device, self.graphics_queue);
+        self.rc.submit_command(self.swapchain, bb_index, self.graphics_queue)
     }
-
-    extern "system" fn wev_callback(wnd: HWND, msg: UINT, wp: WPARAM, lp: LPARAM) -> LRESULT
+}
+impl FrameSink for ExtRenderTarget
+{
+    fn name(&self) -> &str { "window" }
+    fn process(&mut self, frame: &UnityVulkanImage) -> SinkResult
     {
-        if msg == WM_QUIT
+        // the window stays closed until the sink is created again
+        if !self.window.process_events() { return Ok(()); }
+        let extent = self.surface_extent();
+        if self.needs_rebuild || extent.width != self.extent.width || extent.height != self.extent.height
         {
-            return 0;
+            self.rebuild_swapchain()?;
+            // nothing to present to while the window has no area
+            if self.needs_rebuild { return Ok(()); }
         }
+        let config = settings::current();
+        if config.frames_in_flight != self.rc.frames_in_flight() { self.rc.set_frames_in_flight(config.frames_in_flight)?; }
 
-        unsafe { DefWindowProcA(wnd, msg, wp, lp) }
+        // placed in the orientation of the output: (source rect in the frame, crop of the transformed source, destination rect)
+        let transform = self.transform;
+        let placement = config.source_rect(frame.extent).and_then(|src|
+        {
+            let (width, height) = transform.output_extent(src.width, src.height);
+            scaling::place(Rect::new(0, 0, width, height), self.extent, config.scaling_mode).map(|(crop, dst)| (src, crop, dst))
+        });
+        if let Some((src, _, _)) = placement
+        {
+            if transform.transpose { self.transposer.prepare(frame.format, src)?; }
+        }
+
+        let cbuf = self.rc.begin_frame();
+        let bb_index = match self.wait_next_frame()
+        {
+            Ok((x, suboptimal)) => { self.needs_rebuild = suboptimal; x },
+            Err(VK_ERROR_OUT_OF_DATE_KHR) =>
+            {
+                // no image was acquired; skip this frame and present the next one to the new swapchain
+                return self.rebuild_swapchain();
+            },
+            Err(r) => return Err(InterceptorError::vulkan("vkAcquireNextImageKHR", r))
+        };
+        (self.fp_begin_command_record)(cbuf, &Default::default());
+
+        let dst_image = self.bb_images[bb_index as usize];
+        let first_use = !self.bb_presented[bb_index as usize];
+        let in_barrier_transfer_ready = VkImageMemoryBarrier
+        {
+            image: dst_image, subresourceRange: VkImageSubresourceRange
+            {
+                aspectMask: VK_IMAGE_ASPECT_COLOR_BIT,
+                baseArrayLayer: 0, layerCount: 1,
+                baseMipLevel: 0, levelCount: 1
+            },
+            // images of a new swapchain have never been presented and start out undefined
+            oldLayout: if first_use { VK_IMAGE_LAYOUT_UNDEFINED } else { VK_IMAGE_LAYOUT_PRESENT_SRC_KHR },
+            newLayout: VK_IMAGE_LAYOUT_TRANSFER_DST_OPTIMAL,
+            srcAccessMask: if first_use { 0 } else { VK_ACCESS_MEMORY_READ_BIT }, dstAccessMask: VK_ACCESS_TRANSFER_WRITE_BIT,
+            srcQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED, dstQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED,
+            .. Default::default()
+        };
+        let out_barrier_present_ready = VkImageMemoryBarrier
+        {
+            image: dst_image, subresourceRange: VkImageSubresourceRange
+            {
+                aspectMask: VK_IMAGE_ASPECT_COLOR_BIT,
+                baseArrayLayer: 0, layerCount: 1,
+                baseMipLevel: 0, levelCount: 1
+            },
+            oldLayout: VK_IMAGE_LAYOUT_TRANSFER_DST_OPTIMAL, newLayout: VK_IMAGE_LAYOUT_PRESENT_SRC_KHR,
+            srcAccessMask: VK_ACCESS_TRANSFER_WRITE_BIT, dstAccessMask: VK_ACCESS_MEMORY_READ_BIT,
+            srcQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED, dstQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED,
+            .. Default::default()
+        };
+        let color_range = VkImageSubresourceRange
+        {
+            aspectMask: VK_IMAGE_ASPECT_COLOR_BIT,
+            baseArrayLayer: 0, layerCount: 1,
+            baseMipLevel: 0, levelCount: 1
+        };
+        // the blit overwrites before the clear has finished otherwise
+        let barrier_clear_done = VkImageMemoryBarrier
+        {
+            image: dst_image, subresourceRange: color_range.clone(),
+            oldLayout: VK_IMAGE_LAYOUT_TRANSFER_DST_OPTIMAL, newLayout: VK_IMAGE_LAYOUT_TRANSFER_DST_OPTIMAL,
+            srcAccessMask: VK_ACCESS_TRANSFER_WRITE_BIT, dstAccessMask: VK_ACCESS_TRANSFER_WRITE_BIT,
+            srcQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED, dstQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED,
+            .. Default::default()
+        };
+        // the border is left over from an older frame (or undefined) unless cleared
+        let has_border = placement.map_or(true, |(_, _, dst)| !dst.covers(self.extent));
+        let border_color = VkClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] };
+
+        (self.fp_cmd_pipeline_barrier)(cbuf, VK_PIPELINE_STAGE_BOTTOM_OF_PIPE_BIT, VK_PIPELINE_STAGE_TRANSFER_BIT, 0,
+            0, std::ptr::null(), 0, std::ptr::null(), 1, &in_barrier_transfer_ready);
+        if has_border
+        {
+            (self.fp_cmd_clear_color_image)(cbuf, dst_image, VK_IMAGE_LAYOUT_TRANSFER_DST_OPTIMAL, &border_color, 1, &color_range);
+        }
+        if let Some((src, crop, dst_rect)) = placement
+        {
+            if has_border
+            {
+                (self.fp_cmd_pipeline_barrier)(cbuf, VK_PIPELINE_STAGE_TRANSFER_BIT, VK_PIPELINE_STAGE_TRANSFER_BIT, 0,
+                    0, std::ptr::null(), 0, std::ptr::null(), 1, &barrier_clear_done);
+            }
+            // blits can only mirror; swapping the axes takes copies through a buffer
+            let (src_image, src_layout, src_area) = if transform.transpose
+            {
+                let (width, height) = transform.output_extent(src.width, src.height);
+                (self.transposer.record(cbuf, frame, src), VK_IMAGE_LAYOUT_TRANSFER_SRC_OPTIMAL, Rect::new(0, 0, width, height))
+            }
+            else { (frame.image, frame.layout, src) };
+            let region = VkImageBlit
+            {
+                srcSubresource: VkImageSubresourceLayers
+                {
+                    aspectMask: VK_IMAGE_ASPECT_COLOR_BIT,
+                    baseArrayLayer: 0,
+                    layerCount: 1,
+                    mipLevel: 0
+                },
+                dstSubresource: VkImageSubresourceLayers
+                {
+                    aspectMask: VK_IMAGE_ASPECT_COLOR_BIT,
+                    baseArrayLayer: 0,
+                    layerCount: 1,
+                    mipLevel: 0
+                },
+                srcOffsets: transform.blit_source_offsets(src_area, crop),
+                dstOffsets: dst_rect.blit_offsets()
+            };
+            (self.fp_cmd_blit_image)(cbuf, src_image, src_layout, dst_image, VK_IMAGE_LAYOUT_TRANSFER_DST_OPTIMAL,
+                1, &region, scaling::filter(config.scaling_mode));
+        }
+        (self.fp_cmd_pipeline_barrier)(cbuf, VK_PIPELINE_STAGE_TRANSFER_BIT, VK_PIPELINE_STAGE_TOP_OF_PIPE_BIT, 0,
+            0, std::ptr::null(), 0, std::ptr::null(), 1, &out_barrier_present_ready);
+
+        (self.fp_end_command_record)(cbuf);
+        match self.submit_command(bb_index)?
+        {
+            VK_SUCCESS => { self.bb_presented[bb_index as usize] = true; Ok(()) },
+            // rebuilt before the next acquisition
+            VK_SUBOPTIMAL_KHR | VK_ERROR_OUT_OF_DATE_KHR => { self.bb_presented[bb_index as usize] = true; self.needs_rebuild = true; Ok(()) },
+            r => Err(InterceptorError::vulkan("vkQueuePresentKHR", r))
+        }
     }
+    fn set_transform(&mut self, transform: Transform) -> bool { self.transform = transform; true }
 }
 impl Drop for ExtRenderTarget
 {
     fn drop(&mut self)
     {
-        let fp_destroy_swapchain: PFN_vkDestroySwapchainKHR = unsafe
+        (self.fp_device_wait_idle)(self.device);
+        if !self.swapchain.is_null() { (self.fp_destroy_swapchain)(self.device, self.swapchain, std::ptr::null_mut()); live_objects::destroyed("swapchain"); }
+        (self.fp_destroy_surface)(self.instance, self.surface, std::ptr::null_mut());
+        live_objects::destroyed("surface");
+    }
+}
+
+impl Drop for VkRenderingInterceptor
+{
+    fn drop(&mut self)
+    {
+        // nothing submitted by the plugin may still be running; the parts are then destroyed in reverse order of creation
+        (self.fp_device_wait_idle)(self.instance.device);
+        self.present_capture = None;
+        self.texture_requests.clear();
+        self.resolver = None;
+        self.readback_sink = None;
+        self.readback_callback = None;
+        self.sinks.clear();
+        trace!("Interceptor::VkRenderingInterceptor Destroyed");
+    }
+}
+
+/// How Unity has to prepare its command buffer for each kind of event (see `event::EVENT_*`)
+fn event_config(kind: c_int) -> UnityVulkanPluginEventConfig
+{
+    match kind
+    {
+        // Copyコマンド+Present命令を出すのでoutside renderpass、かつGraphics Queueアクセス可能である必要がある
+        // The layout barriers of the textures accessed for the copies (and the commands rendering them)
+        // have to be submitted before the plugin's own submissions
+        event::EVENT_CAPTURE | event::EVENT_SCREENSHOT => UnityVulkanPluginEventConfig
         {
-            std::mem::transmute((self.get_instance_proc_addr)(self.instance, b"vkDestroySwapchainKHR\0".as_ptr() as *const _).unwrap())
-        };
-        let fp_destroy_surface: PFN_vkDestroySurfaceKHR = unsafe
+            flags: UnityVulkanEventConfigFlags::FlushCommandBuffers,
+            render_pass_precondition: UnityVulkanEventRenderPassPreCondition::EnsureOutside,
+            graphics_queue_access: UnityVulkanGraphicsQueueAccess::Allow
+        },
+        // the copies waited for may depend on commands Unity has not submitted yet
+        event::EVENT_FLUSH => UnityVulkanPluginEventConfig
         {
-            std::mem::transmute((self.get_instance_proc_addr)(self.instance, b"vkDestroySurfaceKHR\0".as_ptr() as *const _).unwrap())
-        };
-
-        fp_destroy_swapchain(self.device, self.swapchain, std::ptr::null_mut());
-        fp_destroy_surface(self.instance, self.surface, std::ptr::null_mut());
+            flags: UnityVulkanEventConfigFlags::FlushCommandBuffers,
+            render_pass_precondition: UnityVulkanEventRenderPassPreCondition::EnsureOutside,
+            graphics_queue_access: UnityVulkanGraphicsQueueAccess::Allow
+        },
+        // only the plugin's own state changes
+        _ => UnityVulkanPluginEventConfig
+        {
+            flags: UnityVulkanEventConfigFlags::empty(),
+            render_pass_precondition: UnityVulkanEventRenderPassPreCondition::DontCare,
+            graphics_queue_access: UnityVulkanGraphicsQueueAccess::DontCare
+        }
     }
 }
 
-const SCREEN_CAPTURE_EVENT_ID: c_int = 1;
+/// Sink id of the mirror window registered at initialization
+pub const WINDOW_SINK_ID: SinkId = 0;
+/// Sink id of the PNG screenshot writer registered at initialization
+pub const SCREENSHOT_SINK_ID: SinkId = 1;
 
 pub struct VkRenderingInterceptor
 {
     uinstance: UnityGraphicsVulkanRef,
     instance: UnityVulkanInstance,
-    ert: ExtRenderTarget,
     current_rb: UnityRenderBuffer,
-    cmd_pool: VkCommandPool,
-    cbuf: VkCommandBuffer,
-    fp_reset_command_pool: PFN_vkResetCommandPool,
-    fp_begin_command_record: PFN_vkBeginCommandBuffer,
-    fp_end_command_record: PFN_vkEndCommandBuffer,
-    fp_cmd_blit_image: PFN_vkCmdBlitImage,
-    fp_cmd_pipeline_barrier: PFN_vkCmdPipelineBarrier
+    sinks: SinkRegistry,
+    readback_sink: Option<SinkId>,
+    /// shared with the readback sink, which is recreated around it after a device reset
+    readback_callback: Option<SharedReadbackCallback>,
+    /// created on the first multisampled frame Unity has no resolve texture for
+    resolver: Option<MsaaResolver>,
+    /// textures to feed to a sink instead of the render buffer on the next capture event
+    texture_requests: Vec<(*mut c_void, SinkId)>,
+    /// created on the first frame Unity presents
+    present_capture: Option<PresentCapture>,
+    /// set by a capture event, so that the next presented frame is not captured again
+    event_captured: bool,
+    fp_device_wait_idle: PFN_vkDeviceWaitIdle
 }
 impl VkRenderingInterceptor
 {
-    pub fn new(ifs: *mut IUnityInterfaces) -> Self
+    pub fn new(ifs: *mut IUnityInterfaces) -> Result<Self, InterceptorError> { Self::build(ifs, None) }
+    /// Recreates an interceptor released by `release` on the device Unity has after a reset
+    pub fn rebuild(ifs: *mut IUnityInterfaces, released: ReleasedInterceptor) -> Result<Self, InterceptorError>
     {
-        let uinstance = UnityGraphicsVulkanRef::from_interfaces(ifs).expect("no IUnityGraphicsVulkan");
+        Self::build(ifs, Some(released))
+    }
+    fn build(ifs: *mut IUnityInterfaces, released: Option<ReleasedInterceptor>) -> Result<Self, InterceptorError>
+    {
+        let uinstance = UnityGraphicsVulkanRef::from_interfaces(ifs).ok_or(InterceptorError::MissingUnityInterface("IUnityGraphicsVulkan"))?;
         let instance = uinstance.instance();
-        let ert = ExtRenderTarget::new(&instance);
         
-        // Copyコマンド+Present命令を出すのでoutside renderpass、かつGraphics Queueアクセス可能である必要がある
-        uinstance.configure_event(SCREEN_CAPTURE_EVENT_ID, &UnityVulkanPluginEventConfig
+        for kind in 0 .. event::EVENT_KIND_COUNT
         {
-            flags: 0,
-            render_pass_precondition: kUnityVulkanRenderPass_EnsureOutside,
-            graphics_queue_access: kUnityVulkanGraphicsQueueAccess_Allow
-        });
+            if let Some(id) = event::event_id(kind) { uinstance.configure_event(id, &event_config(kind)); }
+        }
 
-        let fp_create_command_pool: PFN_vkCreateCommandPool = unsafe
+        let fp_device_wait_idle = load_instance_proc!(instance, "vkDeviceWaitIdle")?;
+        let window_sink = Box::new(ExtRenderTarget::new(&instance, window::create_default_backend()?)?);
+        let screenshot_sink = Box::new(ScreenshotSink::new(&instance)?);
+        let (sinks, readback) = match released
         {
-            std::mem::transmute((instance.get_instance_proc_addr)(instance.instance, b"vkCreateCommandPool\0".as_ptr() as *const _).unwrap())
+            None =>
+            {
+                let mut sinks = SinkRegistry::new();
+                let window_sink = sinks.add(window_sink);
+                let screenshot_sink = sinks.add(screenshot_sink);
+                debug_assert_eq!(window_sink, WINDOW_SINK_ID);
+                debug_assert_eq!(screenshot_sink, SCREENSHOT_SINK_ID);
+                (sinks, None)
+            },
+            // the new sinks take the places of the released ones
+            Some(ReleasedInterceptor { mut sinks, readback }) =>
+            {
+                sinks.replace(WINDOW_SINK_ID, window_sink);
+                sinks.replace(SCREENSHOT_SINK_ID, screenshot_sink);
+                if let Some((id, ref callback)) = readback
+                {
+                    sinks.replace(id, Box::new(ReadbackSink::new(&instance, forward_readback(callback))?));
+                }
+                (sinks, readback)
+            }
         };
-        let fp_alloc_command_buffer: PFN_vkAllocateCommandBuffers = unsafe
+        let (readback_sink, readback_callback) = match readback { Some((id, cb)) => (Some(id), Some(cb)), None => (None, None) };
+
+        trace!("Interceptor::VkRenderingInterceptor Initialized");
+        Ok(VkRenderingInterceptor
         {
-            std::mem::transmute((instance.get_instance_proc_addr)(instance.instance, b"vkAllocateCommandBuffers\0".as_ptr() as *const _).unwrap())
-        };
-        let cpinfo = VkCommandPoolCreateInfo
+            uinstance, instance, current_rb: std::ptr::null_mut(),
+            sinks, readback_sink, readback_callback, resolver: None, texture_requests: Vec::new(),
+            present_capture: None, event_captured: false, fp_device_wait_idle
+        })
+    }
+    pub fn set_render_buffer(&mut self, rb: UnityRenderBuffer)
+    {
+        self.current_rb = rb;
+    }
+    /// Enables readback of every intercepted frame into host memory, or disables it with None
+    pub fn set_readback_callback(&mut self, callback: Option<ReadbackCallback>) -> Result<(), InterceptorError>
+    {
+        if let Some(id) = self.readback_sink.take() { self.sinks.remove(id); }
+        self.readback_callback = None;
+        if let Some(cb) = callback
         {
-            queueFamilyIndex: instance.queue_family_index,
-            .. Default::default()
-        };
-        let mut cmd_pool = std::mem::MaybeUninit::uninit();
-        fp_create_command_pool(instance.device, &cpinfo, std::ptr::null(), cmd_pool.as_mut_ptr());
-        let cmd_pool = unsafe { cmd_pool.assume_init() };
-        let ainfo = VkCommandBufferAllocateInfo
+            let shared = Arc::new(Mutex::new(cb));
+            self.readback_sink = Some(self.sinks.add(Box::new(ReadbackSink::new(&self.instance, forward_readback(&shared))?)));
+            self.readback_callback = Some(shared);
+        }
+
+        Ok(())
+    }
+    /// Destroys every object made on the device before Unity resets it.
+    /// The sinks keep their ids, states and transforms and the readback its callback, until `rebuild` recreates them;
+    /// sinks added by `add_sink` are kept as they are.
+    pub fn release(mut self) -> ReleasedInterceptor
+    {
+        (self.fp_device_wait_idle)(self.instance.device);
+        self.present_capture = None;
+        self.texture_requests.clear();
+        self.resolver = None;
+        let readback = self.readback_sink.take().zip(self.readback_callback.take());
+        let mut sinks = std::mem::replace(&mut self.sinks, SinkRegistry::new());
+        // in reverse order of registration
+        for &id in readback.as_ref().map(|(id, _)| id).into_iter().chain(&[SCREENSHOT_SINK_ID, WINDOW_SINK_ID])
         {
-            commandPool: cmd_pool,
-            commandBufferCount: 1,
-            level: VK_COMMAND_BUFFER_LEVEL_PRIMARY,
-            .. Default::default()
-        };
-        let mut cbuf = std::mem::MaybeUninit::uninit();
-        fp_alloc_command_buffer(instance.device, &ainfo, cbuf.as_mut_ptr());
+            sinks.replace(id, Box::new(ReleasedSink));
+        }
+        trace!("Interceptor::VkRenderingInterceptor Released");
 
-        trace!("Interceptor::VkRenderingInterceptor Initialized");
-        VkRenderingInterceptor
+        ReleasedInterceptor { sinks, readback }
+    }
+    pub fn sinks_mut(&mut self) -> &mut SinkRegistry { &mut self.sinks }
+    /// Sink id of the readback enabled by `set_readback_callback`
+    pub fn readback_sink_id(&self) -> Option<SinkId> { self.readback_sink }
+    
+    /// Feeds `texture` (a native texture pointer) to the sink `id` instead of the render buffer on the next capture event.
+    /// Returns false if no sink has the id.
+    pub fn request_texture_capture(&mut self, texture: *mut c_void, id: SinkId) -> bool
+    {
+        if self.sinks.is_enabled(id).is_none() { return false; }
+        // a later request for the same sink replaces the earlier one
+        self.texture_requests.retain(|&(_, s)| s != id);
+        self.texture_requests.push((texture, id));
+        true
+    }
+
+    /// Handles a plugin event of `kind` (see `event::EVENT_*`), with the data given to `GL.IssuePluginEventAndData` if any
+    pub fn handle_plugin_event(&mut self, kind: c_int, data: Option<&CaptureEventData>) -> Result<(), InterceptorError>
+    {
+        match kind
         {
-            fp_reset_command_pool: unsafe
-            {
-                std::mem::transmute((instance.get_instance_proc_addr)(instance.instance, b"vkResetCommandPool\0".as_ptr() as *const _).unwrap())
-            },
-            fp_begin_command_record: unsafe
-            {
-                std::mem::transmute((instance.get_instance_proc_addr)(instance.instance, b"vkBeginCommandBuffer\0".as_ptr() as *const _).unwrap())
-            },
-            fp_end_command_record: unsafe
+            event::EVENT_CAPTURE => match data
             {
-                std::mem::transmute((instance.get_instance_proc_addr)(instance.instance, b"vkEndCommandBuffer\0".as_ptr() as *const _).unwrap())
+                Some(d) => self.handle_event_data(d),
+                None => self.handle_event()
             },
-            fp_cmd_blit_image: unsafe
+            // the other outputs still get the presented frame
+            event::EVENT_SCREENSHOT => match data
             {
-                std::mem::transmute((instance.get_instance_proc_addr)(instance.instance, b"vkCmdBlitImage\0".as_ptr() as *const _).unwrap())
+                Some(d) => { let _scope = EventScope::enter(d)?; self.capture(d.render_buffer, Some(SCREENSHOT_SINK_ID)) },
+                None => self.capture(self.current_rb, Some(SCREENSHOT_SINK_ID))
             },
-            fp_cmd_pipeline_barrier: unsafe
+            event::EVENT_START_RECORDING | event::EVENT_STOP_RECORDING =>
             {
-                std::mem::transmute((instance.get_instance_proc_addr)(instance.instance, b"vkCmdPipelineBarrier\0".as_ptr() as *const _).unwrap())
+                if let Some(id) = self.readback_sink { self.sinks.set_enabled(id, kind == event::EVENT_START_RECORDING); }
+                Ok(())
             },
-            uinstance, instance, ert, current_rb: std::ptr::null_mut(),
-            cmd_pool, cbuf: unsafe { cbuf.assume_init() }
+            event::EVENT_FLUSH => { self.sinks.flush(); Ok(()) },
+            _ => Ok(())
         }
     }
-    pub fn set_render_buffer(&mut self, rb: UnityRenderBuffer)
+    /// Captures the render buffer set by `set_render_buffer` for every enabled sink
+    pub fn handle_event(&mut self) -> Result<(), InterceptorError>
     {
-        self.current_rb = rb;
+        self.capture(self.current_rb, None)?;
+        self.event_captured = true;
+        Ok(())
     }
-    
-    pub fn handle_event(&mut self)
+    /// Captures the render buffer of an event issued with data, with its own target, region and tag
+    pub fn handle_event_data(&mut self, data: &CaptureEventData) -> Result<(), InterceptorError>
     {
-        let rb_image = self.uinstance.access_render_buffer_texture(
-            self.current_rb,
-            Some(&VkImageSubresource { aspectMask: VK_IMAGE_ASPECT_COLOR_BIT, arrayLayer: 0, mipLevel: 0 }),
-            VK_IMAGE_LAYOUT_TRANSFER_SRC_OPTIMAL,
-            VK_PIPELINE_STAGE_TRANSFER_BIT,
-            VK_ACCESS_TRANSFER_READ_BIT,
-            kUnityVulkanResourceAccess_PipelineBarrier
-        ).expect("Unable to get render buffer texture");
-
-        let bb_index = self.ert.wait_next_frame();
-        (self.fp_reset_command_pool)(self.ert.device, self.cmd_pool, VK_COMMAND_POOL_RESET_RELEASE_RESOURCES_BIT);
-        (self.fp_begin_command_record)(self.cbuf, &Default::default());
+        let target = if data.target == event::ALL_SINKS { None } else { Some(data.target) };
+        if let Some(id) = target
+        {
+            if self.sinks.is_enabled(id).is_none() { return Err(InterceptorError::InvalidEventData("no sink has the target id")); }
+        }
+        let _scope = EventScope::enter(data)?;
 
-        let dst_image = self.ert.bb_images[bb_index as usize];
-        let in_barrier_transfer_ready = VkImageMemoryBarrier
+        self.capture(data.render_buffer, target)?;
+        self.event_captured = true;
+        Ok(())
+    }
+    /// Feeds `rb` to the sink `target`, or to every enabled sink if None
+    fn capture(&mut self, rb: UnityRenderBuffer, target: Option<SinkId>) -> Result<(), InterceptorError>
+    {
+        let subresource = VkImageSubresource { aspectMask: VK_IMAGE_ASPECT_COLOR_BIT, arrayLayer: 0, mipLevel: 0 };
+        // sinks fed from a texture skip the render buffer this time; a failed texture falls back to it.
+        // Requests for sinks this event does not feed wait for a later one.
+        let (requests, later): (Vec<_>, Vec<_>) = std::mem::replace(&mut self.texture_requests, Vec::new()).into_iter()
+            .partition(|&(_, id)| target.map_or(true, |t| t == id));
+        self.texture_requests = later;
+        let mut texture_fed = Vec::new();
+        for (texture, id) in requests
         {
-            image: dst_image, subresourceRange: VkImageSubresourceRange
+            let image = self.uinstance.access_texture(
+                texture,
+                Some(&subresource),
+                VK_IMAGE_LAYOUT_TRANSFER_SRC_OPTIMAL,
+                VK_PIPELINE_STAGE_TRANSFER_BIT,
+                VK_ACCESS_TRANSFER_READ_BIT,
+                UnityVulkanResourceAccessMode::PipelineBarrier
+            ).ok_or(InterceptorError::TextureUnavailable).and_then(|image|
             {
-                aspectMask: VK_IMAGE_ASPECT_COLOR_BIT,
-                baseArrayLayer: 0, layerCount: 1,
-                baseMipLevel: 0, levelCount: 1
-            },
-            oldLayout: VK_IMAGE_LAYOUT_PRESENT_SRC_KHR, newLayout: VK_IMAGE_LAYOUT_TRANSFER_DST_OPTIMAL,
-            srcAccessMask: VK_ACCESS_MEMORY_READ_BIT, dstAccessMask: VK_ACCESS_TRANSFER_WRITE_BIT,
-            srcQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED, dstQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED,
-            .. Default::default()
-        };
-        let out_barrier_present_ready = VkImageMemoryBarrier
-        {
-            image: dst_image, subresourceRange: VkImageSubresourceRange
+                // depth and stencil cannot be blitted into color images
+                if image.aspect & VK_IMAGE_ASPECT_COLOR_BIT == 0 { return Err(InterceptorError::UnsupportedFormat(image.format)); }
+                self.single_sampled(image)
+            });
+            match image
             {
-                aspectMask: VK_IMAGE_ASPECT_COLOR_BIT,
-                baseArrayLayer: 0, layerCount: 1,
-                baseMipLevel: 0, levelCount: 1
-            },
-            oldLayout: VK_IMAGE_LAYOUT_TRANSFER_DST_OPTIMAL, newLayout: VK_IMAGE_LAYOUT_PRESENT_SRC_KHR,
-            srcAccessMask: VK_ACCESS_TRANSFER_WRITE_BIT, dstAccessMask: VK_ACCESS_MEMORY_READ_BIT,
-            srcQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED, dstQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED,
-            .. Default::default()
-        };
-        let region = VkImageBlit
+                Ok(image) => { self.sinks.dispatch_to(id, &image); texture_fed.push(id); },
+                Err(e) => error::set_last_error(&e)
+            }
+        }
+
+        if target.map_or(false, |t| texture_fed.contains(&t)) { return Ok(()); }
+        let mut rb_image = self.uinstance.access_render_buffer_texture(
+            rb,
+            Some(&subresource),
+            VK_IMAGE_LAYOUT_TRANSFER_SRC_OPTIMAL,
+            VK_PIPELINE_STAGE_TRANSFER_BIT,
+            VK_ACCESS_TRANSFER_READ_BIT,
+            UnityVulkanResourceAccessMode::PipelineBarrier
+        ).ok_or(InterceptorError::RenderBufferUnavailable)?;
+        // blits and copies cannot read multisampled images
+        if rb_image.samples != VK_SAMPLE_COUNT_1_BIT
         {
-            srcSubresource: VkImageSubresourceLayers
+            let resolved = self.uinstance.access_render_buffer_resolve_texture(
+                rb,
+                Some(&subresource),
+                VK_IMAGE_LAYOUT_TRANSFER_SRC_OPTIMAL,
+                VK_PIPELINE_STAGE_TRANSFER_BIT,
+                VK_ACCESS_TRANSFER_READ_BIT,
+                UnityVulkanResourceAccessMode::PipelineBarrier
+            ).filter(|r| r.samples == VK_SAMPLE_COUNT_1_BIT);
+            rb_image = match resolved
             {
-                aspectMask: VK_IMAGE_ASPECT_COLOR_BIT,
-                baseArrayLayer: 0,
-                layerCount: 1,
-                mipLevel: 0
-            },
-            dstSubresource: VkImageSubresourceLayers
-            {
-                aspectMask: VK_IMAGE_ASPECT_COLOR_BIT,
-                baseArrayLayer: 0,
-                layerCount: 1,
-                mipLevel: 0
-            },
-            srcOffsets: [
-                VkOffset3D { x: 0, y: 0, z: 0 },
-                VkOffset3D { x: rb_image.extent.width as _, y: rb_image.extent.height as _, z: 1 }
-            ],
-            dstOffsets: [
-                VkOffset3D { x: 0, y: 0, z: 0 },
-                VkOffset3D { x: self.ert.extent.width as _, y: self.ert.extent.height as _, z: 1 }
-            ]
-        };
+                Some(r) => r,
+                None => self.single_sampled(rb_image)?
+            };
+        }
 
-        (self.fp_cmd_pipeline_barrier)(self.cbuf, VK_PIPELINE_STAGE_BOTTOM_OF_PIPE_BIT, VK_PIPELINE_STAGE_TRANSFER_BIT, 0,
-            0, std::ptr::null(), 0, std::ptr::null(), 1, &in_barrier_transfer_ready);
-        (self.fp_cmd_blit_image)(self.cbuf, rb_image.image, rb_image.layout, dst_image, VK_IMAGE_LAYOUT_TRANSFER_DST_OPTIMAL,
-            1, &region, VK_FILTER_LINEAR);
-        (self.fp_cmd_pipeline_barrier)(self.cbuf, VK_PIPELINE_STAGE_TRANSFER_BIT, VK_PIPELINE_STAGE_TOP_OF_PIPE_BIT, 0,
-            0, std::ptr::null(), 0, std::ptr::null(), 1, &out_barrier_present_ready);
+        match target
+        {
+            Some(id) => { self.sinks.dispatch_to(id, &rb_image); },
+            None => self.sinks.dispatch_except(&rb_image, &texture_fed)
+        }
+        Ok(())
+    }
+    /// Feeds an image Unity is about to present on `queue` to the sinks, after `wait_semaphores`.
+    /// Returns the semaphore the presentation has to wait for instead, or None if the frame was not captured.
+    pub fn handle_present(&mut self, queue: VkQueue, wait_semaphores: &[VkSemaphore], frame: UnityVulkanImage)
+        -> Result<Option<VkSemaphore>, InterceptorError>
+    {
+        // a script issuing capture events has already chosen what to capture this frame
+        if std::mem::replace(&mut self.event_captured, false) { return Ok(None); }
+        // the sinks submit to the graphics queue
+        if queue != self.instance.graphics_queue { return Ok(None); }
+
+        if self.present_capture.is_none() { self.present_capture = Some(PresentCapture::new(&self.instance)?); }
+        let pc = self.present_capture.as_mut().unwrap();
+        let image = pc.begin(wait_semaphores, frame)?;
+        self.sinks.dispatch(&image);
+        match pc.end()
+        {
+            Ok(s) => Ok(Some(s)),
+            // Unity's semaphores have been waited for: the presentation needs one that is signaled anyway
+            Err(e) => { error::set_last_error(&e); pc.signal().map(Some) }
+        }
+    }
+    /// Resolves a multisampled image with the plugin's own resolver
+    fn single_sampled(&mut self, image: UnityVulkanImage) -> Result<UnityVulkanImage, InterceptorError>
+    {
+        if image.samples == VK_SAMPLE_COUNT_1_BIT { return Ok(image); }
 
-        (self.fp_end_command_record)(self.cbuf);
-        self.ert.submit_command(self.cbuf, bb_index);
+        if self.resolver.is_none() { self.resolver = Some(MsaaResolver::new(&self.instance)?); }
+        self.resolver.as_mut().unwrap().resolve(&image)
     }
 }
 unsafe impl Sync for VkRenderingInterceptor {}
 unsafe impl Send for VkRenderingInterceptor {}
 
+type SharedReadbackCallback = Arc<Mutex<ReadbackCallback>>;
+/// A callback for a readback sink, calling the shared one
+fn forward_readback(callback: &SharedReadbackCallback) -> ReadbackCallback
+{
+    let callback = callback.clone();
+    Box::new(move |f| (*callback.lock().unwrap_or_else(|e| e.into_inner()))(f))
+}
+
+/// What is left of an interceptor while Unity resets the device (see `VkRenderingInterceptor::release`)
+pub struct ReleasedInterceptor
+{
+    sinks: SinkRegistry,
+    readback: Option<(SinkId, SharedReadbackCallback)>
+}
+unsafe impl Send for ReleasedInterceptor {}
+/// Stands in for a sink whose objects have been destroyed, until the device is back
+struct ReleasedSink;
+impl FrameSink for ReleasedSink
+{
+    fn name(&self) -> &str { "released" }
+    fn process(&mut self, _frame: &UnityVulkanImage) -> SinkResult { Ok(()) }
+    fn set_transform(&mut self, _transform: Transform) -> bool { true }
+}
+
 #[no_mangle]
 pub extern "system" fn rendering_event_ptr() -> UnityRenderingEvent { rendering_event }
+/// The callback for `GL.IssuePluginEventAndData`, taking a `event::CaptureEventData` with each capture event
+#[no_mangle]
+pub extern "system" fn rendering_event_and_data_ptr() -> UnityRenderingEventAndData { rendering_event_and_data }
+/// The event id to issue for a kind of event (see `event::EVENT_*`). The ids are reserved from Unity at load.
+/// Returns -1 if the kind is unknown or the plugin is not loaded.
+#[no_mangle]
+pub extern "system" fn plugin_event_id(kind: c_int) -> c_int { event::event_id(kind).unwrap_or(-1) }
 extern "system" fn rendering_event(event_id: c_int)
 {
-    if event_id == SCREEN_CAPTURE_EVENT_ID
+    ffi_guard((), ||
     {
-        let mut rh = GRAPHICS_DEVICE.write().unwrap();
-        if let Some(ref mut gd) = *rh { gd.handle_event(); }
-    }
+        let kind = match event::event_kind(event_id) { Some(k) => k, None => return Ok(()) };
+
+        match *graphics_device()
+        {
+            Some(ref mut gd) => gd.handle_plugin_event(kind, None),
+            None => Ok(())
+        }
+    })
+}
+extern "system" fn rendering_event_and_data(event_id: c_int, data: *mut c_void)
+{
+    ffi_guard((), ||
+    {
+        let kind = match event::event_kind(event_id) { Some(k) => k, None => return Ok(()) };
+        let data = if data.is_null() { None } else { Some(unsafe { *(data as *const CaptureEventData) }) };
+        if data.is_none() && (kind == event::EVENT_CAPTURE || kind == event::EVENT_SCREENSHOT)
+        {
+            return Err(InterceptorError::InvalidEventData("no data"));
+        }
+
+        match *graphics_device()
+        {
+            Some(ref mut gd) => gd.handle_plugin_event(kind, data.as_ref()),
+            None => Ok(())
+        }
+    })
+}
+
+/// Called by the vkQueuePresentKHR hook (see `present`)
+fn capture_presented(queue: VkQueue, wait_semaphores: &[VkSemaphore], frame: UnityVulkanImage) -> Option<VkSemaphore>
+{
+    ffi_guard(None, ||
+    {
+        match *graphics_device()
+        {
+            Some(ref mut gd) => gd.handle_present(queue, wait_semaphores, frame),
+            None => Ok(None)
+        }
+    })
 }
 
 #[no_mangle]
 pub extern "system" fn set_render_buffer(rb: UnityRenderBuffer)
 {
-    let mut wh = GRAPHICS_DEVICE.write().unwrap();
-    if let Some(ref mut gd) = *wh { gd.set_render_buffer(rb); }
+    ffi_guard((), ||
+    {
+        if let Some(ref mut gd) = *graphics_device() { gd.set_render_buffer(rb); }
+        Ok(())
+    })
 }
 
-lazy_static!{
-    static ref GRAPHICS_DEVICE: RwLock<Option<VkRenderingInterceptor>> = RwLock::new(None);
+/// Captures the next intercepted frame into a PNG file at `path` (UTF-8, nul-terminated).
+/// Returns false if the path is invalid or another screenshot is still in progress.
+#[no_mangle]
+pub extern "system" fn request_screenshot(path: *const c_char) -> bool
+{
+    ffi_guard(false, ||
+    {
+        if path.is_null() { return Ok(false); }
+        let path = match unsafe { std::ffi::CStr::from_ptr(path) }.to_str()
+        {
+            Ok(p) => std::path::PathBuf::from(p),
+            Err(_) => return Ok(false)
+        };
+
+        Ok(screenshot::request(path))
+    })
 }
-extern "system" fn gfx_event_handler(event_type: UnityGfxDeviceEventType)
+/// Polls the progress of the last requested screenshot (see `screenshot::SCREENSHOT_STATUS_*`)
+#[no_mangle]
+pub extern "system" fn screenshot_status() -> c_int
 {
-    trace!("Interceptor Event: {}", event_type);
+    ffi_guard(screenshot::SCREENSHOT_STATUS_FAILED, || Ok(screenshot::status()))
+}
 
-    if event_type == kUnityGfxDeviceEventInitialize
+/// Feeds a texture (`Texture.GetNativeTexturePtr()`) to the sink `id` instead of the render buffer,
+/// on the next capture event only. Call it every frame to keep capturing the texture.
+/// Returns false if the texture is null, the graphics device is not initialized or no sink has the id.
+#[no_mangle]
+pub extern "system" fn capture_texture(texture: *mut c_void, id: SinkId) -> bool
+{
+    ffi_guard(false, ||
+    {
+        if texture.is_null() { return Ok(false); }
+        match *graphics_device()
+        {
+            Some(ref mut gd) => Ok(gd.request_texture_capture(texture, id)),
+            None => Ok(false)
+        }
+    })
+}
+/// Turns a sink on or off (see `WINDOW_SINK_ID`, `SCREENSHOT_SINK_ID`).
+/// Returns false if the graphics device is not initialized or no sink has the id.
+#[no_mangle]
+pub extern "system" fn set_sink_enabled(id: SinkId, enabled: bool) -> bool
+{
+    ffi_guard(false, ||
     {
-        // init here
-        let rt = GFX_IF.with(|o| unsafe { ((*o.get()).get_renderer)() });
-        if rt != kUnityGfxRendererVulkan
+        match *graphics_device()
         {
-            // Renderer Type is not supported! ignoring
-            return;
+            Some(ref mut gd) => Ok(gd.sinks_mut().set_enabled(id, enabled)),
+            None => Ok(false)
         }
+    })
+}
+/// Flips and/or rotates the frames of a sink (see `transform::TRANSFORM_*`); 0 restores the original orientation.
+/// Returns false if the flags are unknown, the graphics device is not initialized or the sink does not support transforms.
+#[no_mangle]
+pub extern "system" fn set_sink_transform(id: SinkId, flags: transform::TransformFlags) -> bool
+{
+    ffi_guard(false, ||
+    {
+        let transform = match Transform::from_flags(flags)
+        {
+            Some(t) => t,
+            None => return Ok(false)
+        };
+        match *graphics_device()
+        {
+            Some(ref mut gd) => Ok(gd.sinks_mut().set_transform(id, transform)),
+            None => Ok(false)
+        }
+    })
+}
+/// Sets how many mirror frames may be queued on the GPU before the render thread waits (1 to `settings::MAX_FRAMES_IN_FLIGHT`).
+/// Takes effect on the next frame. Returns false if the count is out of range.
+#[no_mangle]
+pub extern "system" fn set_frames_in_flight(count: u32) -> bool
+{
+    ffi_guard(false, ||
+    {
+        if count == 0 || count > settings::MAX_FRAMES_IN_FLIGHT { return Ok(false); }
+        settings::update(|s| s.frames_in_flight = count);
+        Ok(true)
+    })
+}
+/// Selects how frames are placed in the mirror window (see `scaling::SCALING_MODE_*`).
+/// Takes effect on the next frame. Returns false if the mode is unknown.
+#[no_mangle]
+pub extern "system" fn set_scaling_mode(mode: scaling::ScalingMode) -> bool
+{
+    ffi_guard(false, ||
+    {
+        if !scaling::is_valid_mode(mode) { return Ok(false); }
+        settings::update(|s| s.scaling_mode = mode);
+        Ok(true)
+    })
+}
+/// Restricts every output to the `width` x `height` area at (`x`, `y`) of the frame, clipped to the frame size.
+/// A zero-sized region captures the whole frame again.
+/// Returns false if the offset is negative or only one side of the region is zero.
+#[no_mangle]
+pub extern "system" fn set_capture_region(x: c_int, y: c_int, width: c_int, height: c_int) -> bool
+{
+    ffi_guard(false, ||
+    {
+        if width == 0 && height == 0
+        {
+            settings::update(|s| s.capture_region = None);
+            return Ok(true);
+        }
+        if x < 0 || y < 0 || width <= 0 || height <= 0 { return Ok(false); }
 
-        *GRAPHICS_DEVICE.write().unwrap() = Some(INTERFACES.with(|v| VkRenderingInterceptor::new(v.get())));
-    }
-    else if event_type == kUnityGfxDeviceEventShutdown
+        settings::update(|s| s.capture_region = Some(Rect::new(x, y, width as _, height as _)));
+        Ok(true)
+    })
+}
+/// Registers an additional output for intercepted frames. It is kept as it is across device resets.
+/// Returns None if the graphics device has not been initialized yet.
+pub fn add_sink(sink: Box<dyn FrameSink>) -> Option<SinkId>
+{
+    graphics_device().as_mut().map(|gd| gd.sinks_mut().add(sink))
+}
+/// Unregisters an output added by `add_sink`
+pub fn remove_sink(id: SinkId) -> Option<Box<dyn FrameSink>>
+{
+    graphics_device().as_mut().and_then(|gd| gd.sinks_mut().remove(id))
+}
+
+/// Routes intercepted frames to `callback` on the render thread (None to stop).
+/// Returns false if the graphics device has not been initialized yet or the readback could not be set up.
+pub fn set_readback_callback(callback: Option<ReadbackCallback>) -> bool
+{
+    match *graphics_device()
     {
-        // fini here
-        *GRAPHICS_DEVICE.write().unwrap() = None;
+        Some(ref mut gd) => match gd.set_readback_callback(callback)
+        {
+            Ok(()) => true,
+            Err(e) => { error::set_last_error(&e); false }
+        },
+        None => false
     }
 }
+/// Sink id of the readback enabled by `set_readback_callback`, e.g. for `set_sink_transform`
+pub fn readback_sink_id() -> Option<SinkId>
+{
+    graphics_device().as_ref().and_then(|gd| gd.readback_sink_id())
+}
+
+lazy_static!{
+    static ref GRAPHICS_DEVICE: RwLock<Option<VkRenderingInterceptor>> = RwLock::new(None);
+    /// kept from BeforeReset to AfterReset
+    static ref RELEASED_DEVICE: Mutex<Option<ReleasedInterceptor>> = Mutex::new(None);
+}
+/// The interceptor locked by this thread. Meanwhile the presentation hooks pass the calls of the thread straight
+/// through, so that a sink presenting or recreating its swapchain does not reenter the lock.
+struct LockedDevice(RwLockWriteGuard<'static, Option<VkRenderingInterceptor>>, present::Bypass);
+impl std::ops::Deref for LockedDevice
+{
+    type Target = Option<VkRenderingInterceptor>;
+    fn deref(&self) -> &Self::Target { &*self.0 }
+}
+impl std::ops::DerefMut for LockedDevice
+{
+    fn deref_mut(&mut self) -> &mut Self::Target { &mut *self.0 }
+}
+/// Locks the interceptor. A panic caught while it was locked does not make it unusable.
+fn graphics_device() -> LockedDevice
+{
+    let bypass = present::Bypass::enter();
+    LockedDevice(GRAPHICS_DEVICE.write().unwrap_or_else(|e| e.into_inner()), bypass)
+}
+fn released_device() -> MutexGuard<'static, Option<ReleasedInterceptor>>
+{
+    RELEASED_DEVICE.lock().unwrap_or_else(|e| e.into_inner())
+}
+extern "system" fn gfx_event_handler(event_type: UnityGfxDeviceEventType)
+{
+    trace!("Interceptor Event: {:?}", event_type);
+
+    ffi_guard((), ||
+    {
+        // events may come from any thread, and none after unload
+        let context = match plugin_context() { Some(c) => c, None => return Ok(()) };
+        if event_type == UnityGfxDeviceEventType::Initialize
+        {
+            // init here
+            let rt = unsafe { ((*context.graphics).get_renderer)() };
+            if rt != UnityGfxRenderer::Vulkan
+            {
+                // Renderer Type is not supported! ignoring
+                return Ok(());
+            }
+
+            // stays uninitialized (capturing nothing) if any part fails
+            *released_device() = None;
+            let mut gd = graphics_device();
+            *gd = None;
+            *gd = Some(VkRenderingInterceptor::new(context.interfaces)?);
+        }
+        else if event_type == UnityGfxDeviceEventType::Shutdown
+        {
+            // fini here
+            *graphics_device() = None;
+            *released_device() = None;
+        }
+        else if event_type == UnityGfxDeviceEventType::BeforeReset
+        {
+            // Unity's device and queue go away; what the user configured stays for AfterReset
+            let released = graphics_device().take().map(VkRenderingInterceptor::release);
+            *released_device() = released;
+        }
+        else if event_type == UnityGfxDeviceEventType::AfterReset
+        {
+            let released = released_device().take();
+            if let Some(r) = released
+            {
+                let mut gd = graphics_device();
+                *gd = None;
+                *gd = Some(VkRenderingInterceptor::rebuild(context.interfaces, r)?);
+            }
+        }
 
-thread_local!{
-    static INTERFACES: Cell<*mut IUnityInterfaces> = Cell::new(null_mut());
-    static GFX_IF: Cell<*mut IUnityGraphics> = Cell::new(null_mut());
+        Ok(())
+    })
 }
+
+/// Set at plugin load if Unity agreed to render offscreen (see `window::OFFSCREEN_ENV_NAME`)
+static UNITY_OFFSCREEN: AtomicBool = AtomicBool::new(false);
+/// Whether Unity renders offscreen, leaving the window sink as the only presentation of its frames
 #[no_mangle]
-pub extern "system" fn UnityPluginLoad(ifs: *mut IUnityInterfaces)
+pub extern "system" fn unity_renders_offscreen() -> bool { UNITY_OFFSCREEN.load(Ordering::SeqCst) }
+
+/// The interfaces Unity passed to `UnityPluginLoad`. Unity loads the plugin on the main thread but sends device
+/// and rendering events on the render thread, so they are shared by the whole process until the plugin is unloaded.
+#[derive(Clone, Copy)]
+struct PluginContext
+{
+    interfaces: *mut IUnityInterfaces,
+    graphics: *mut IUnityGraphics
+}
+unsafe impl Sync for PluginContext {}
+unsafe impl Send for PluginContext {}
+lazy_static!{
+    static ref PLUGIN_CONTEXT: RwLock<Option<PluginContext>> = RwLock::new(None);
+}
+fn plugin_context() -> Option<PluginContext> { *PLUGIN_CONTEXT.read().unwrap_or_else(|e| e.into_inner()) }
+fn set_plugin_context(context: Option<PluginContext>) { *PLUGIN_CONTEXT.write().unwrap_or_else(|e| e.into_inner()) = context; }
+
+unity_native_plugin::unity_plugin!{ load: plugin_load, unload: plugin_unload }
+
+/// The event ids used before they were reserved from Unity
+const FALLBACK_EVENT_ID_BASE: c_int = 1;
+
+fn plugin_load(ifs: &IUnityInterfaces)
 {
     // flexi_logger::Logger::with_str("trace").log_to_file().start().expect("Logger initialization failed");
     info!("Initializing Plugin...");
 
-    INTERFACES.with(|v| v.set(ifs));
-    let gfx_if = unsafe { ((*ifs).get_interface)(IUnityGraphics::GUID) as *mut IUnityGraphics };
-    GFX_IF.with(|v| v.set(gfx_if));
-    unsafe { ((*gfx_if).register_device_event_callback)(gfx_event_handler); }
-    
-    // Manual Initialization
-    // ref: https://docs.unity3d.com/Manual/NativePluginInterface.html
-    gfx_event_handler(kUnityGfxDeviceEventInitialize);
+    ffi_guard((), ||
+    {
+        let gfx_if = (ifs.get_interface)(IUnityGraphics::GUID) as *mut IUnityGraphics;
+        if gfx_if.is_null() { return Err(InterceptorError::MissingUnityInterface("IUnityGraphics")); }
+        set_plugin_context(Some(PluginContext { interfaces: ifs as *const _ as *mut _, graphics: gfx_if }));
+        // ids of its own, not to be mistaken for the events of other plugins
+        let event_id_base = unsafe { ((*gfx_if).reserve_event_id_range)(event::EVENT_KIND_COUNT) };
+        if event_id_base < 0
+        {
+            warn!("Interceptor: no event ids could be reserved, falling back to {}", FALLBACK_EVENT_ID_BASE);
+            event::set_event_id_base(FALLBACK_EVENT_ID_BASE);
+        }
+        else { event::set_event_id_base(event_id_base); }
+        unsafe { ((*gfx_if).register_device_event_callback)(gfx_event_handler); }
+        // Unity creates its instance, device and swapchain after loading the plugins; not available with other renderers
+        UNITY_OFFSCREEN.store(false, Ordering::SeqCst);
+        if let Some(vulkan) = UnityGraphicsVulkanRef::from_interfaces(ifs as *const _ as *mut _)
+        {
+            extensions::install(vulkan);
+            if window::offscreen_requested()
+            {
+                let offscreen = vulkan.configure_swapchain(&UnityVulkanSwapchainConfiguration { mode: UnityVulkanSwapchainMode::Offscreen });
+                if offscreen { info!("Interceptor: Unity renders offscreen, presenting through the plugin's window"); }
+                else { warn!("Interceptor: Unity does not render offscreen; its window is mirrored instead"); }
+                UNITY_OFFSCREEN.store(offscreen, Ordering::SeqCst);
+            }
+            // offscreen, Unity has no swapchain to present
+            if !unity_renders_offscreen() { present::install(vulkan); }
+        }
+        
+        // Manual Initialization
+        // ref: https://docs.unity3d.com/Manual/NativePluginInterface.html
+        gfx_event_handler(UnityGfxDeviceEventType::Initialize);
+        Ok(())
+    })
 }
-#[no_mangle]
-pub extern "system" fn UnityPluginUnload()
+fn plugin_unload()
 {
     info!("Uninitializing Plugin...");
-    GFX_IF.with(|o| unsafe { ((*o.get()).unregister_device_event_callback)(gfx_event_handler) });
+    ffi_guard((), ||
+    {
+        if let Some(context) = plugin_context()
+        {
+            unsafe { ((*context.graphics).unregister_device_event_callback)(gfx_event_handler); }
+        }
+        set_plugin_context(None);
+        event::set_event_id_base(-1);
+        present::uninstall();
+        Ok(())
+    })
 }
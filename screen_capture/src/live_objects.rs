@@ -0,0 +1,37 @@
+//! Accounting of Live Objects (debug builds)
+//!
+//! Every Vulkan object and window the plugin creates is counted by kind until it is destroyed,
+//! so that tests can check that a shutdown leaves nothing behind. Release builds count nothing.
+
+use lazy_static::*;
+use libc::*;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+
+lazy_static!{
+    static ref LIVE: Mutex<BTreeMap<&'static str, isize>> = Mutex::new(BTreeMap::new());
+}
+fn counts() -> MutexGuard<'static, BTreeMap<&'static str, isize>> { LIVE.lock().unwrap_or_else(|e| e.into_inner()) }
+
+/// Counts an object of `kind` (`"fence"`, `"window"`, ...) that has been created
+pub fn created(kind: &'static str)
+{
+    if cfg!(debug_assertions) { *counts().entry(kind).or_insert(0) += 1; }
+}
+/// Counts an object of `kind` that has been destroyed
+pub fn destroyed(kind: &'static str)
+{
+    if cfg!(debug_assertions) { *counts().entry(kind).or_insert(0) -= 1; }
+}
+/// The kinds that have objects alive, with their numbers. A negative number means objects destroyed twice.
+pub fn live() -> Vec<(&'static str, isize)>
+{
+    counts().iter().filter(|&(_, &n)| n != 0).map(|(&k, &n)| (k, n)).collect()
+}
+
+/// Number of objects the plugin has created and not destroyed yet. Always 0 in release builds.
+#[no_mangle]
+pub extern "system" fn live_object_count() -> c_int
+{
+    counts().values().sum::<isize>() as _
+}
@@ -0,0 +1,491 @@
+//! Capture of the Frames Unity Presents
+//!
+//! Unity's own vkCreateSwapchainKHR, vkDestroySwapchainKHR and vkQueuePresentKHR are intercepted so that the
+//! backbuffer is captured right before it is presented, without a script issuing capture events.
+
+use bedrock::vk::*;
+use lazy_static::*;
+use log::*;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard};
+use crate::unity::*;
+use crate::error::{self, InterceptorError, vk_check};
+use crate::live_objects;
+
+/// The functions Unity called before they were intercepted
+struct Hooks
+{
+    vulkan: UnityGraphicsVulkanRef,
+    installed: bool,
+    create_swapchain: PFN_vkCreateSwapchainKHR,
+    destroy_swapchain: PFN_vkDestroySwapchainKHR,
+    queue_present: PFN_vkQueuePresentKHR
+}
+unsafe impl Sync for Hooks {}
+unsafe impl Send for Hooks {}
+
+/// What the presented images of a swapchain Unity created look like
+struct SwapchainInfo
+{
+    format: VkFormat,
+    extent: VkExtent2D,
+    usage: VkImageUsageFlags,
+    images: Vec<VkImage>
+}
+
+lazy_static!{
+    /// kept after uninstallation, in case Unity still calls a hook it had already looked up
+    static ref HOOKS: RwLock<Option<Hooks>> = RwLock::new(None);
+    static ref SWAPCHAINS: Mutex<HashMap<usize, SwapchainInfo>> = Mutex::new(HashMap::new());
+}
+thread_local!{
+    /// set while this thread holds the interceptor, which is not reentrant
+    static BYPASSING: Cell<bool> = Cell::new(false);
+}
+fn hooks() -> RwLockReadGuard<'static, Option<Hooks>> { HOOKS.read().unwrap_or_else(|e| e.into_inner()) }
+fn swapchains() -> MutexGuard<'static, HashMap<usize, SwapchainInfo>> { SWAPCHAINS.lock().unwrap_or_else(|e| e.into_inner()) }
+
+/// Makes the hooks pass the calls of this thread straight through until dropped. Has to be held while the interceptor is:
+/// the sinks present and recreate swapchains, and Unity may resolve those commands to the hooks.
+pub struct Bypass(bool);
+impl Bypass
+{
+    pub fn enter() -> Self { Bypass(BYPASSING.with(|c| c.replace(true))) }
+}
+impl Drop for Bypass
+{
+    fn drop(&mut self) { BYPASSING.with(|c| c.set(self.0)); }
+}
+fn bypassing() -> bool { BYPASSING.with(|c| c.get()) }
+
+/// The functions behind the hooks, for the plugin's own swapchain, which is not one of Unity's to capture
+pub fn original_queue_present() -> Option<PFN_vkQueuePresentKHR> { hooks().as_ref().map(|h| h.queue_present) }
+pub fn original_create_swapchain() -> Option<PFN_vkCreateSwapchainKHR> { hooks().as_ref().map(|h| h.create_swapchain) }
+pub fn original_destroy_swapchain() -> Option<PFN_vkDestroySwapchainKHR> { hooks().as_ref().map(|h| h.destroy_swapchain) }
+
+const CREATE_SWAPCHAIN: &[u8] = b"vkCreateSwapchainKHR\0";
+const DESTROY_SWAPCHAIN: &[u8] = b"vkDestroySwapchainKHR\0";
+const QUEUE_PRESENT: &[u8] = b"vkQueuePresentKHR\0";
+fn entry_name(name: &'static [u8]) -> &'static CStr { CStr::from_bytes_with_nul(name).unwrap() }
+
+/// Intercepts the swapchain functions of Unity. Has to be called before Unity creates its swapchain,
+/// i.e. from `UnityPluginLoad`. Returns false if Unity does not let them be intercepted.
+pub fn install(vulkan: UnityGraphicsVulkanRef) -> bool
+{
+    let mut state = HOOKS.write().unwrap_or_else(|e| e.into_inner());
+    if state.as_ref().map_or(false, |h| h.installed) { return true; }
+
+    let intercept = |name: &'static [u8], f: PFN_vkVoidFunction| vulkan.intercept_vulkan_api(entry_name(name), f);
+    let queue_present = match intercept(QUEUE_PRESENT, unsafe { std::mem::transmute::<PFN_vkQueuePresentKHR, _>(hook_queue_present) })
+    {
+        Some(f) => f,
+        None => { warn!("Interceptor: vkQueuePresentKHR cannot be intercepted; frames are captured by events only"); return false; }
+    };
+    let create_swapchain = intercept(CREATE_SWAPCHAIN, unsafe { std::mem::transmute::<PFN_vkCreateSwapchainKHR, _>(hook_create_swapchain) });
+    let destroy_swapchain = intercept(DESTROY_SWAPCHAIN, unsafe { std::mem::transmute::<PFN_vkDestroySwapchainKHR, _>(hook_destroy_swapchain) });
+    match (create_swapchain, destroy_swapchain)
+    {
+        (Some(create_swapchain), Some(destroy_swapchain)) =>
+        {
+            *state = Some(Hooks
+            {
+                vulkan, installed: true,
+                create_swapchain: unsafe { std::mem::transmute::<PFN_vkVoidFunction, _>(create_swapchain) },
+                destroy_swapchain: unsafe { std::mem::transmute::<PFN_vkVoidFunction, _>(destroy_swapchain) },
+                queue_present: unsafe { std::mem::transmute::<PFN_vkVoidFunction, _>(queue_present) }
+            });
+            true
+        },
+        (create_swapchain, destroy_swapchain) =>
+        {
+            // presented images cannot be described without the parameters of their swapchain
+            warn!("Interceptor: swapchain creation cannot be intercepted; frames are captured by events only");
+            intercept(QUEUE_PRESENT, queue_present);
+            if let Some(f) = create_swapchain { intercept(CREATE_SWAPCHAIN, f); }
+            if let Some(f) = destroy_swapchain { intercept(DESTROY_SWAPCHAIN, f); }
+            false
+        }
+    }
+}
+/// Gives Unity back the functions it used before `install`
+pub fn uninstall()
+{
+    let mut state = HOOKS.write().unwrap_or_else(|e| e.into_inner());
+    if let Some(ref mut h) = *state
+    {
+        if !h.installed { return; }
+        unsafe
+        {
+            h.vulkan.intercept_vulkan_api(entry_name(QUEUE_PRESENT), std::mem::transmute::<PFN_vkQueuePresentKHR, _>(h.queue_present));
+            h.vulkan.intercept_vulkan_api(entry_name(CREATE_SWAPCHAIN), std::mem::transmute::<PFN_vkCreateSwapchainKHR, _>(h.create_swapchain));
+            h.vulkan.intercept_vulkan_api(entry_name(DESTROY_SWAPCHAIN), std::mem::transmute::<PFN_vkDestroySwapchainKHR, _>(h.destroy_swapchain));
+        }
+        h.installed = false;
+    }
+    swapchains().clear();
+}
+
+/// Whether swapchain images of `surface` can be made readable by transfer operations
+fn transfer_source_supported(instance: &UnityVulkanInstance, surface: VkSurfaceKHR) -> bool
+{
+    if instance.instance.is_null() { return false; }
+    let fp_get_surface_capabilities: Option<PFN_vkGetPhysicalDeviceSurfaceCapabilitiesKHR> =
+        load_instance_proc!(instance, "vkGetPhysicalDeviceSurfaceCapabilitiesKHR").ok();
+    let fp_get_surface_capabilities = match fp_get_surface_capabilities { Some(f) => f, None => return false };
+
+    let mut caps = std::mem::MaybeUninit::uninit();
+    if fp_get_surface_capabilities(instance.physical_device, surface, caps.as_mut_ptr()) != VK_SUCCESS { return false; }
+    let caps: VkSurfaceCapabilitiesKHR = unsafe { caps.assume_init() };
+    (caps.supportedUsageFlags & VK_IMAGE_USAGE_TRANSFER_SRC_BIT) != 0
+}
+fn swapchain_images(instance: &UnityVulkanInstance, device: VkDevice, swapchain: VkSwapchainKHR) -> Vec<VkImage>
+{
+    let fp_get_swapchain_images: Option<PFN_vkGetSwapchainImagesKHR> = load_instance_proc!(instance, "vkGetSwapchainImagesKHR").ok();
+    let fp_get_swapchain_images = match fp_get_swapchain_images { Some(f) => f, None => return Vec::new() };
+
+    let mut count = 0;
+    fp_get_swapchain_images(device, swapchain, &mut count, std::ptr::null_mut());
+    let mut images = Vec::with_capacity(count as _);
+    unsafe { images.set_len(count as _); }
+    if fp_get_swapchain_images(device, swapchain, &mut count, images.as_mut_ptr()) != VK_SUCCESS { return Vec::new(); }
+    images
+}
+
+extern "system" fn hook_create_swapchain(device: VkDevice, create_info: *const VkSwapchainCreateInfoKHR,
+    allocator: *const VkAllocationCallbacks, swapchain: *mut VkSwapchainKHR) -> VkResult
+{
+    let (original, vulkan) = match *hooks()
+    {
+        Some(ref h) => (h.create_swapchain, h.vulkan),
+        None => return VK_ERROR_INITIALIZATION_FAILED
+    };
+    if bypassing() { return original(device, create_info, allocator, swapchain); }
+    let instance = vulkan.instance();
+    let mut info = unsafe { std::ptr::read(create_info) };
+    // the sinks copy from the presented images
+    if transfer_source_supported(&instance, info.surface) { info.imageUsage |= VK_IMAGE_USAGE_TRANSFER_SRC_BIT; }
+
+    let r = original(device, &info, allocator, swapchain);
+    if r == VK_SUCCESS
+    {
+        let swapchain = unsafe { *swapchain };
+        swapchains().insert(swapchain as usize, SwapchainInfo
+        {
+            format: info.imageFormat,
+            extent: info.imageExtent.clone(),
+            usage: info.imageUsage,
+            images: swapchain_images(&instance, device, swapchain)
+        });
+    }
+    r
+}
+extern "system" fn hook_destroy_swapchain(device: VkDevice, swapchain: VkSwapchainKHR, allocator: *const VkAllocationCallbacks)
+{
+    let original = match *hooks()
+    {
+        Some(ref h) => h.destroy_swapchain,
+        None => return
+    };
+    swapchains().remove(&(swapchain as usize));
+    original(device, swapchain, allocator);
+}
+extern "system" fn hook_queue_present(queue: VkQueue, present_info: *const VkPresentInfoKHR) -> VkResult
+{
+    let original = match *hooks()
+    {
+        Some(ref h) => h.queue_present,
+        None => return VK_ERROR_INITIALIZATION_FAILED
+    };
+    if bypassing() { return original(queue, present_info); }
+
+    let p = unsafe { &*present_info };
+    let wait_semaphores = if p.waitSemaphoreCount == 0 { &[][..] }
+        else { unsafe { std::slice::from_raw_parts(p.pWaitSemaphores, p.waitSemaphoreCount as _) } };
+    let signal = presented_image(p).and_then(|frame| crate::capture_presented(queue, wait_semaphores, frame));
+
+    match signal
+    {
+        // Unity's semaphores have been waited for by the capture
+        Some(s) => original(queue, &VkPresentInfoKHR
+        {
+            waitSemaphoreCount: 1,
+            pWaitSemaphores: &s,
+            .. unsafe { std::ptr::read(present_info) }
+        }),
+        None => original(queue, present_info)
+    }
+}
+/// The first presented image that can be captured, in PRESENT_SRC_KHR layout
+fn presented_image(p: &VkPresentInfoKHR) -> Option<UnityVulkanImage>
+{
+    let swapchains = swapchains();
+    (0 .. p.swapchainCount as usize).find_map(|i|
+    {
+        let (swapchain, index) = unsafe { (*p.pSwapchains.add(i), *p.pImageIndices.add(i)) };
+        let info = swapchains.get(&(swapchain as usize)).filter(|s| (s.usage & VK_IMAGE_USAGE_TRANSFER_SRC_BIT) != 0)?;
+        let image = *info.images.get(index as usize)?;
+
+        Some(UnityVulkanImage
+        {
+            memory: UnityVulkanMemory
+            {
+                memory: std::ptr::null_mut(), offset: 0, size: 0, mapped: std::ptr::null_mut(),
+                flags: 0, memory_type_index: 0, _resv: [std::ptr::null_mut(); 4]
+            },
+            image,
+            layout: VK_IMAGE_LAYOUT_PRESENT_SRC_KHR,
+            aspect: VK_IMAGE_ASPECT_COLOR_BIT,
+            usage: info.usage,
+            format: info.format,
+            extent: VkExtent3D { width: info.extent.width, height: info.extent.height, depth: 1 },
+            tiling: VK_IMAGE_TILING_OPTIMAL,
+            type_: VK_IMAGE_TYPE_2D,
+            samples: VK_SAMPLE_COUNT_1_BIT,
+            layers: 1,
+            mip_count: 1,
+            _resv: [std::ptr::null_mut(); 4]
+        })
+    })
+}
+
+/// Presentations that may be in flight before the oldest one is waited for
+const PRESENT_SLOTS: usize = 3;
+
+struct PresentSlot
+{
+    cmd_pool: VkCommandPool,
+    /// makes the image readable, then gives it back to the presentation
+    /// (again from the last one if the second could not be submitted)
+    cbufs: [VkCommandBuffer; 3],
+    /// waited for by the presentation instead of Unity's semaphores
+    present_order: VkSemaphore,
+    fence: VkFence,
+    issued: bool
+}
+
+/// Brackets the sinks' work on a presented image with layout transitions, submitted on the graphics queue:
+/// the first waits for Unity's rendering, the last signals the semaphore the presentation waits for.
+pub struct PresentCapture
+{
+    device: VkDevice,
+    queue: VkQueue,
+    slots: Vec<PresentSlot>,
+    current: usize,
+    /// the image of the capture in progress
+    image: VkImage,
+    fp_destroy_command_pool: PFN_vkDestroyCommandPool,
+    fp_reset_command_pool: PFN_vkResetCommandPool,
+    fp_begin_command_record: PFN_vkBeginCommandBuffer,
+    fp_end_command_record: PFN_vkEndCommandBuffer,
+    fp_cmd_pipeline_barrier: PFN_vkCmdPipelineBarrier,
+    fp_submit_commands: PFN_vkQueueSubmit,
+    fp_wait_fences: PFN_vkWaitForFences,
+    fp_reset_fences: PFN_vkResetFences,
+    fp_destroy_fence: PFN_vkDestroyFence,
+    fp_destroy_semaphore: PFN_vkDestroySemaphore,
+    fp_device_wait_idle: PFN_vkDeviceWaitIdle
+}
+impl PresentCapture
+{
+    pub fn new(instance: &UnityVulkanInstance) -> Result<Self, InterceptorError>
+    {
+        trace!("Interceptor: PresentCapture::new");
+
+        let fp_create_command_pool: PFN_vkCreateCommandPool = load_instance_proc!(instance, "vkCreateCommandPool")?;
+        let fp_alloc_command_buffer: PFN_vkAllocateCommandBuffers = load_instance_proc!(instance, "vkAllocateCommandBuffers")?;
+        let fp_create_semaphore: PFN_vkCreateSemaphore = load_instance_proc!(instance, "vkCreateSemaphore")?;
+        let fp_create_fence: PFN_vkCreateFence = load_instance_proc!(instance, "vkCreateFence")?;
+
+        let mut capture = PresentCapture
+        {
+            device: instance.device,
+            queue: instance.graphics_queue,
+            slots: Vec::with_capacity(PRESENT_SLOTS), current: 0,
+            image: std::ptr::null_mut(),
+            fp_destroy_command_pool: load_instance_proc!(instance, "vkDestroyCommandPool")?,
+            fp_reset_command_pool: load_instance_proc!(instance, "vkResetCommandPool")?,
+            fp_begin_command_record: load_instance_proc!(instance, "vkBeginCommandBuffer")?,
+            fp_end_command_record: load_instance_proc!(instance, "vkEndCommandBuffer")?,
+            fp_cmd_pipeline_barrier: load_instance_proc!(instance, "vkCmdPipelineBarrier")?,
+            fp_submit_commands: load_instance_proc!(instance, "vkQueueSubmit")?,
+            fp_wait_fences: load_instance_proc!(instance, "vkWaitForFences")?,
+            fp_reset_fences: load_instance_proc!(instance, "vkResetFences")?,
+            fp_destroy_fence: load_instance_proc!(instance, "vkDestroyFence")?,
+            fp_destroy_semaphore: load_instance_proc!(instance, "vkDestroySemaphore")?,
+            fp_device_wait_idle: load_instance_proc!(instance, "vkDeviceWaitIdle")?
+        };
+
+        // on failure, dropping the capture destroys the slots created so far
+        for _ in 0 .. PRESENT_SLOTS
+        {
+            capture.slots.push(PresentSlot
+            {
+                cmd_pool: std::ptr::null_mut(), cbufs: [std::ptr::null_mut(); 3],
+                present_order: std::ptr::null_mut(), fence: std::ptr::null_mut(),
+                issued: false
+            });
+            let s = capture.slots.last_mut().unwrap();
+            let cpinfo = VkCommandPoolCreateInfo
+            {
+                queueFamilyIndex: instance.queue_family_index,
+                .. Default::default()
+            };
+            vk_check("vkCreateCommandPool", fp_create_command_pool(instance.device, &cpinfo, std::ptr::null(), &mut s.cmd_pool))?;
+            live_objects::created("command_pool");
+            let ainfo = VkCommandBufferAllocateInfo
+            {
+                commandPool: s.cmd_pool,
+                commandBufferCount: 3,
+                level: VK_COMMAND_BUFFER_LEVEL_PRIMARY,
+                .. Default::default()
+            };
+            vk_check("vkAllocateCommandBuffers", fp_alloc_command_buffer(instance.device, &ainfo, s.cbufs.as_mut_ptr()))?;
+            vk_check("vkCreateSemaphore", fp_create_semaphore(instance.device, &Default::default(), std::ptr::null(), &mut s.present_order))?;
+            live_objects::created("semaphore");
+            vk_check("vkCreateFence", fp_create_fence(instance.device, &Default::default(), std::ptr::null(), &mut s.fence))?;
+            live_objects::created("fence");
+        }
+
+        Ok(capture)
+    }
+
+    /// Submits the transition of `frame` (in PRESENT_SRC_KHR layout) for the sinks, after `wait_semaphores`.
+    /// Returns the image in TRANSFER_SRC_OPTIMAL layout. `end` has to follow once the sinks have been dispatched.
+    pub fn begin(&mut self, wait_semaphores: &[VkSemaphore], frame: UnityVulkanImage) -> Result<UnityVulkanImage, InterceptorError>
+    {
+        let s = &mut self.slots[self.current];
+        if s.issued
+        {
+            vk_check("vkWaitForFences", (self.fp_wait_fences)(self.device, 1, &s.fence, true as _, std::u64::MAX))?;
+            (self.fp_reset_fences)(self.device, 1, &s.fence);
+            s.issued = false;
+        }
+        (self.fp_reset_command_pool)(self.device, s.cmd_pool, 0);
+
+        let readable = VkImageMemoryBarrier
+        {
+            image: frame.image, subresourceRange: VkImageSubresourceRange
+            {
+                aspectMask: VK_IMAGE_ASPECT_COLOR_BIT,
+                baseArrayLayer: 0, layerCount: 1,
+                baseMipLevel: 0, levelCount: 1
+            },
+            oldLayout: VK_IMAGE_LAYOUT_PRESENT_SRC_KHR, newLayout: VK_IMAGE_LAYOUT_TRANSFER_SRC_OPTIMAL,
+            srcAccessMask: 0, dstAccessMask: VK_ACCESS_TRANSFER_READ_BIT,
+            srcQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED, dstQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED,
+            .. Default::default()
+        };
+        vk_check("vkBeginCommandBuffer", (self.fp_begin_command_record)(s.cbufs[0], &VkCommandBufferBeginInfo
+        {
+            flags: VK_COMMAND_BUFFER_USAGE_ONE_TIME_SUBMIT_BIT,
+            .. Default::default()
+        }))?;
+        // also orders the reads by the sinks, which are submitted later to the same queue
+        (self.fp_cmd_pipeline_barrier)(s.cbufs[0], VK_PIPELINE_STAGE_TRANSFER_BIT, VK_PIPELINE_STAGE_TRANSFER_BIT, 0,
+            0, std::ptr::null(), 0, std::ptr::null(), 1, &readable);
+        vk_check("vkEndCommandBuffer", (self.fp_end_command_record)(s.cbufs[0]))?;
+
+        let wait_stages = vec![VK_PIPELINE_STAGE_TRANSFER_BIT; wait_semaphores.len()];
+        let subinfo = VkSubmitInfo
+        {
+            waitSemaphoreCount: wait_semaphores.len() as _,
+            pWaitSemaphores: wait_semaphores.as_ptr(),
+            pWaitDstStageMask: wait_stages.as_ptr(),
+            commandBufferCount: 1,
+            pCommandBuffers: &s.cbufs[0],
+            .. Default::default()
+        };
+        vk_check("vkQueueSubmit", (self.fp_submit_commands)(self.queue, 1, &subinfo, std::ptr::null_mut()))?;
+        self.image = frame.image;
+
+        Ok(UnityVulkanImage { layout: VK_IMAGE_LAYOUT_TRANSFER_SRC_OPTIMAL, .. frame })
+    }
+    /// Submits the transition back to PRESENT_SRC_KHR and returns the semaphore the presentation has to wait for
+    pub fn end(&mut self) -> Result<VkSemaphore, InterceptorError>
+    {
+        let cbuf = self.slots[self.current].cbufs[1];
+        self.record_presentable(cbuf)?;
+
+        let s = &mut self.slots[self.current];
+        let subinfo = VkSubmitInfo
+        {
+            commandBufferCount: 1,
+            pCommandBuffers: &s.cbufs[1],
+            signalSemaphoreCount: 1,
+            pSignalSemaphores: &s.present_order,
+            .. Default::default()
+        };
+        vk_check("vkQueueSubmit", (self.fp_submit_commands)(self.queue, 1, &subinfo, s.fence))?;
+        s.issued = true;
+        let present_order = s.present_order;
+        self.current = (self.current + 1) % self.slots.len();
+
+        Ok(present_order)
+    }
+    /// Signals the semaphore `end` would have, for a presentation whose semaphores `begin` has already waited for
+    /// when `end` failed. The transition back to PRESENT_SRC_KHR is recorded again into the spare command buffer,
+    /// and only left out (the image staying in TRANSFER_SRC_OPTIMAL layout) if that fails too.
+    pub fn signal(&mut self) -> Result<VkSemaphore, InterceptorError>
+    {
+        let cbuf = self.slots[self.current].cbufs[2];
+        let recorded = self.record_presentable(cbuf);
+        if let Err(e) = &recorded { error::set_last_error(e); }
+
+        let s = &mut self.slots[self.current];
+        let subinfo = VkSubmitInfo
+        {
+            commandBufferCount: if recorded.is_ok() { 1 } else { 0 },
+            pCommandBuffers: &s.cbufs[2],
+            signalSemaphoreCount: 1,
+            pSignalSemaphores: &s.present_order,
+            .. Default::default()
+        };
+        vk_check("vkQueueSubmit", (self.fp_submit_commands)(self.queue, 1, &subinfo, s.fence))?;
+        s.issued = true;
+        let present_order = s.present_order;
+        self.current = (self.current + 1) % self.slots.len();
+
+        Ok(present_order)
+    }
+    /// Records the transition of the captured image back to PRESENT_SRC_KHR into `cbuf`
+    fn record_presentable(&self, cbuf: VkCommandBuffer) -> Result<(), InterceptorError>
+    {
+        let presentable = VkImageMemoryBarrier
+        {
+            image: self.image, subresourceRange: VkImageSubresourceRange
+            {
+                aspectMask: VK_IMAGE_ASPECT_COLOR_BIT,
+                baseArrayLayer: 0, layerCount: 1,
+                baseMipLevel: 0, levelCount: 1
+            },
+            oldLayout: VK_IMAGE_LAYOUT_TRANSFER_SRC_OPTIMAL, newLayout: VK_IMAGE_LAYOUT_PRESENT_SRC_KHR,
+            srcAccessMask: 0, dstAccessMask: 0,
+            srcQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED, dstQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED,
+            .. Default::default()
+        };
+        vk_check("vkBeginCommandBuffer", (self.fp_begin_command_record)(cbuf, &VkCommandBufferBeginInfo
+        {
+            flags: VK_COMMAND_BUFFER_USAGE_ONE_TIME_SUBMIT_BIT,
+            .. Default::default()
+        }))?;
+        (self.fp_cmd_pipeline_barrier)(cbuf, VK_PIPELINE_STAGE_TRANSFER_BIT, VK_PIPELINE_STAGE_BOTTOM_OF_PIPE_BIT, 0,
+            0, std::ptr::null(), 0, std::ptr::null(), 1, &presentable);
+        vk_check("vkEndCommandBuffer", (self.fp_end_command_record)(cbuf))
+    }
+}
+impl Drop for PresentCapture
+{
+    fn drop(&mut self)
+    {
+        if self.slots.iter().any(|s| s.issued) { (self.fp_device_wait_idle)(self.device); }
+        // a slot whose creation failed misses some objects
+        for s in &self.slots
+        {
+            if !s.cmd_pool.is_null() { (self.fp_destroy_command_pool)(self.device, s.cmd_pool, std::ptr::null()); live_objects::destroyed("command_pool"); }
+            if !s.present_order.is_null() { (self.fp_destroy_semaphore)(self.device, s.present_order, std::ptr::null()); live_objects::destroyed("semaphore"); }
+            if !s.fence.is_null() { (self.fp_destroy_fence)(self.device, s.fence, std::ptr::null()); live_objects::destroyed("fence"); }
+        }
+    }
+}
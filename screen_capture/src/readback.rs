@@ -0,0 +1,435 @@
+//! GPU to CPU Frame Readback
+
+use bedrock::vk::*;
+use log::*;
+use crate::unity::*;
+use crate::error::{InterceptorError, vk_check};
+use crate::live_objects;
+use crate::sink::{FrameSink, SinkResult};
+use crate::scaling::Rect;
+use crate::settings;
+use crate::event;
+use crate::transform::Transform;
+
+/// Bytes per texel of the color formats Unity uses for render buffers
+pub fn format_texel_size(format: VkFormat) -> Option<u32>
+{
+    match format
+    {
+        VK_FORMAT_R8G8B8A8_UNORM | VK_FORMAT_R8G8B8A8_SRGB |
+        VK_FORMAT_B8G8R8A8_UNORM | VK_FORMAT_B8G8R8A8_SRGB |
+        VK_FORMAT_A2B10G10R10_UNORM_PACK32 | VK_FORMAT_A2R10G10B10_UNORM_PACK32 |
+        VK_FORMAT_B10G11R11_UFLOAT_PACK32 => Some(4),
+        VK_FORMAT_R16G16B16A16_SFLOAT | VK_FORMAT_R16G16B16A16_UNORM => Some(8),
+        VK_FORMAT_R32G32B32A32_SFLOAT => Some(16),
+        _ => None
+    }
+}
+
+/// A frame that finished copying into host memory
+pub struct ReadbackFrame<'a>
+{
+    /// Mapped staging memory, `row_pitch * extent.height` bytes
+    pub data: &'a [u8],
+    pub format: VkFormat,
+    /// Size of the captured region of the frame
+    pub extent: VkExtent2D,
+    pub row_pitch: usize,
+    /// Sequential number of the captured frame, counted from the pool creation
+    pub frame_number: u64,
+    /// `frame_tag` of the capture event the frame comes from (see `event::CaptureEventData`); 0 for other frames
+    pub tag: u64
+}
+/// Called on the render thread when a frame becomes readable. The slice is only valid during the call.
+/// Heavy work should be moved off to another thread to keep the render thread going.
+pub type ReadbackCallback = Box<dyn FnMut(&ReadbackFrame) + Send>;
+
+/// Number of staging buffers; frames are dropped while all of them are in flight
+pub const DEFAULT_STAGING_COUNT: usize = 3;
+
+struct PendingCopy
+{
+    format: VkFormat,
+    extent: VkExtent2D,
+    row_pitch: usize,
+    texel_size: usize,
+    frame_number: u64,
+    tag: u64,
+    /// applied on the CPU when the copy is delivered
+    transform: Transform
+}
+struct StagingSlot
+{
+    buffer: VkBuffer,
+    memory: VkDeviceMemory,
+    mapped: *mut u8,
+    capacity: VkDeviceSize,
+    cmd_pool: VkCommandPool,
+    cbuf: VkCommandBuffer,
+    fence: VkFence,
+    pending: Option<PendingCopy>
+}
+
+pub struct ReadbackPool
+{
+    device: VkDevice,
+    queue: VkQueue,
+    memory_properties: VkPhysicalDeviceMemoryProperties,
+    slots: Vec<StagingSlot>,
+    frame_counter: u64,
+    transform: Transform,
+    /// receives transformed frames
+    scratch: Vec<u8>,
+    fp_create_buffer: PFN_vkCreateBuffer,
+    fp_destroy_buffer: PFN_vkDestroyBuffer,
+    fp_get_buffer_memory_requirements: PFN_vkGetBufferMemoryRequirements,
+    fp_allocate_memory: PFN_vkAllocateMemory,
+    fp_free_memory: PFN_vkFreeMemory,
+    fp_bind_buffer_memory: PFN_vkBindBufferMemory,
+    fp_map_memory: PFN_vkMapMemory,
+    fp_destroy_command_pool: PFN_vkDestroyCommandPool,
+    fp_reset_command_pool: PFN_vkResetCommandPool,
+    fp_begin_command_record: PFN_vkBeginCommandBuffer,
+    fp_end_command_record: PFN_vkEndCommandBuffer,
+    fp_cmd_copy_image_to_buffer: PFN_vkCmdCopyImageToBuffer,
+    fp_cmd_pipeline_barrier: PFN_vkCmdPipelineBarrier,
+    fp_submit_commands: PFN_vkQueueSubmit,
+    fp_get_fence_status: PFN_vkGetFenceStatus,
+    fp_wait_fences: PFN_vkWaitForFences,
+    fp_reset_fences: PFN_vkResetFences,
+    fp_destroy_fence: PFN_vkDestroyFence
+}
+impl ReadbackPool
+{
+    pub fn new(instance: &UnityVulkanInstance, staging_count: usize) -> Result<Self, InterceptorError>
+    {
+        trace!("Interceptor: ReadbackPool::new");
+
+        let fp_get_physical_device_memory_properties: PFN_vkGetPhysicalDeviceMemoryProperties = load_instance_proc!(instance, "vkGetPhysicalDeviceMemoryProperties")?;
+        let fp_create_command_pool: PFN_vkCreateCommandPool = load_instance_proc!(instance, "vkCreateCommandPool")?;
+        let fp_alloc_command_buffer: PFN_vkAllocateCommandBuffers = load_instance_proc!(instance, "vkAllocateCommandBuffers")?;
+        let fp_create_fence: PFN_vkCreateFence = load_instance_proc!(instance, "vkCreateFence")?;
+
+        let mut memory_properties = std::mem::MaybeUninit::uninit();
+        fp_get_physical_device_memory_properties(instance.physical_device, memory_properties.as_mut_ptr());
+
+        let mut pool = ReadbackPool
+        {
+            device: instance.device,
+            queue: instance.graphics_queue,
+            memory_properties: unsafe { memory_properties.assume_init() },
+            slots: Vec::with_capacity(staging_count), frame_counter: 0,
+            transform: Transform::IDENTITY, scratch: Vec::new(),
+            fp_create_buffer: load_instance_proc!(instance, "vkCreateBuffer")?,
+            fp_destroy_buffer: load_instance_proc!(instance, "vkDestroyBuffer")?,
+            fp_get_buffer_memory_requirements: load_instance_proc!(instance, "vkGetBufferMemoryRequirements")?,
+            fp_allocate_memory: load_instance_proc!(instance, "vkAllocateMemory")?,
+            fp_free_memory: load_instance_proc!(instance, "vkFreeMemory")?,
+            fp_bind_buffer_memory: load_instance_proc!(instance, "vkBindBufferMemory")?,
+            fp_map_memory: load_instance_proc!(instance, "vkMapMemory")?,
+            fp_destroy_command_pool: load_instance_proc!(instance, "vkDestroyCommandPool")?,
+            fp_reset_command_pool: load_instance_proc!(instance, "vkResetCommandPool")?,
+            fp_begin_command_record: load_instance_proc!(instance, "vkBeginCommandBuffer")?,
+            fp_end_command_record: load_instance_proc!(instance, "vkEndCommandBuffer")?,
+            fp_cmd_copy_image_to_buffer: load_instance_proc!(instance, "vkCmdCopyImageToBuffer")?,
+            fp_cmd_pipeline_barrier: load_instance_proc!(instance, "vkCmdPipelineBarrier")?,
+            fp_submit_commands: load_instance_proc!(instance, "vkQueueSubmit")?,
+            fp_get_fence_status: load_instance_proc!(instance, "vkGetFenceStatus")?,
+            fp_wait_fences: load_instance_proc!(instance, "vkWaitForFences")?,
+            fp_reset_fences: load_instance_proc!(instance, "vkResetFences")?,
+            fp_destroy_fence: load_instance_proc!(instance, "vkDestroyFence")?
+        };
+
+        // every entry point is loaded before the first object is created; on failure, dropping the pool destroys what was created
+        for _ in 0 .. staging_count
+        {
+            pool.slots.push(StagingSlot
+            {
+                buffer: std::ptr::null_mut(), memory: std::ptr::null_mut(), mapped: std::ptr::null_mut(), capacity: 0,
+                cmd_pool: std::ptr::null_mut(), cbuf: std::ptr::null_mut(), fence: std::ptr::null_mut(),
+                pending: None
+            });
+            let s = pool.slots.last_mut().unwrap();
+            let cpinfo = VkCommandPoolCreateInfo
+            {
+                queueFamilyIndex: instance.queue_family_index,
+                .. Default::default()
+            };
+            vk_check("vkCreateCommandPool", fp_create_command_pool(instance.device, &cpinfo, std::ptr::null(), &mut s.cmd_pool))?;
+            live_objects::created("command_pool");
+            let ainfo = VkCommandBufferAllocateInfo
+            {
+                commandPool: s.cmd_pool,
+                commandBufferCount: 1,
+                level: VK_COMMAND_BUFFER_LEVEL_PRIMARY,
+                .. Default::default()
+            };
+            vk_check("vkAllocateCommandBuffers", fp_alloc_command_buffer(instance.device, &ainfo, &mut s.cbuf))?;
+            vk_check("vkCreateFence", fp_create_fence(instance.device, &Default::default(), std::ptr::null(), &mut s.fence))?;
+            live_objects::created("fence");
+        }
+
+        Ok(pool)
+    }
+
+    /// Waits for every copy in flight and hands them to `receiver` with the finished ones
+    pub fn flush<F: FnMut(&ReadbackFrame)>(&mut self, receiver: F)
+    {
+        let fences: Vec<_> = self.slots.iter().filter(|s| s.pending.is_some()).map(|s| s.fence).collect();
+        if !fences.is_empty() { (self.fp_wait_fences)(self.device, fences.len() as _, fences.as_ptr(), true as _, std::u64::MAX); }
+        self.poll(receiver);
+    }
+    /// Hands the finished copies to `receiver` in frame order, up to the first one still in flight. Never blocks.
+    pub fn poll<F: FnMut(&ReadbackFrame)>(&mut self, mut receiver: F)
+    {
+        // staging buffers are reused as they free up, so a later frame may be in an earlier slot
+        while let Some((_, i)) = self.slots.iter().enumerate().filter_map(|(i, s)| s.pending.as_ref().map(|p| (p.frame_number, i))).min()
+        {
+            let s = &mut self.slots[i];
+            if (self.fp_get_fence_status)(self.device, s.fence) != VK_SUCCESS { break; }
+
+            let p = s.pending.take().unwrap();
+            let data = unsafe { std::slice::from_raw_parts(s.mapped, p.row_pitch * p.extent.height as usize) };
+            if p.transform.is_identity()
+            {
+                receiver(&ReadbackFrame
+                {
+                    data, format: p.format, extent: p.extent, row_pitch: p.row_pitch, frame_number: p.frame_number, tag: p.tag
+                });
+            }
+            else
+            {
+                let row_pitch = p.transform.apply(data, p.extent.width, p.extent.height, p.row_pitch, p.texel_size, &mut self.scratch);
+                let (width, height) = p.transform.output_extent(p.extent.width, p.extent.height);
+                receiver(&ReadbackFrame
+                {
+                    data: &self.scratch, format: p.format, extent: VkExtent2D { width, height }, row_pitch, frame_number: p.frame_number, tag: p.tag
+                });
+            }
+            (self.fp_reset_fences)(self.device, 1, &s.fence);
+        }
+    }
+
+    /// Records and submits a copy of the `region` of `image` into a free staging buffer.
+    /// The image must already be in a layout readable by transfer operations, and the region must lie inside it.
+    /// Returns the frame number assigned to the copy, or None if the frame was dropped
+    /// (no free staging buffer or unsupported format).
+    pub fn enqueue(&mut self, image: &UnityVulkanImage, region: Rect) -> Result<Option<u64>, InterceptorError>
+    {
+        let texel_size = match format_texel_size(image.format)
+        {
+            Some(s) => s,
+            None => { warn!("Interceptor: readback does not support format {}", image.format); return Ok(None); }
+        };
+        let slot_index = match self.slots.iter().position(|s| s.pending.is_none())
+        {
+            Some(x) => x,
+            // all staging buffers are still in flight; drop this frame rather than waiting
+            None => return Ok(None)
+        };
+
+        let extent = VkExtent2D { width: region.width, height: region.height };
+        let row_pitch = (extent.width * texel_size) as usize;
+        let required = (row_pitch * extent.height as usize) as VkDeviceSize;
+        if self.slots[slot_index].capacity < required && !self.reallocate(slot_index, required)? { return Ok(None); }
+
+        let s = &mut self.slots[slot_index];
+        (self.fp_reset_command_pool)(self.device, s.cmd_pool, 0);
+        vk_check("vkBeginCommandBuffer", (self.fp_begin_command_record)(s.cbuf, &VkCommandBufferBeginInfo
+        {
+            flags: VK_COMMAND_BUFFER_USAGE_ONE_TIME_SUBMIT_BIT,
+            .. Default::default()
+        }))?;
+        let region = VkBufferImageCopy
+        {
+            bufferOffset: 0, bufferRowLength: 0, bufferImageHeight: 0,
+            imageSubresource: VkImageSubresourceLayers
+            {
+                aspectMask: VK_IMAGE_ASPECT_COLOR_BIT,
+                baseArrayLayer: 0,
+                layerCount: 1,
+                mipLevel: 0
+            },
+            imageOffset: VkOffset3D { x: region.x, y: region.y, z: 0 },
+            imageExtent: VkExtent3D { width: extent.width, height: extent.height, depth: 1 }
+        };
+        (self.fp_cmd_copy_image_to_buffer)(s.cbuf, image.image, image.layout, s.buffer, 1, &region);
+        let host_visible_barrier = VkBufferMemoryBarrier
+        {
+            buffer: s.buffer, offset: 0, size: required,
+            srcAccessMask: VK_ACCESS_TRANSFER_WRITE_BIT, dstAccessMask: VK_ACCESS_HOST_READ_BIT,
+            srcQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED, dstQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED,
+            .. Default::default()
+        };
+        (self.fp_cmd_pipeline_barrier)(s.cbuf, VK_PIPELINE_STAGE_TRANSFER_BIT, VK_PIPELINE_STAGE_HOST_BIT, 0,
+            0, std::ptr::null(), 1, &host_visible_barrier, 0, std::ptr::null());
+        vk_check("vkEndCommandBuffer", (self.fp_end_command_record)(s.cbuf))?;
+
+        let subinfo = VkSubmitInfo
+        {
+            commandBufferCount: 1,
+            pCommandBuffers: &s.cbuf,
+            .. Default::default()
+        };
+        // the slot stays free if the copy never reaches the queue, as its fence would never signal
+        vk_check("vkQueueSubmit", (self.fp_submit_commands)(self.queue, 1, &subinfo, s.fence))?;
+        let frame_number = self.frame_counter;
+        s.pending = Some(PendingCopy
+        {
+            format: image.format, extent, row_pitch, texel_size: texel_size as _, frame_number, tag: event::current_tag(), transform: self.transform
+        });
+        self.frame_counter += 1;
+
+        Ok(Some(frame_number))
+    }
+
+    /// Orientation of the frames enqueued from now on
+    pub fn set_transform(&mut self, transform: Transform) { self.transform = transform; }
+
+    /// (Re)creates the staging buffer of an idle slot with at least `size` bytes.
+    /// Returns false if no memory the CPU can read is available.
+    fn reallocate(&mut self, slot_index: usize, size: VkDeviceSize) -> Result<bool, InterceptorError>
+    {
+        self.release_buffer(slot_index);
+
+        let binfo = VkBufferCreateInfo
+        {
+            size,
+            usage: VK_BUFFER_USAGE_TRANSFER_DST_BIT,
+            sharingMode: VK_SHARING_MODE_EXCLUSIVE,
+            .. Default::default()
+        };
+        let mut buffer = std::mem::MaybeUninit::uninit();
+        vk_check("vkCreateBuffer", (self.fp_create_buffer)(self.device, &binfo, std::ptr::null(), buffer.as_mut_ptr()))?;
+        live_objects::created("buffer");
+        let buffer = unsafe { buffer.assume_init() };
+        let mut req = std::mem::MaybeUninit::uninit();
+        (self.fp_get_buffer_memory_requirements)(self.device, buffer, req.as_mut_ptr());
+        let req: VkMemoryRequirements = unsafe { req.assume_init() };
+
+        // Cached memory is much faster to read from the CPU; coherency spares the invalidate calls
+        let memory_type_index = self.find_memory_type(req.memoryTypeBits, VK_MEMORY_PROPERTY_HOST_VISIBLE_BIT | VK_MEMORY_PROPERTY_HOST_COHERENT_BIT | VK_MEMORY_PROPERTY_HOST_CACHED_BIT)
+            .or_else(|| self.find_memory_type(req.memoryTypeBits, VK_MEMORY_PROPERTY_HOST_VISIBLE_BIT | VK_MEMORY_PROPERTY_HOST_COHERENT_BIT));
+        let memory_type_index = match memory_type_index
+        {
+            Some(x) => x,
+            None =>
+            {
+                (self.fp_destroy_buffer)(self.device, buffer, std::ptr::null());
+                live_objects::destroyed("buffer");
+                warn!("Interceptor: no host visible memory type for readback");
+                return Ok(false);
+            }
+        };
+        let ainfo = VkMemoryAllocateInfo
+        {
+            allocationSize: req.size,
+            memoryTypeIndex: memory_type_index,
+            .. Default::default()
+        };
+        let mut memory = std::mem::MaybeUninit::uninit();
+        if let Err(e) = vk_check("vkAllocateMemory", (self.fp_allocate_memory)(self.device, &ainfo, std::ptr::null(), memory.as_mut_ptr()))
+        {
+            (self.fp_destroy_buffer)(self.device, buffer, std::ptr::null());
+            live_objects::destroyed("buffer");
+            return Err(e);
+        }
+        live_objects::created("memory");
+        let memory = unsafe { memory.assume_init() };
+        let mut mapped = std::ptr::null_mut();
+        let bound = vk_check("vkBindBufferMemory", (self.fp_bind_buffer_memory)(self.device, buffer, memory, 0))
+            .and_then(|_| vk_check("vkMapMemory", (self.fp_map_memory)(self.device, memory, 0, req.size, 0, &mut mapped)));
+        if let Err(e) = bound
+        {
+            (self.fp_destroy_buffer)(self.device, buffer, std::ptr::null());
+            live_objects::destroyed("buffer");
+            (self.fp_free_memory)(self.device, memory, std::ptr::null());
+            live_objects::destroyed("memory");
+            return Err(e);
+        }
+
+        let s = &mut self.slots[slot_index];
+        s.buffer = buffer;
+        s.memory = memory;
+        s.mapped = mapped as *mut u8;
+        s.capacity = size;
+        Ok(true)
+    }
+    fn release_buffer(&mut self, slot_index: usize)
+    {
+        let s = &mut self.slots[slot_index];
+        if s.buffer.is_null() { return; }
+
+        // freeing the memory implicitly unmaps it
+        (self.fp_destroy_buffer)(self.device, s.buffer, std::ptr::null());
+        live_objects::destroyed("buffer");
+        (self.fp_free_memory)(self.device, s.memory, std::ptr::null());
+        live_objects::destroyed("memory");
+        s.buffer = std::ptr::null_mut();
+        s.memory = std::ptr::null_mut();
+        s.mapped = std::ptr::null_mut();
+        s.capacity = 0;
+    }
+    fn find_memory_type(&self, type_bits: u32, flags: VkMemoryPropertyFlags) -> Option<u32>
+    {
+        (0 .. self.memory_properties.memoryTypeCount).find(|&i|
+            (type_bits & (1 << i)) != 0 && (self.memory_properties.memoryTypes[i as usize].propertyFlags & flags) == flags)
+    }
+}
+impl Drop for ReadbackPool
+{
+    fn drop(&mut self)
+    {
+        for i in 0 .. self.slots.len()
+        {
+            if self.slots[i].pending.take().is_some()
+            {
+                (self.fp_wait_fences)(self.device, 1, &self.slots[i].fence, true as _, std::u64::MAX);
+            }
+            self.release_buffer(i);
+            // a slot whose creation failed misses some objects
+            let s = &self.slots[i];
+            if !s.fence.is_null() { (self.fp_destroy_fence)(self.device, s.fence, std::ptr::null()); live_objects::destroyed("fence"); }
+            if !s.cmd_pool.is_null() { (self.fp_destroy_command_pool)(self.device, s.cmd_pool, std::ptr::null()); live_objects::destroyed("command_pool"); }
+        }
+    }
+}
+
+/// Hands every intercepted frame to a callback through a ReadbackPool
+pub struct ReadbackSink
+{
+    pool: ReadbackPool,
+    callback: ReadbackCallback
+}
+impl ReadbackSink
+{
+    pub fn new(instance: &UnityVulkanInstance, callback: ReadbackCallback) -> Result<Self, InterceptorError>
+    {
+        Ok(ReadbackSink { pool: ReadbackPool::new(instance, DEFAULT_STAGING_COUNT)?, callback })
+    }
+}
+impl FrameSink for ReadbackSink
+{
+    fn name(&self) -> &str { "readback" }
+    fn process(&mut self, frame: &UnityVulkanImage) -> SinkResult
+    {
+        let callback = &mut self.callback;
+        self.pool.poll(|f| callback(f));
+        let region = match settings::current().source_rect(frame.extent)
+        {
+            Some(r) => r,
+            // nothing of the frame is captured
+            None => return Ok(())
+        };
+        if self.pool.enqueue(frame, region)?.is_none() { trace!("Interceptor: readback dropped a frame"); }
+
+        Ok(())
+    }
+    fn set_transform(&mut self, transform: Transform) -> bool { self.pool.set_transform(transform); true }
+    fn flush(&mut self) -> SinkResult
+    {
+        let callback = &mut self.callback;
+        self.pool.flush(|f| callback(f));
+        Ok(())
+    }
+}
@@ -0,0 +1,297 @@
+//! Resolution of Multisampled Render Buffers
+
+use bedrock::vk::*;
+use log::*;
+use crate::unity::*;
+use crate::error::{InterceptorError, vk_check};
+use crate::live_objects;
+
+/// Resolve submissions that may be in flight before the oldest one is waited for
+const RESOLVE_SLOTS: usize = 2;
+
+struct ResolveSlot
+{
+    cmd_pool: VkCommandPool,
+    cbuf: VkCommandBuffer,
+    fence: VkFence,
+    issued: bool
+}
+
+/// Resolves multisampled frames into a single-sampled image with vkCmdResolveImage, for render buffers that
+/// Unity has no resolve texture for. Transfer commands (blits and copies) cannot read multisampled images.
+/// The resolve is submitted on the graphics queue ahead of the outputs, which then read the result.
+pub struct MsaaResolver
+{
+    device: VkDevice,
+    queue: VkQueue,
+    memory_properties: VkPhysicalDeviceMemoryProperties,
+    slots: Vec<ResolveSlot>,
+    current: usize,
+    image: VkImage,
+    memory: VkDeviceMemory,
+    memory_size: VkDeviceSize,
+    memory_type_index: u32,
+    extent: VkExtent3D,
+    format: VkFormat,
+    fp_create_image: PFN_vkCreateImage,
+    fp_destroy_image: PFN_vkDestroyImage,
+    fp_get_image_memory_requirements: PFN_vkGetImageMemoryRequirements,
+    fp_bind_image_memory: PFN_vkBindImageMemory,
+    fp_allocate_memory: PFN_vkAllocateMemory,
+    fp_free_memory: PFN_vkFreeMemory,
+    fp_destroy_command_pool: PFN_vkDestroyCommandPool,
+    fp_reset_command_pool: PFN_vkResetCommandPool,
+    fp_begin_command_record: PFN_vkBeginCommandBuffer,
+    fp_end_command_record: PFN_vkEndCommandBuffer,
+    fp_cmd_resolve_image: PFN_vkCmdResolveImage,
+    fp_cmd_pipeline_barrier: PFN_vkCmdPipelineBarrier,
+    fp_submit_commands: PFN_vkQueueSubmit,
+    fp_wait_fences: PFN_vkWaitForFences,
+    fp_reset_fences: PFN_vkResetFences,
+    fp_destroy_fence: PFN_vkDestroyFence,
+    fp_device_wait_idle: PFN_vkDeviceWaitIdle
+}
+impl MsaaResolver
+{
+    pub fn new(instance: &UnityVulkanInstance) -> Result<Self, InterceptorError>
+    {
+        trace!("Interceptor: MsaaResolver::new");
+
+        let fp_get_physical_device_memory_properties: PFN_vkGetPhysicalDeviceMemoryProperties = load_instance_proc!(instance, "vkGetPhysicalDeviceMemoryProperties")?;
+        let fp_create_command_pool: PFN_vkCreateCommandPool = load_instance_proc!(instance, "vkCreateCommandPool")?;
+        let fp_alloc_command_buffer: PFN_vkAllocateCommandBuffers = load_instance_proc!(instance, "vkAllocateCommandBuffers")?;
+        let fp_create_fence: PFN_vkCreateFence = load_instance_proc!(instance, "vkCreateFence")?;
+
+        let mut memory_properties = std::mem::MaybeUninit::uninit();
+        fp_get_physical_device_memory_properties(instance.physical_device, memory_properties.as_mut_ptr());
+
+        let mut resolver = MsaaResolver
+        {
+            device: instance.device,
+            queue: instance.graphics_queue,
+            memory_properties: unsafe { memory_properties.assume_init() },
+            slots: Vec::with_capacity(RESOLVE_SLOTS), current: 0,
+            image: std::ptr::null_mut(), memory: std::ptr::null_mut(), memory_size: 0, memory_type_index: 0,
+            extent: VkExtent3D { width: 0, height: 0, depth: 0 }, format: VK_FORMAT_UNDEFINED,
+            fp_create_image: load_instance_proc!(instance, "vkCreateImage")?,
+            fp_destroy_image: load_instance_proc!(instance, "vkDestroyImage")?,
+            fp_get_image_memory_requirements: load_instance_proc!(instance, "vkGetImageMemoryRequirements")?,
+            fp_bind_image_memory: load_instance_proc!(instance, "vkBindImageMemory")?,
+            fp_allocate_memory: load_instance_proc!(instance, "vkAllocateMemory")?,
+            fp_free_memory: load_instance_proc!(instance, "vkFreeMemory")?,
+            fp_destroy_command_pool: load_instance_proc!(instance, "vkDestroyCommandPool")?,
+            fp_reset_command_pool: load_instance_proc!(instance, "vkResetCommandPool")?,
+            fp_begin_command_record: load_instance_proc!(instance, "vkBeginCommandBuffer")?,
+            fp_end_command_record: load_instance_proc!(instance, "vkEndCommandBuffer")?,
+            fp_cmd_resolve_image: load_instance_proc!(instance, "vkCmdResolveImage")?,
+            fp_cmd_pipeline_barrier: load_instance_proc!(instance, "vkCmdPipelineBarrier")?,
+            fp_submit_commands: load_instance_proc!(instance, "vkQueueSubmit")?,
+            fp_wait_fences: load_instance_proc!(instance, "vkWaitForFences")?,
+            fp_reset_fences: load_instance_proc!(instance, "vkResetFences")?,
+            fp_destroy_fence: load_instance_proc!(instance, "vkDestroyFence")?,
+            fp_device_wait_idle: load_instance_proc!(instance, "vkDeviceWaitIdle")?
+        };
+
+        // same as ReadbackPool: on failure, dropping the resolver destroys the slots created so far
+        for _ in 0 .. RESOLVE_SLOTS
+        {
+            resolver.slots.push(ResolveSlot { cmd_pool: std::ptr::null_mut(), cbuf: std::ptr::null_mut(), fence: std::ptr::null_mut(), issued: false });
+            let s = resolver.slots.last_mut().unwrap();
+            let cpinfo = VkCommandPoolCreateInfo
+            {
+                queueFamilyIndex: instance.queue_family_index,
+                .. Default::default()
+            };
+            vk_check("vkCreateCommandPool", fp_create_command_pool(instance.device, &cpinfo, std::ptr::null(), &mut s.cmd_pool))?;
+            live_objects::created("command_pool");
+            let ainfo = VkCommandBufferAllocateInfo
+            {
+                commandPool: s.cmd_pool,
+                commandBufferCount: 1,
+                level: VK_COMMAND_BUFFER_LEVEL_PRIMARY,
+                .. Default::default()
+            };
+            vk_check("vkAllocateCommandBuffers", fp_alloc_command_buffer(instance.device, &ainfo, &mut s.cbuf))?;
+            vk_check("vkCreateFence", fp_create_fence(instance.device, &Default::default(), std::ptr::null(), &mut s.fence))?;
+            live_objects::created("fence");
+        }
+
+        Ok(resolver)
+    }
+
+    /// Submits the resolve of `src` (multisampled, in a layout readable by transfer operations).
+    /// Returns the single-sampled result in TRANSFER_SRC_OPTIMAL layout, valid until the next call.
+    pub fn resolve(&mut self, src: &UnityVulkanImage) -> Result<UnityVulkanImage, InterceptorError>
+    {
+        if self.image.is_null() || self.extent.width != src.extent.width || self.extent.height != src.extent.height || self.format != src.format
+        {
+            self.allocate(src.extent, src.format)?;
+        }
+
+        let s = &mut self.slots[self.current];
+        if s.issued
+        {
+            vk_check("vkWaitForFences", (self.fp_wait_fences)(self.device, 1, &s.fence, true as _, std::u64::MAX))?;
+            vk_check("vkResetFences", (self.fp_reset_fences)(self.device, 1, &s.fence))?;
+            s.issued = false;
+        }
+        (self.fp_reset_command_pool)(self.device, s.cmd_pool, 0);
+        vk_check("vkBeginCommandBuffer", (self.fp_begin_command_record)(s.cbuf, &VkCommandBufferBeginInfo
+        {
+            flags: VK_COMMAND_BUFFER_USAGE_ONE_TIME_SUBMIT_BIT,
+            .. Default::default()
+        }))?;
+
+        let color_range = VkImageSubresourceRange
+        {
+            aspectMask: VK_IMAGE_ASPECT_COLOR_BIT,
+            baseArrayLayer: 0, layerCount: 1,
+            baseMipLevel: 0, levelCount: 1
+        };
+        // the outputs of the previous frame may still be reading the image
+        let writable = VkImageMemoryBarrier
+        {
+            image: self.image, subresourceRange: color_range.clone(),
+            oldLayout: VK_IMAGE_LAYOUT_UNDEFINED, newLayout: VK_IMAGE_LAYOUT_TRANSFER_DST_OPTIMAL,
+            srcAccessMask: 0, dstAccessMask: VK_ACCESS_TRANSFER_WRITE_BIT,
+            srcQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED, dstQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED,
+            .. Default::default()
+        };
+        // also orders the reads by the outputs, which are submitted later to the same queue
+        let readable = VkImageMemoryBarrier
+        {
+            image: self.image, subresourceRange: color_range,
+            oldLayout: VK_IMAGE_LAYOUT_TRANSFER_DST_OPTIMAL, newLayout: VK_IMAGE_LAYOUT_TRANSFER_SRC_OPTIMAL,
+            srcAccessMask: VK_ACCESS_TRANSFER_WRITE_BIT, dstAccessMask: VK_ACCESS_TRANSFER_READ_BIT,
+            srcQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED, dstQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED,
+            .. Default::default()
+        };
+        let layers = VkImageSubresourceLayers
+        {
+            aspectMask: VK_IMAGE_ASPECT_COLOR_BIT,
+            baseArrayLayer: 0,
+            layerCount: 1,
+            mipLevel: 0
+        };
+        let region = VkImageResolve
+        {
+            srcSubresource: layers.clone(), srcOffset: VkOffset3D { x: 0, y: 0, z: 0 },
+            dstSubresource: layers, dstOffset: VkOffset3D { x: 0, y: 0, z: 0 },
+            extent: VkExtent3D { width: src.extent.width, height: src.extent.height, depth: 1 }
+        };
+        (self.fp_cmd_pipeline_barrier)(s.cbuf, VK_PIPELINE_STAGE_TRANSFER_BIT, VK_PIPELINE_STAGE_TRANSFER_BIT, 0,
+            0, std::ptr::null(), 0, std::ptr::null(), 1, &writable);
+        (self.fp_cmd_resolve_image)(s.cbuf, src.image, src.layout, self.image, VK_IMAGE_LAYOUT_TRANSFER_DST_OPTIMAL, 1, &region);
+        (self.fp_cmd_pipeline_barrier)(s.cbuf, VK_PIPELINE_STAGE_TRANSFER_BIT, VK_PIPELINE_STAGE_TRANSFER_BIT, 0,
+            0, std::ptr::null(), 0, std::ptr::null(), 1, &readable);
+        vk_check("vkEndCommandBuffer", (self.fp_end_command_record)(s.cbuf))?;
+
+        let subinfo = VkSubmitInfo
+        {
+            commandBufferCount: 1,
+            pCommandBuffers: &s.cbuf,
+            .. Default::default()
+        };
+        vk_check("vkQueueSubmit", (self.fp_submit_commands)(self.queue, 1, &subinfo, s.fence))?;
+        s.issued = true;
+        self.current = (self.current + 1) % self.slots.len();
+
+        Ok(UnityVulkanImage
+        {
+            memory: UnityVulkanMemory
+            {
+                memory: self.memory, offset: 0, size: self.memory_size, mapped: std::ptr::null_mut(),
+                flags: self.memory_properties.memoryTypes[self.memory_type_index as usize].propertyFlags,
+                memory_type_index: self.memory_type_index, _resv: [std::ptr::null_mut(); 4]
+            },
+            image: self.image,
+            layout: VK_IMAGE_LAYOUT_TRANSFER_SRC_OPTIMAL,
+            aspect: VK_IMAGE_ASPECT_COLOR_BIT,
+            usage: VK_IMAGE_USAGE_TRANSFER_SRC_BIT | VK_IMAGE_USAGE_TRANSFER_DST_BIT,
+            format: self.format,
+            extent: self.extent,
+            tiling: VK_IMAGE_TILING_OPTIMAL,
+            type_: VK_IMAGE_TYPE_2D,
+            samples: VK_SAMPLE_COUNT_1_BIT,
+            layers: 1,
+            mip_count: 1,
+            _resv: [std::ptr::null_mut(); 4]
+        })
+    }
+
+    /// Recreates the resolve target. Waits for the device as submitted frames may still use the old one.
+    fn allocate(&mut self, extent: VkExtent3D, format: VkFormat) -> Result<(), InterceptorError>
+    {
+        if !self.image.is_null() { (self.fp_device_wait_idle)(self.device); }
+        self.release();
+        trace!("Interceptor: allocating resolve target {}x{}", extent.width, extent.height);
+
+        let extent = VkExtent3D { width: extent.width, height: extent.height, depth: 1 };
+        let iinfo = VkImageCreateInfo
+        {
+            imageType: VK_IMAGE_TYPE_2D,
+            format,
+            extent: extent.clone(),
+            mipLevels: 1, arrayLayers: 1,
+            samples: VK_SAMPLE_COUNT_1_BIT,
+            tiling: VK_IMAGE_TILING_OPTIMAL,
+            usage: VK_IMAGE_USAGE_TRANSFER_SRC_BIT | VK_IMAGE_USAGE_TRANSFER_DST_BIT,
+            sharingMode: VK_SHARING_MODE_EXCLUSIVE,
+            initialLayout: VK_IMAGE_LAYOUT_UNDEFINED,
+            .. Default::default()
+        };
+        let mut image = std::mem::MaybeUninit::uninit();
+        vk_check("vkCreateImage", (self.fp_create_image)(self.device, &iinfo, std::ptr::null(), image.as_mut_ptr()))?;
+        live_objects::created("image");
+        self.image = unsafe { image.assume_init() };
+        let mut req = std::mem::MaybeUninit::uninit();
+        (self.fp_get_image_memory_requirements)(self.device, self.image, req.as_mut_ptr());
+        let req: VkMemoryRequirements = unsafe { req.assume_init() };
+
+        // only the GPU touches it
+        let types = &self.memory_properties.memoryTypes[.. self.memory_properties.memoryTypeCount as usize];
+        let allowed = |i: usize| (req.memoryTypeBits & (1 << i)) != 0;
+        let memory_type_index = (0 .. types.len()).find(|&i| allowed(i) && (types[i].propertyFlags & VK_MEMORY_PROPERTY_DEVICE_LOCAL_BIT) != 0)
+            .or_else(|| (0 .. types.len()).find(|&i| allowed(i)))
+            .ok_or_else(|| InterceptorError::vulkan("vkAllocateMemory", VK_ERROR_OUT_OF_DEVICE_MEMORY))?;
+        let ainfo = VkMemoryAllocateInfo
+        {
+            allocationSize: req.size,
+            memoryTypeIndex: memory_type_index as _,
+            .. Default::default()
+        };
+        let mut memory = std::mem::MaybeUninit::uninit();
+        vk_check("vkAllocateMemory", (self.fp_allocate_memory)(self.device, &ainfo, std::ptr::null(), memory.as_mut_ptr()))?;
+        live_objects::created("memory");
+        self.memory = unsafe { memory.assume_init() };
+        vk_check("vkBindImageMemory", (self.fp_bind_image_memory)(self.device, self.image, self.memory, 0))?;
+        self.memory_size = req.size;
+        self.memory_type_index = memory_type_index as _;
+        self.extent = extent;
+        self.format = format;
+
+        Ok(())
+    }
+    fn release(&mut self)
+    {
+        if !self.image.is_null() { (self.fp_destroy_image)(self.device, self.image, std::ptr::null()); live_objects::destroyed("image"); }
+        if !self.memory.is_null() { (self.fp_free_memory)(self.device, self.memory, std::ptr::null()); live_objects::destroyed("memory"); }
+        self.image = std::ptr::null_mut();
+        self.memory = std::ptr::null_mut();
+        self.extent = VkExtent3D { width: 0, height: 0, depth: 0 };
+    }
+}
+impl Drop for MsaaResolver
+{
+    fn drop(&mut self)
+    {
+        if self.slots.iter().any(|s| s.issued) { (self.fp_device_wait_idle)(self.device); }
+        self.release();
+        // a slot whose creation failed misses some objects
+        for s in &self.slots
+        {
+            if !s.cmd_pool.is_null() { (self.fp_destroy_command_pool)(self.device, s.cmd_pool, std::ptr::null()); live_objects::destroyed("command_pool"); }
+            if !s.fence.is_null() { (self.fp_destroy_fence)(self.device, s.fence, std::ptr::null()); live_objects::destroyed("fence"); }
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! Placement of Captured Frames in the Mirror Window
+
+use bedrock::vk::*;
+use libc::*;
+
+pub type ScalingMode = c_int;
+/// Stretches the frame over the whole window, distorting it if the aspect ratios differ
+pub const SCALING_MODE_STRETCH: ScalingMode = 0;
+/// The largest size that fits in the window with the aspect ratio kept (letterbox/pillarbox)
+pub const SCALING_MODE_FIT: ScalingMode = 1;
+/// The smallest size that covers the window with the aspect ratio kept; the overflowing part is cropped
+pub const SCALING_MODE_FILL: ScalingMode = 2;
+/// The largest integer multiple that fits (at least 1x), sampled without filtering for pixel art
+pub const SCALING_MODE_INTEGER: ScalingMode = 3;
+/// 1:1 at the center of the window, cropped if the frame is larger
+pub const SCALING_MODE_CENTER: ScalingMode = 4;
+
+pub fn is_valid_mode(mode: ScalingMode) -> bool
+{
+    SCALING_MODE_STRETCH <= mode && mode <= SCALING_MODE_CENTER
+}
+/// The blit filter for the mode; pixel-exact modes must not blur
+pub fn filter(mode: ScalingMode) -> VkFilter
+{
+    if mode == SCALING_MODE_INTEGER || mode == SCALING_MODE_CENTER { VK_FILTER_NEAREST } else { VK_FILTER_LINEAR }
+}
+
+/// An area of an image in texels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect
+{
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32
+}
+impl Rect
+{
+    pub fn new(x: i32, y: i32, width: u32, height: u32) -> Self { Rect { x, y, width, height } }
+    /// The whole image
+    pub fn of_extent(extent: VkExtent2D) -> Self { Rect::new(0, 0, extent.width, extent.height) }
+
+    pub fn is_empty(&self) -> bool { self.width == 0 || self.height == 0 }
+    /// The overlapping part of the two rects, None if they do not overlap
+    pub fn intersection(&self, other: &Rect) -> Option<Rect>
+    {
+        let (left, top) = (self.x.max(other.x) as i64, self.y.max(other.y) as i64);
+        let right = (self.x as i64 + self.width as i64).min(other.x as i64 + other.width as i64);
+        let bottom = (self.y as i64 + self.height as i64).min(other.y as i64 + other.height as i64);
+        if right <= left || bottom <= top { return None; }
+
+        Some(Rect::new(left as _, top as _, (right - left) as _, (bottom - top) as _))
+    }
+    /// true if the rect is the whole image of `extent`
+    pub fn covers(&self, extent: VkExtent2D) -> bool { *self == Rect::of_extent(extent) }
+    /// The corners as the `srcOffsets`/`dstOffsets` of `VkImageBlit`
+    pub fn blit_offsets(&self) -> [VkOffset3D; 2]
+    {
+        [
+            VkOffset3D { x: self.x, y: self.y, z: 0 },
+            VkOffset3D { x: self.x + self.width as i32, y: self.y + self.height as i32, z: 1 }
+        ]
+    }
+}
+
+/// Scales a span of `src_len` by `num / den` and centers it in `dst_len`, cropping the source if it overflows.
+/// Returns (offset in source, length in source, offset in destination, length in destination).
+fn place_axis(src_len: u32, dst_len: u32, num: u64, den: u64) -> (u32, u32, u32, u32)
+{
+    let scaled = src_len as u64 * num / den;
+    if scaled <= dst_len as u64
+    {
+        return (0, src_len, (dst_len - scaled as u32) / 2, scaled as u32);
+    }
+
+    let visible = (dst_len as u64 * den / num) as u32;
+    ((src_len - visible) / 2, visible, 0, dst_len)
+}
+/// Where `src` of the frame goes in a window of `dst` with the mode: (source rect, destination rect).
+/// The part of the window outside the destination rect is not covered by the frame.
+/// Returns None if nothing of the frame would be visible.
+pub fn place(src: Rect, dst: VkExtent2D, mode: ScalingMode) -> Option<(Rect, Rect)>
+{
+    if src.is_empty() || dst.width == 0 || dst.height == 0 { return None; }
+
+    let (sw, sh, dw, dh) = (src.width as u64, src.height as u64, dst.width as u64, dst.height as u64);
+    // the window is relatively wider than the frame
+    let wider = dw * sh > dh * sw;
+    let (num, den) = match mode
+    {
+        SCALING_MODE_STRETCH => return Some((src, Rect::of_extent(dst))),
+        SCALING_MODE_FIT => if wider { (dh, sh) } else { (dw, sw) },
+        SCALING_MODE_FILL => if wider { (dw, sw) } else { (dh, sh) },
+        SCALING_MODE_INTEGER => ((dw / sw).min(dh / sh).max(1), 1),
+        _ => (1, 1)
+    };
+    let (sx, sw, dx, dw) = place_axis(src.width, dst.width, num, den);
+    let (sy, sh, dy, dh) = place_axis(src.height, dst.height, num, den);
+    let (src, dst) = (Rect::new(src.x + sx as i32, src.y + sy as i32, sw, sh), Rect::new(dx as _, dy as _, dw, dh));
+    if src.is_empty() || dst.is_empty() { None } else { Some((src, dst)) }
+}
@@ -0,0 +1,232 @@
+//! On-demand Screenshot Export to PNG
+
+use bedrock::vk::*;
+use libc::*;
+use lazy_static::*;
+use log::*;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use crate::error::InterceptorError;
+use crate::readback::{ReadbackFrame, ReadbackPool};
+use crate::sink::{FrameSink, SinkResult};
+use crate::settings;
+use crate::transform::Transform;
+use crate::unity::{UnityVulkanInstance, UnityVulkanImage};
+
+pub type ScreenshotStatus = c_int;
+/// No screenshot has been requested yet
+pub const SCREENSHOT_STATUS_IDLE: ScreenshotStatus = 0;
+/// Waiting for the next intercepted frame to be read back
+pub const SCREENSHOT_STATUS_PENDING: ScreenshotStatus = 1;
+/// The worker thread is encoding the PNG file
+pub const SCREENSHOT_STATUS_WRITING: ScreenshotStatus = 2;
+pub const SCREENSHOT_STATUS_COMPLETED: ScreenshotStatus = 3;
+pub const SCREENSHOT_STATUS_FAILED: ScreenshotStatus = -1;
+
+enum Request
+{
+    None,
+    /// waiting for a frame to be assigned
+    Waiting(PathBuf),
+    /// readback of the frame is in flight
+    InFlight(PathBuf, u64)
+}
+struct State
+{
+    status: ScreenshotStatus,
+    request: Request
+}
+lazy_static!{
+    static ref STATE: Mutex<State> = Mutex::new(State { status: SCREENSHOT_STATUS_IDLE, request: Request::None });
+}
+/// Locks the request state; a panic caught while it was locked does not make it unusable
+fn state() -> std::sync::MutexGuard<'static, State>
+{
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Memory layout of a readback frame as seen by the PNG encoder
+#[derive(Clone, Copy)]
+struct PixelLayout
+{
+    /// channels are stored in B, G, R, A order
+    bgra: bool,
+    /// values are sRGB encoded (otherwise displayed as is)
+    srgb: bool
+}
+fn pixel_layout(format: VkFormat) -> Option<PixelLayout>
+{
+    match format
+    {
+        VK_FORMAT_R8G8B8A8_UNORM => Some(PixelLayout { bgra: false, srgb: false }),
+        VK_FORMAT_R8G8B8A8_SRGB => Some(PixelLayout { bgra: false, srgb: true }),
+        VK_FORMAT_B8G8R8A8_UNORM => Some(PixelLayout { bgra: true, srgb: false }),
+        VK_FORMAT_B8G8R8A8_SRGB => Some(PixelLayout { bgra: true, srgb: true }),
+        _ => None
+    }
+}
+/// true if frames of this format can be written as PNG
+fn supports_format(format: VkFormat) -> bool { pixel_layout(format).is_some() }
+
+/// Queues a screenshot of the next intercepted frame. Returns false if another one is still in progress.
+pub fn request(path: PathBuf) -> bool
+{
+    let mut st = state();
+    if st.status == SCREENSHOT_STATUS_PENDING || st.status == SCREENSHOT_STATUS_WRITING { return false; }
+
+    st.status = SCREENSHOT_STATUS_PENDING;
+    st.request = Request::Waiting(path);
+    true
+}
+pub fn status() -> ScreenshotStatus { state().status }
+
+/// true while a requested screenshot has not been assigned a frame yet
+fn wants_frame() -> bool
+{
+    match state().request { Request::Waiting(_) => true, _ => false }
+}
+/// Assigns the readback frame that the waiting request will be written from
+fn bind_frame(frame_number: u64)
+{
+    let mut st = state();
+    if let Request::Waiting(_) = st.request
+    {
+        if let Request::Waiting(path) = std::mem::replace(&mut st.request, Request::None)
+        {
+            st.request = Request::InFlight(path, frame_number);
+        }
+    }
+}
+/// Gives up the current request
+fn fail(reason: &str)
+{
+    let mut st = state();
+    error!("Interceptor: screenshot failed: {}", reason);
+    st.status = SCREENSHOT_STATUS_FAILED;
+    st.request = Request::None;
+}
+
+/// Receives readback frames and starts writing the one bound to the request
+fn on_readback(frame: &ReadbackFrame)
+{
+    let path = {
+        let mut st = state();
+        match st.request
+        {
+            Request::InFlight(_, n) if n == frame.frame_number => (),
+            _ => return
+        }
+        st.status = SCREENSHOT_STATUS_WRITING;
+        match std::mem::replace(&mut st.request, Request::None)
+        {
+            Request::InFlight(path, _) => path,
+            _ => unreachable!()
+        }
+    };
+    let layout = match pixel_layout(frame.format)
+    {
+        Some(l) => l,
+        None => { fail("unsupported render buffer format"); return; }
+    };
+
+    // the staging memory is recycled after this call; take a tightly packed copy for the worker
+    let row_bytes = frame.extent.width as usize * 4;
+    let mut pixels = Vec::with_capacity(row_bytes * frame.extent.height as usize);
+    for y in 0 .. frame.extent.height as usize
+    {
+        pixels.extend_from_slice(&frame.data[y * frame.row_pitch .. y * frame.row_pitch + row_bytes]);
+    }
+    let (width, height) = (frame.extent.width, frame.extent.height);
+
+    let spawned = std::thread::Builder::new().name("RenderingInterceptor Screenshot Writer".to_owned()).spawn(move ||
+    {
+        let r = write_png(&path, width, height, pixels, layout);
+        let mut st = state();
+        match r
+        {
+            Ok(()) =>
+            {
+                info!("Interceptor: screenshot written to {}", path.display());
+                st.status = SCREENSHOT_STATUS_COMPLETED;
+            },
+            Err(e) =>
+            {
+                error!("Interceptor: writing screenshot to {} failed: {}", path.display(), e);
+                st.status = SCREENSHOT_STATUS_FAILED;
+            }
+        }
+    });
+    if spawned.is_err() { fail("unable to spawn the writer thread"); }
+}
+
+/// Writes the frame following a request through its own single-slot readback pool.
+/// The copy is waited for right away, as no further frame may come to collect it.
+pub struct ScreenshotSink
+{
+    pool: ReadbackPool
+}
+impl ScreenshotSink
+{
+    pub fn new(instance: &UnityVulkanInstance) -> Result<Self, InterceptorError>
+    {
+        Ok(ScreenshotSink { pool: ReadbackPool::new(instance, 1)? })
+    }
+}
+impl FrameSink for ScreenshotSink
+{
+    fn name(&self) -> &str { "screenshot" }
+    fn process(&mut self, frame: &UnityVulkanImage) -> SinkResult
+    {
+        self.pool.poll(on_readback);
+        if wants_frame()
+        {
+            if !supports_format(frame.format)
+            {
+                fail("unsupported render buffer format");
+                return Ok(());
+            }
+            let region = match settings::current().source_rect(frame.extent)
+            {
+                Some(r) => r,
+                None => { fail("the capture region is outside the frame"); return Ok(()); }
+            };
+            if let Some(n) = self.pool.enqueue(frame, region)?
+            {
+                bind_frame(n);
+                self.pool.flush(on_readback);
+            }
+        }
+
+        Ok(())
+    }
+    fn set_transform(&mut self, transform: Transform) -> bool { self.pool.set_transform(transform); true }
+    fn flush(&mut self) -> SinkResult { self.pool.flush(on_readback); Ok(()) }
+}
+
+fn write_png(path: &Path, width: u32, height: u32, mut pixels: Vec<u8>, layout: PixelLayout) -> Result<(), Box<dyn std::error::Error>>
+{
+    for px in pixels.chunks_exact_mut(4)
+    {
+        if layout.bgra { px.swap(0, 2); }
+        // the alpha channel of the render buffer is not what the player sees
+        px[3] = 0xff;
+    }
+
+    let fp = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let mut enc = png::Encoder::new(fp, width, height);
+    enc.set_color(png::ColorType::Rgba);
+    enc.set_depth(png::BitDepth::Eight);
+    if layout.srgb
+    {
+        enc.set_srgb(png::SrgbRenderingIntent::Perceptual);
+    }
+    else
+    {
+        // UNORM buffers reach the display without any conversion
+        enc.set_source_gamma(png::ScaledFloat::new(1.0 / 2.2));
+    }
+    let mut w = enc.write_header()?;
+    w.write_image_data(&pixels)?;
+
+    Ok(())
+}
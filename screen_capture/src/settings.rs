@@ -0,0 +1,60 @@
+//! Runtime Capture Settings
+
+use bedrock::vk::*;
+use lazy_static::*;
+use std::sync::RwLock;
+use crate::scaling::{ScalingMode, SCALING_MODE_STRETCH, Rect};
+
+/// Mirror frames that may be queued on the GPU before the render thread waits for the oldest one
+pub const DEFAULT_FRAMES_IN_FLIGHT: u32 = 2;
+pub const MAX_FRAMES_IN_FLIGHT: u32 = 8;
+
+/// Options changed from the host at any time. They survive graphics device re-initialization
+/// and are picked up by the outputs on their next frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureSettings
+{
+    pub frames_in_flight: u32,
+    /// How the frame is placed in the mirror window
+    pub scaling_mode: ScalingMode,
+    /// Part of the frame passed to every output; None for the whole frame
+    pub capture_region: Option<Rect>
+}
+impl Default for CaptureSettings
+{
+    fn default() -> Self
+    {
+        CaptureSettings { frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT, scaling_mode: SCALING_MODE_STRETCH, capture_region: None }
+    }
+}
+impl CaptureSettings
+{
+    /// The part of a frame of `extent` to capture: the capture region clipped to the frame.
+    /// None if the region lies outside the frame.
+    pub fn source_rect(&self, extent: VkExtent3D) -> Option<Rect>
+    {
+        let frame = Rect::new(0, 0, extent.width, extent.height);
+        match self.capture_region
+        {
+            Some(ref r) => r.intersection(&frame),
+            None => if frame.is_empty() { None } else { Some(frame) }
+        }
+    }
+}
+
+lazy_static!{
+    static ref SETTINGS: RwLock<CaptureSettings> = RwLock::new(CaptureSettings::default());
+}
+/// A snapshot of the current settings, with the capture region of the event being handled (if any)
+pub fn current() -> CaptureSettings
+{
+    let mut s = SETTINGS.read().unwrap_or_else(|e| e.into_inner()).clone();
+    if let Some(r) = crate::event::region_override() { s.capture_region = Some(r); }
+    s
+}
+pub fn update<F: FnOnce(&mut CaptureSettings)>(f: F)
+{
+    f(&mut SETTINGS.write().unwrap_or_else(|e| e.into_inner()));
+}
+/// Restores the defaults
+pub fn reset() { update(|s| *s = CaptureSettings::default()); }
@@ -0,0 +1,139 @@
+//! Frame Outputs and Fan-out Registry
+
+use log::*;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use crate::error::InterceptorError;
+use crate::unity::UnityVulkanImage;
+use crate::transform::Transform;
+
+pub type SinkResult = Result<(), InterceptorError>;
+pub type SinkId = u32;
+
+/// Destination of intercepted frames (window, file, shared memory, network, ...)
+pub trait FrameSink
+{
+    /// Short name used in log messages
+    fn name(&self) -> &str;
+    /// Called on the render thread for every intercepted frame while the sink is enabled.
+    /// `frame` is in a layout readable by transfer operations and must not be modified.
+    fn process(&mut self, frame: &UnityVulkanImage) -> SinkResult;
+    /// Changes the orientation of the frames this sink outputs, from the next frame on.
+    /// Returns false if the sink does not support transforms.
+    fn set_transform(&mut self, _transform: Transform) -> bool { false }
+    /// Completes the work of the frames given so far (e.g. delivers frames still being copied).
+    /// Called on the render thread by a flush event.
+    fn flush(&mut self) -> SinkResult { Ok(()) }
+}
+
+struct SinkEntry
+{
+    id: SinkId,
+    enabled: bool,
+    /// the last transform the sink accepted, given again to a replacement
+    transform: Transform,
+    sink: Box<dyn FrameSink>
+}
+
+/// Routes one intercepted frame to every enabled sink in registration order
+pub struct SinkRegistry
+{
+    entries: Vec<SinkEntry>,
+    next_id: SinkId
+}
+impl SinkRegistry
+{
+    pub fn new() -> Self
+    {
+        SinkRegistry { entries: Vec::new(), next_id: 0 }
+    }
+
+    /// Registers an enabled sink and returns its id
+    pub fn add(&mut self, sink: Box<dyn FrameSink>) -> SinkId
+    {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.push(SinkEntry { id, enabled: true, transform: Transform::IDENTITY, sink });
+
+        id
+    }
+    pub fn remove(&mut self, id: SinkId) -> Option<Box<dyn FrameSink>>
+    {
+        let p = self.entries.iter().position(|e| e.id == id)?;
+        Some(self.entries.remove(p).sink)
+    }
+    /// Puts `sink` in place of the sink `id`, keeping its id, state and transform, and returns the replaced one
+    pub fn replace(&mut self, id: SinkId, mut sink: Box<dyn FrameSink>) -> Option<Box<dyn FrameSink>>
+    {
+        let e = self.entries.iter_mut().find(|e| e.id == id)?;
+        if !e.transform.is_identity() && !sink.set_transform(e.transform)
+        {
+            warn!("Interceptor: sink #{} ({}) does not support transforms", id, sink.name());
+        }
+        Some(std::mem::replace(&mut e.sink, sink))
+    }
+    /// Drops every sink, the last registered first
+    pub fn clear(&mut self)
+    {
+        while let Some(e) = self.entries.pop() { drop(e); }
+    }
+    /// Returns false if no sink has the id
+    pub fn set_enabled(&mut self, id: SinkId, enabled: bool) -> bool
+    {
+        match self.entries.iter_mut().find(|e| e.id == id)
+        {
+            Some(e) => { e.enabled = enabled; true },
+            None => false
+        }
+    }
+    pub fn is_enabled(&self, id: SinkId) -> Option<bool>
+    {
+        self.entries.iter().find(|e| e.id == id).map(|e| e.enabled)
+    }
+    /// Returns false if no sink has the id or the sink does not support transforms
+    pub fn set_transform(&mut self, id: SinkId, transform: Transform) -> bool
+    {
+        match self.entries.iter_mut().find(|e| e.id == id)
+        {
+            Some(e) if e.sink.set_transform(transform) => { e.transform = transform; true },
+            _ => false
+        }
+    }
+
+    /// Hands the frame to every enabled sink.
+    /// A failing sink does not affect the others; a panicking one is disabled.
+    pub fn dispatch(&mut self, frame: &UnityVulkanImage) { self.dispatch_except(frame, &[]); }
+    /// Hands the frame to every enabled sink not in `skip`, which are fed from elsewhere this time
+    pub fn dispatch_except(&mut self, frame: &UnityVulkanImage, skip: &[SinkId])
+    {
+        for e in self.entries.iter_mut().filter(|e| e.enabled && !skip.contains(&e.id)) { Self::process(e, frame); }
+    }
+    /// Hands the frame to a single sink if it is enabled. Returns false if no sink has the id.
+    pub fn dispatch_to(&mut self, id: SinkId, frame: &UnityVulkanImage) -> bool
+    {
+        match self.entries.iter_mut().find(|e| e.id == id)
+        {
+            Some(e) => { if e.enabled { Self::process(e, frame); } true },
+            None => false
+        }
+    }
+    /// Lets every enabled sink complete the work of the frames given so far
+    pub fn flush(&mut self)
+    {
+        for e in self.entries.iter_mut().filter(|e| e.enabled) { Self::run(e, |s| s.flush()); }
+    }
+    fn process(e: &mut SinkEntry, frame: &UnityVulkanImage) { Self::run(e, |s| s.process(frame)); }
+    fn run<F: FnOnce(&mut dyn FrameSink) -> SinkResult>(e: &mut SinkEntry, f: F)
+    {
+        let sink = &mut *e.sink;
+        match catch_unwind(AssertUnwindSafe(|| f(sink)))
+        {
+            Ok(Ok(())) => (),
+            Ok(Err(r)) => warn!("Interceptor: sink #{} ({}) failed: {}", e.id, e.sink.name(), r),
+            Err(_) =>
+            {
+                error!("Interceptor: sink #{} ({}) panicked; disabled", e.id, e.sink.name());
+                e.enabled = false;
+            }
+        }
+    }
+}
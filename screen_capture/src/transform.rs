@@ -0,0 +1,361 @@
+//! Flip and Rotation of Captured Frames
+
+use bedrock::vk::*;
+use libc::*;
+use log::*;
+use crate::unity::*;
+use crate::error::{InterceptorError, vk_check};
+use crate::live_objects;
+use crate::readback::format_texel_size;
+use crate::scaling::Rect;
+
+/// Quarter turns clockwise in the lower 2 bits, combined with the flip flags.
+/// The flips are applied to the frame before it is rotated.
+pub type TransformFlags = c_int;
+pub const TRANSFORM_ROTATE_90: TransformFlags = 1;
+pub const TRANSFORM_ROTATE_180: TransformFlags = 2;
+pub const TRANSFORM_ROTATE_270: TransformFlags = 3;
+/// Mirrors left and right
+pub const TRANSFORM_FLIP_X: TransformFlags = 4;
+/// Mirrors top and bottom (for render buffers that are upside-down)
+pub const TRANSFORM_FLIP_Y: TransformFlags = 8;
+const TRANSFORM_ROTATION_MASK: TransformFlags = 3;
+
+/// A change of orientation, normalized to an optional swap of the axes followed by mirroring.
+/// Every combination of rotations and flips reduces to one of these 8 forms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Transform
+{
+    /// (x, y) -> (y, x)
+    pub transpose: bool,
+    /// Mirrors the transposed image left and right
+    pub flip_x: bool,
+    /// Mirrors the transposed image top and bottom
+    pub flip_y: bool
+}
+impl Transform
+{
+    pub const IDENTITY: Transform = Transform { transpose: false, flip_x: false, flip_y: false };
+
+    /// None if unknown flags are set
+    pub fn from_flags(flags: TransformFlags) -> Option<Self>
+    {
+        if flags & !(TRANSFORM_ROTATION_MASK | TRANSFORM_FLIP_X | TRANSFORM_FLIP_Y) != 0 { return None; }
+
+        let mut t = Transform { transpose: false, flip_x: flags & TRANSFORM_FLIP_X != 0, flip_y: flags & TRANSFORM_FLIP_Y != 0 };
+        for _ in 0 .. flags & TRANSFORM_ROTATION_MASK
+        {
+            // a quarter turn clockwise moves (x, y) to (h - 1 - y, x)
+            t = Transform { transpose: !t.transpose, flip_x: !t.flip_y, flip_y: t.flip_x };
+        }
+        Some(t)
+    }
+    pub fn is_identity(&self) -> bool { *self == Transform::IDENTITY }
+
+    /// Size of a `width` x `height` image after the transform
+    pub fn output_extent(&self, width: u32, height: u32) -> (u32, u32)
+    {
+        if self.transpose { (height, width) } else { (width, height) }
+    }
+    /// Where the texel (x, y) of a `width` x `height` image ends up
+    pub fn map(&self, x: u32, y: u32, width: u32, height: u32) -> (u32, u32)
+    {
+        let (ow, oh) = self.output_extent(width, height);
+        let (tx, ty) = if self.transpose { (y, x) } else { (x, y) };
+        (if self.flip_x { ow - 1 - tx } else { tx }, if self.flip_y { oh - 1 - ty } else { ty })
+    }
+
+    /// Transforms the texels of `src` (`row_pitch` bytes per row) into `dst`, tightly packed.
+    /// Returns the row pitch of `dst`.
+    pub fn apply(&self, src: &[u8], width: u32, height: u32, row_pitch: usize, texel_size: usize, dst: &mut Vec<u8>) -> usize
+    {
+        let (ow, oh) = self.output_extent(width, height);
+        let out_pitch = ow as usize * texel_size;
+        dst.clear();
+        dst.resize(out_pitch * oh as usize, 0);
+        for y in 0 .. height
+        {
+            let row = &src[y as usize * row_pitch ..];
+            for x in 0 .. width
+            {
+                let (ox, oy) = self.map(x, y, width, height);
+                let (s, d) = (x as usize * texel_size, oy as usize * out_pitch + ox as usize * texel_size);
+                dst[d .. d + texel_size].copy_from_slice(&row[s .. s + texel_size]);
+            }
+        }
+
+        out_pitch
+    }
+
+    /// `srcOffsets` of a blit reading `crop` of the transformed image from `image_rect`, an area that
+    /// holds the image with only the mirroring left to apply (the axes are already swapped, if needed).
+    /// Mirrored axes are expressed by reversed offsets.
+    pub fn blit_source_offsets(&self, image_rect: Rect, crop: Rect) -> [VkOffset3D; 2]
+    {
+        let axis = |flip: bool, origin: i32, len: u32, start: i32, crop_len: u32| if flip
+        {
+            (origin + len as i32 - start, origin + len as i32 - start - crop_len as i32)
+        }
+        else { (origin + start, origin + start + crop_len as i32) };
+        let (x0, x1) = axis(self.flip_x, image_rect.x, image_rect.width, crop.x, crop.width);
+        let (y0, y1) = axis(self.flip_y, image_rect.y, image_rect.height, crop.y, crop.height);
+
+        [VkOffset3D { x: x0, y: y0, z: 0 }, VkOffset3D { x: x1, y: y1, z: 1 }]
+    }
+}
+
+/// Regions of vkCmdCopyBufferToImage that write a tightly packed `width` x `height` buffer into a
+/// `height` x `width` image with the axes swapped: each row of the buffer becomes a column of the image,
+/// by reading it as an image 1 texel wide.
+pub fn transpose_regions(width: u32, height: u32, texel_size: u32) -> Vec<VkBufferImageCopy>
+{
+    (0 .. height).map(|y| VkBufferImageCopy
+    {
+        bufferOffset: y as VkDeviceSize * width as VkDeviceSize * texel_size as VkDeviceSize, bufferRowLength: 1, bufferImageHeight: 0,
+        imageSubresource: VkImageSubresourceLayers
+        {
+            aspectMask: VK_IMAGE_ASPECT_COLOR_BIT,
+            baseArrayLayer: 0,
+            layerCount: 1,
+            mipLevel: 0
+        },
+        imageOffset: VkOffset3D { x: y as _, y: 0, z: 0 },
+        imageExtent: VkExtent3D { width: 1, height: width, depth: 1 }
+    }).collect()
+}
+
+/// Swaps the axes of a region of a frame on the GPU (blits cannot), through a staging buffer and an image
+/// that are kept while the region size and format stay the same
+pub struct Transposer
+{
+    device: VkDevice,
+    memory_properties: VkPhysicalDeviceMemoryProperties,
+    buffer: VkBuffer,
+    buffer_memory: VkDeviceMemory,
+    image: VkImage,
+    image_memory: VkDeviceMemory,
+    /// extent and format of the transposed image
+    image_extent: VkExtent2D,
+    image_format: VkFormat,
+    texel_size: u32,
+    fp_create_buffer: PFN_vkCreateBuffer,
+    fp_destroy_buffer: PFN_vkDestroyBuffer,
+    fp_get_buffer_memory_requirements: PFN_vkGetBufferMemoryRequirements,
+    fp_bind_buffer_memory: PFN_vkBindBufferMemory,
+    fp_create_image: PFN_vkCreateImage,
+    fp_destroy_image: PFN_vkDestroyImage,
+    fp_get_image_memory_requirements: PFN_vkGetImageMemoryRequirements,
+    fp_bind_image_memory: PFN_vkBindImageMemory,
+    fp_allocate_memory: PFN_vkAllocateMemory,
+    fp_free_memory: PFN_vkFreeMemory,
+    fp_device_wait_idle: PFN_vkDeviceWaitIdle,
+    fp_cmd_copy_image_to_buffer: PFN_vkCmdCopyImageToBuffer,
+    fp_cmd_copy_buffer_to_image: PFN_vkCmdCopyBufferToImage,
+    fp_cmd_pipeline_barrier: PFN_vkCmdPipelineBarrier
+}
+impl Transposer
+{
+    pub fn new(instance: &UnityVulkanInstance) -> Result<Self, InterceptorError>
+    {
+        let fp_get_physical_device_memory_properties: PFN_vkGetPhysicalDeviceMemoryProperties = load_instance_proc!(instance, "vkGetPhysicalDeviceMemoryProperties")?;
+        let mut memory_properties = std::mem::MaybeUninit::uninit();
+        fp_get_physical_device_memory_properties(instance.physical_device, memory_properties.as_mut_ptr());
+
+        Ok(Transposer
+        {
+            device: instance.device,
+            memory_properties: unsafe { memory_properties.assume_init() },
+            buffer: std::ptr::null_mut(), buffer_memory: std::ptr::null_mut(),
+            image: std::ptr::null_mut(), image_memory: std::ptr::null_mut(),
+            image_extent: VkExtent2D { width: 0, height: 0 }, image_format: VK_FORMAT_UNDEFINED, texel_size: 0,
+            fp_create_buffer: load_instance_proc!(instance, "vkCreateBuffer")?,
+            fp_destroy_buffer: load_instance_proc!(instance, "vkDestroyBuffer")?,
+            fp_get_buffer_memory_requirements: load_instance_proc!(instance, "vkGetBufferMemoryRequirements")?,
+            fp_bind_buffer_memory: load_instance_proc!(instance, "vkBindBufferMemory")?,
+            fp_create_image: load_instance_proc!(instance, "vkCreateImage")?,
+            fp_destroy_image: load_instance_proc!(instance, "vkDestroyImage")?,
+            fp_get_image_memory_requirements: load_instance_proc!(instance, "vkGetImageMemoryRequirements")?,
+            fp_bind_image_memory: load_instance_proc!(instance, "vkBindImageMemory")?,
+            fp_allocate_memory: load_instance_proc!(instance, "vkAllocateMemory")?,
+            fp_free_memory: load_instance_proc!(instance, "vkFreeMemory")?,
+            fp_device_wait_idle: load_instance_proc!(instance, "vkDeviceWaitIdle")?,
+            fp_cmd_copy_image_to_buffer: load_instance_proc!(instance, "vkCmdCopyImageToBuffer")?,
+            fp_cmd_copy_buffer_to_image: load_instance_proc!(instance, "vkCmdCopyBufferToImage")?,
+            fp_cmd_pipeline_barrier: load_instance_proc!(instance, "vkCmdPipelineBarrier")?
+        })
+    }
+
+    /// Makes sure the buffer and the image fit a `region` of a frame in `format`.
+    /// Called before anything of the frame is recorded, as it may fail.
+    pub fn prepare(&mut self, format: VkFormat, region: Rect) -> Result<(), InterceptorError>
+    {
+        let texel_size = format_texel_size(format).ok_or(InterceptorError::UnsupportedFormat(format))?;
+        let extent = VkExtent2D { width: region.height, height: region.width };
+        if !self.image.is_null() && extent.width == self.image_extent.width && extent.height == self.image_extent.height && format == self.image_format
+        {
+            return Ok(());
+        }
+
+        self.allocate(extent, format, region.width as VkDeviceSize * region.height as VkDeviceSize * texel_size as VkDeviceSize)?;
+        self.texel_size = texel_size;
+        Ok(())
+    }
+    /// Records the transposition of `region` of `frame` into `cbuf` (`prepare` must have succeeded for them).
+    /// Returns the image holding the result (`region.height` x `region.width`) in TRANSFER_SRC_OPTIMAL layout.
+    pub fn record(&self, cbuf: VkCommandBuffer, frame: &UnityVulkanImage, region: Rect) -> VkImage
+    {
+        let color_range = VkImageSubresourceRange
+        {
+            aspectMask: VK_IMAGE_ASPECT_COLOR_BIT,
+            baseArrayLayer: 0, layerCount: 1,
+            baseMipLevel: 0, levelCount: 1
+        };
+        // the previous frame may still be reading the buffer and the image
+        let buffer_writable = VkBufferMemoryBarrier
+        {
+            buffer: self.buffer, offset: 0, size: VK_WHOLE_SIZE,
+            srcAccessMask: VK_ACCESS_TRANSFER_READ_BIT, dstAccessMask: VK_ACCESS_TRANSFER_WRITE_BIT,
+            srcQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED, dstQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED,
+            .. Default::default()
+        };
+        let buffer_readable = VkBufferMemoryBarrier
+        {
+            buffer: self.buffer, offset: 0, size: VK_WHOLE_SIZE,
+            srcAccessMask: VK_ACCESS_TRANSFER_WRITE_BIT, dstAccessMask: VK_ACCESS_TRANSFER_READ_BIT,
+            srcQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED, dstQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED,
+            .. Default::default()
+        };
+        let image_writable = VkImageMemoryBarrier
+        {
+            image: self.image, subresourceRange: color_range.clone(),
+            oldLayout: VK_IMAGE_LAYOUT_UNDEFINED, newLayout: VK_IMAGE_LAYOUT_TRANSFER_DST_OPTIMAL,
+            srcAccessMask: 0, dstAccessMask: VK_ACCESS_TRANSFER_WRITE_BIT,
+            srcQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED, dstQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED,
+            .. Default::default()
+        };
+        let image_readable = VkImageMemoryBarrier
+        {
+            image: self.image, subresourceRange: color_range,
+            oldLayout: VK_IMAGE_LAYOUT_TRANSFER_DST_OPTIMAL, newLayout: VK_IMAGE_LAYOUT_TRANSFER_SRC_OPTIMAL,
+            srcAccessMask: VK_ACCESS_TRANSFER_WRITE_BIT, dstAccessMask: VK_ACCESS_TRANSFER_READ_BIT,
+            srcQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED, dstQueueFamilyIndex: VK_QUEUE_FAMILY_IGNORED,
+            .. Default::default()
+        };
+        let rows = VkBufferImageCopy
+        {
+            bufferOffset: 0, bufferRowLength: 0, bufferImageHeight: 0,
+            imageSubresource: VkImageSubresourceLayers
+            {
+                aspectMask: VK_IMAGE_ASPECT_COLOR_BIT,
+                baseArrayLayer: 0,
+                layerCount: 1,
+                mipLevel: 0
+            },
+            imageOffset: VkOffset3D { x: region.x, y: region.y, z: 0 },
+            imageExtent: VkExtent3D { width: region.width, height: region.height, depth: 1 }
+        };
+        let columns = transpose_regions(region.width, region.height, self.texel_size);
+
+        (self.fp_cmd_pipeline_barrier)(cbuf, VK_PIPELINE_STAGE_TRANSFER_BIT, VK_PIPELINE_STAGE_TRANSFER_BIT, 0,
+            0, std::ptr::null(), 1, &buffer_writable, 0, std::ptr::null());
+        (self.fp_cmd_copy_image_to_buffer)(cbuf, frame.image, frame.layout, self.buffer, 1, &rows);
+        (self.fp_cmd_pipeline_barrier)(cbuf, VK_PIPELINE_STAGE_TRANSFER_BIT, VK_PIPELINE_STAGE_TRANSFER_BIT, 0,
+            0, std::ptr::null(), 1, &buffer_readable, 1, &image_writable);
+        (self.fp_cmd_copy_buffer_to_image)(cbuf, self.buffer, self.image, VK_IMAGE_LAYOUT_TRANSFER_DST_OPTIMAL,
+            columns.len() as _, columns.as_ptr());
+        (self.fp_cmd_pipeline_barrier)(cbuf, VK_PIPELINE_STAGE_TRANSFER_BIT, VK_PIPELINE_STAGE_TRANSFER_BIT, 0,
+            0, std::ptr::null(), 0, std::ptr::null(), 1, &image_readable);
+
+        self.image
+    }
+
+    /// Recreates the buffer and the image. Waits for the device as frames in flight may still use the old ones.
+    fn allocate(&mut self, extent: VkExtent2D, format: VkFormat, buffer_size: VkDeviceSize) -> Result<(), InterceptorError>
+    {
+        if !self.image.is_null() { (self.fp_device_wait_idle)(self.device); }
+        self.release();
+        trace!("Interceptor: allocating transposition target {}x{}", extent.width, extent.height);
+
+        let binfo = VkBufferCreateInfo
+        {
+            size: buffer_size,
+            usage: VK_BUFFER_USAGE_TRANSFER_SRC_BIT | VK_BUFFER_USAGE_TRANSFER_DST_BIT,
+            sharingMode: VK_SHARING_MODE_EXCLUSIVE,
+            .. Default::default()
+        };
+        let mut buffer = std::mem::MaybeUninit::uninit();
+        vk_check("vkCreateBuffer", (self.fp_create_buffer)(self.device, &binfo, std::ptr::null(), buffer.as_mut_ptr()))?;
+        live_objects::created("buffer");
+        self.buffer = unsafe { buffer.assume_init() };
+        let mut req = std::mem::MaybeUninit::uninit();
+        (self.fp_get_buffer_memory_requirements)(self.device, self.buffer, req.as_mut_ptr());
+        self.buffer_memory = self.allocate_memory(unsafe { req.assume_init() })?;
+        vk_check("vkBindBufferMemory", (self.fp_bind_buffer_memory)(self.device, self.buffer, self.buffer_memory, 0))?;
+
+        let iinfo = VkImageCreateInfo
+        {
+            imageType: VK_IMAGE_TYPE_2D,
+            format,
+            extent: VkExtent3D { width: extent.width, height: extent.height, depth: 1 },
+            mipLevels: 1, arrayLayers: 1,
+            samples: VK_SAMPLE_COUNT_1_BIT,
+            tiling: VK_IMAGE_TILING_OPTIMAL,
+            usage: VK_IMAGE_USAGE_TRANSFER_SRC_BIT | VK_IMAGE_USAGE_TRANSFER_DST_BIT,
+            sharingMode: VK_SHARING_MODE_EXCLUSIVE,
+            initialLayout: VK_IMAGE_LAYOUT_UNDEFINED,
+            .. Default::default()
+        };
+        let mut image = std::mem::MaybeUninit::uninit();
+        vk_check("vkCreateImage", (self.fp_create_image)(self.device, &iinfo, std::ptr::null(), image.as_mut_ptr()))?;
+        live_objects::created("image");
+        self.image = unsafe { image.assume_init() };
+        let mut req = std::mem::MaybeUninit::uninit();
+        (self.fp_get_image_memory_requirements)(self.device, self.image, req.as_mut_ptr());
+        self.image_memory = self.allocate_memory(unsafe { req.assume_init() })?;
+        vk_check("vkBindImageMemory", (self.fp_bind_image_memory)(self.device, self.image, self.image_memory, 0))?;
+        self.image_extent = extent;
+        self.image_format = format;
+
+        Ok(())
+    }
+    /// Device local memory if available; only the GPU touches it
+    fn allocate_memory(&self, req: VkMemoryRequirements) -> Result<VkDeviceMemory, InterceptorError>
+    {
+        let types = &self.memory_properties.memoryTypes[.. self.memory_properties.memoryTypeCount as usize];
+        let allowed = |i: usize| (req.memoryTypeBits & (1 << i)) != 0;
+        let memory_type_index = (0 .. types.len()).find(|&i| allowed(i) && (types[i].propertyFlags & VK_MEMORY_PROPERTY_DEVICE_LOCAL_BIT) != 0)
+            .or_else(|| (0 .. types.len()).find(|&i| allowed(i)))
+            .ok_or_else(|| InterceptorError::vulkan("vkAllocateMemory", VK_ERROR_OUT_OF_DEVICE_MEMORY))?;
+        let ainfo = VkMemoryAllocateInfo
+        {
+            allocationSize: req.size,
+            memoryTypeIndex: memory_type_index as _,
+            .. Default::default()
+        };
+        let mut memory = std::mem::MaybeUninit::uninit();
+        vk_check("vkAllocateMemory", (self.fp_allocate_memory)(self.device, &ainfo, std::ptr::null(), memory.as_mut_ptr()))?;
+        live_objects::created("memory");
+
+        Ok(unsafe { memory.assume_init() })
+    }
+    fn release(&mut self)
+    {
+        if !self.image.is_null() { (self.fp_destroy_image)(self.device, self.image, std::ptr::null()); live_objects::destroyed("image"); }
+        if !self.image_memory.is_null() { (self.fp_free_memory)(self.device, self.image_memory, std::ptr::null()); live_objects::destroyed("memory"); }
+        if !self.buffer.is_null() { (self.fp_destroy_buffer)(self.device, self.buffer, std::ptr::null()); live_objects::destroyed("buffer"); }
+        if !self.buffer_memory.is_null() { (self.fp_free_memory)(self.device, self.buffer_memory, std::ptr::null()); live_objects::destroyed("memory"); }
+        self.image = std::ptr::null_mut();
+        self.image_memory = std::ptr::null_mut();
+        self.buffer = std::ptr::null_mut();
+        self.buffer_memory = std::ptr::null_mut();
+        self.image_extent = VkExtent2D { width: 0, height: 0 };
+    }
+}
+impl Drop for Transposer
+{
+    fn drop(&mut self)
+    {
+        if !self.image.is_null() { (self.fp_device_wait_idle)(self.device); }
+        self.release();
+    }
+}
@@ -0,0 +1,63 @@
+//! Headless Backend (VK_EXT_headless_surface)
+#![allow(non_camel_case_types, non_upper_case_globals)]
+
+use bedrock::vk::*;
+use libc::*;
+use log::*;
+use crate::error::{InterceptorError, vk_check};
+use crate::unity::UnityVulkanInstance;
+use super::WindowBackend;
+
+const VK_STRUCTURE_TYPE_HEADLESS_SURFACE_CREATE_INFO_EXT: VkStructureType = 1000256000;
+
+#[repr(C)]
+pub struct VkHeadlessSurfaceCreateInfoEXT
+{
+    pub sType: VkStructureType,
+    pub pNext: *const c_void,
+    pub flags: VkFlags
+}
+pub type PFN_vkCreateHeadlessSurfaceEXT = extern "system" fn(instance: VkInstance, pCreateInfo: *const VkHeadlessSurfaceCreateInfoEXT,
+    pAllocator: *const VkAllocationCallbacks, pSurface: *mut VkSurfaceKHR) -> VkResult;
+
+/// A surface without any window system behind it.
+/// The presentation engine defines no extent for headless surfaces, so the size is fixed at creation.
+pub struct HeadlessWindow
+{
+    extent: VkExtent2D
+}
+impl HeadlessWindow
+{
+    pub fn new(width: u32, height: u32) -> Self
+    {
+        trace!("Interceptor: HeadlessWindow::new({}x{})", width, height);
+
+        HeadlessWindow { extent: VkExtent2D { width, height } }
+    }
+}
+impl WindowBackend for HeadlessWindow
+{
+    fn surface_extension(&self) -> &'static str { "VK_EXT_headless_surface" }
+    fn presentation_support(&self, _instance: &UnityVulkanInstance) -> Result<bool, InterceptorError>
+    {
+        // headless surfaces have no platform-specific query; vkGetPhysicalDeviceSurfaceSupportKHR decides
+        Ok(true)
+    }
+    fn create_surface(&self, instance: &UnityVulkanInstance) -> Result<VkSurfaceKHR, InterceptorError>
+    {
+        // missing if VK_EXT_headless_surface is not enabled on this instance
+        let fp_create_surface: PFN_vkCreateHeadlessSurfaceEXT = load_instance_proc!(instance, "vkCreateHeadlessSurfaceEXT")?;
+
+        let sinfo = VkHeadlessSurfaceCreateInfoEXT
+        {
+            sType: VK_STRUCTURE_TYPE_HEADLESS_SURFACE_CREATE_INFO_EXT,
+            pNext: std::ptr::null(),
+            flags: 0
+        };
+        let mut sptr = std::mem::MaybeUninit::uninit();
+        vk_check("vkCreateHeadlessSurfaceEXT", fp_create_surface(instance.instance, &sinfo, std::ptr::null(), sptr.as_mut_ptr()))?;
+
+        Ok(unsafe { sptr.assume_init() })
+    }
+    fn client_extent(&self) -> VkExtent2D { self.extent.clone() }
+}
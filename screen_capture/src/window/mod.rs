@@ -0,0 +1,164 @@
+//! Window System Backends for ExtRenderTarget
+
+use bedrock::vk::*;
+use log::*;
+use crate::error::InterceptorError;
+use crate::unity::UnityVulkanInstance;
+
+#[cfg(windows)] mod win32;
+#[cfg(windows)] pub use self::win32::Win32Window;
+#[cfg(target_os = "linux")] mod xcb;
+#[cfg(target_os = "linux")] pub use self::xcb::XcbWindow;
+mod headless;
+pub use self::headless::HeadlessWindow;
+
+pub const DEFAULT_WINDOW_TITLE: &'static str = "RenderingInterceptorTest";
+pub const DEFAULT_WINDOW_WIDTH: u32 = 1280;
+pub const DEFAULT_WINDOW_HEIGHT: u32 = DEFAULT_WINDOW_WIDTH * 9 / 16;
+/// Set this to run without any window system (`1` or `<width>x<height>`)
+pub const HEADLESS_ENV_NAME: &'static str = "RENDERING_INTERCEPTOR_HEADLESS";
+/// Set this to `1` to have Unity render offscreen and the plugin's window be the only one presenting (read at plugin load)
+pub const OFFSCREEN_ENV_NAME: &'static str = "RENDERING_INTERCEPTOR_OFFSCREEN";
+/// Present mode of the plugin's window (`fifo`, `fifo_relaxed`, `mailbox` or `immediate`)
+pub const PRESENT_MODE_ENV_NAME: &'static str = "RENDERING_INTERCEPTOR_PRESENT_MODE";
+/// Size and position of the plugin's window (`<width>x<height>` or `<width>x<height>+<x>+<y>`)
+pub const WINDOW_GEOMETRY_ENV_NAME: &'static str = "RENDERING_INTERCEPTOR_WINDOW";
+
+/// Client area size and position of a native window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowGeometry
+{
+    pub width: u32,
+    pub height: u32,
+    /// None lets the window system place the window
+    pub position: Option<(i32, i32)>
+}
+impl Default for WindowGeometry
+{
+    fn default() -> Self
+    {
+        WindowGeometry { width: DEFAULT_WINDOW_WIDTH, height: DEFAULT_WINDOW_HEIGHT, position: None }
+    }
+}
+
+/// Platform window that ExtRenderTarget presents into
+pub trait WindowBackend
+{
+    /// The instance extension surfaces of this window system are created with
+    fn surface_extension(&self) -> &'static str;
+    /// Checks whether the queue family can present to this window system
+    fn presentation_support(&self, instance: &UnityVulkanInstance) -> Result<bool, InterceptorError>;
+    /// Creates a VkSurfaceKHR bound to this window
+    fn create_surface(&self, instance: &UnityVulkanInstance) -> Result<VkSurfaceKHR, InterceptorError>;
+    /// Current size of the client area in pixels
+    fn client_extent(&self) -> VkExtent2D;
+    /// Handles the events the window system has queued. Returns false once the user has closed the window
+    fn process_events(&mut self) -> bool { true }
+}
+
+/// Parses the value of `RENDERING_INTERCEPTOR_HEADLESS` into the headless surface extent
+fn parse_headless_extent(v: &str) -> (u32, u32)
+{
+    let mut parts = v.splitn(2, 'x');
+    match (parts.next().and_then(|w| w.trim().parse().ok()), parts.next().and_then(|h| h.trim().parse().ok()))
+    {
+        (Some(w), Some(h)) if w > 0 && h > 0 => (w, h),
+        _ => (DEFAULT_WINDOW_WIDTH, DEFAULT_WINDOW_HEIGHT)
+    }
+}
+
+/// Parses the value of `RENDERING_INTERCEPTOR_WINDOW`. None if it is malformed or the size is empty.
+pub fn parse_window_geometry(v: &str) -> Option<WindowGeometry>
+{
+    let mut parts = v.trim().splitn(2, '+');
+    let mut size = parts.next()?.splitn(2, 'x');
+    let width = size.next()?.trim().parse().ok().filter(|&w: &u32| w > 0)?;
+    let height = size.next()?.trim().parse().ok().filter(|&h: &u32| h > 0)?;
+    let position = match parts.next()
+    {
+        Some(p) =>
+        {
+            let mut p = p.splitn(2, '+');
+            Some((p.next()?.trim().parse().ok()?, p.next()?.trim().parse().ok()?))
+        },
+        None => None
+    };
+    Some(WindowGeometry { width, height, position })
+}
+/// Parses the value of `RENDERING_INTERCEPTOR_PRESENT_MODE`
+pub fn parse_present_mode(v: &str) -> Option<VkPresentModeKHR>
+{
+    match v.trim().to_ascii_lowercase().as_str()
+    {
+        "fifo" => Some(VK_PRESENT_MODE_FIFO_KHR),
+        "fifo_relaxed" => Some(VK_PRESENT_MODE_FIFO_RELAXED_KHR),
+        "mailbox" => Some(VK_PRESENT_MODE_MAILBOX_KHR),
+        "immediate" => Some(VK_PRESENT_MODE_IMMEDIATE_KHR),
+        _ => None
+    }
+}
+/// The requested present mode if the surface supports it, otherwise FIFO or MAILBOX (whichever the surface lists first)
+pub fn choose_present_mode(available: &[VkPresentModeKHR], requested: Option<VkPresentModeKHR>) -> VkPresentModeKHR
+{
+    requested.filter(|m| available.contains(m))
+        .or_else(|| available.iter().cloned().find(|&m| m == VK_PRESENT_MODE_FIFO_KHR || m == VK_PRESENT_MODE_MAILBOX_KHR))
+        .or_else(|| available.first().cloned())
+        .unwrap_or(VK_PRESENT_MODE_FIFO_KHR)
+}
+
+/// The present mode requested by the environment
+pub fn requested_present_mode() -> Option<VkPresentModeKHR>
+{
+    let v = std::env::var(PRESENT_MODE_ENV_NAME).ok()?;
+    let mode = parse_present_mode(&v);
+    if mode.is_none() { warn!("Interceptor: unknown present mode {:?}", v); }
+    mode
+}
+/// Whether the environment asks for Unity to render offscreen
+pub fn offscreen_requested() -> bool
+{
+    std::env::var(OFFSCREEN_ENV_NAME).map_or(false, |v| v.trim() == "1")
+}
+fn requested_geometry() -> WindowGeometry
+{
+    match std::env::var(WINDOW_GEOMETRY_ENV_NAME)
+    {
+        Ok(v) => parse_window_geometry(&v).unwrap_or_else(||
+        {
+            warn!("Interceptor: malformed window geometry {:?}", v);
+            WindowGeometry::default()
+        }),
+        Err(_) => WindowGeometry::default()
+    }
+}
+
+/// Creates the native window backend for the running platform,
+/// or a headless one if requested by the environment or no display is available
+pub fn create_default_backend() -> Result<Box<dyn WindowBackend>, InterceptorError>
+{
+    if let Ok(v) = std::env::var(HEADLESS_ENV_NAME)
+    {
+        let (w, h) = parse_headless_extent(&v);
+        info!("Interceptor: headless presentation requested ({}x{})", w, h);
+        return Ok(Box::new(HeadlessWindow::new(w, h)));
+    }
+    let geometry = requested_geometry();
+    #[cfg(target_os = "linux")]
+    {
+        if std::env::var_os("DISPLAY").is_none()
+        {
+            info!("Interceptor: DISPLAY is not set, falling back to headless presentation");
+            return Ok(Box::new(HeadlessWindow::new(geometry.width, geometry.height)));
+        }
+    }
+
+    #[cfg(windows)]
+    { Ok(Box::new(Win32Window::new(DEFAULT_WINDOW_TITLE, &geometry)?)) }
+    #[cfg(target_os = "linux")]
+    { Ok(Box::new(XcbWindow::new(DEFAULT_WINDOW_TITLE, &geometry)?)) }
+    #[cfg(not(any(windows, target_os = "linux")))]
+    {
+        info!("Interceptor: no native window backend for this platform, falling back to headless presentation");
+        Ok(Box::new(HeadlessWindow::new(geometry.width, geometry.height)))
+    }
+}
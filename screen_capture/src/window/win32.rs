@@ -0,0 +1,129 @@
+//! Win32 Window Backend (VK_KHR_win32_surface)
+
+use bedrock::vk::*;
+use log::*;
+use std::ptr::null_mut;
+use winapi::um::winuser::*;
+use winapi::um::libloaderapi::GetModuleHandleA;
+use winapi::um::errhandlingapi::GetLastError;
+use winapi::shared::winerror::ERROR_CLASS_ALREADY_EXISTS;
+use winapi::shared::windef::{RECT, HWND};
+use winapi::shared::minwindef::{LRESULT, WPARAM, LPARAM, UINT, HINSTANCE};
+use crate::error::{InterceptorError, vk_check};
+use crate::unity::UnityVulkanInstance;
+use crate::live_objects;
+use super::{WindowBackend, WindowGeometry};
+
+const WINDOW_CLASS_NAME: &'static [u8] = b"com.cterm2.unity.render_interceptor.MainWindow\0";
+
+pub struct Win32Window
+{
+    handle: HWND,
+    hinstance: HINSTANCE
+}
+impl Win32Window
+{
+    pub fn new(title: &str, geometry: &WindowGeometry) -> Result<Self, InterceptorError>
+    {
+        trace!("Interceptor: Win32Window::new");
+
+        let c = WNDCLASSEXA
+        {
+            cbSize: std::mem::size_of::<WNDCLASSEXA>() as _,
+            style: CS_OWNDC,
+            lpfnWndProc: Some(Self::wev_callback),
+            cbClsExtra: 0, cbWndExtra: 0,
+            hInstance: unsafe { GetModuleHandleA(null_mut()) },
+            hCursor: unsafe { LoadCursorA(null_mut(), b"IDC_ARROW\0".as_ptr() as _) },
+            lpszClassName: WINDOW_CLASS_NAME.as_ptr() as _,
+            .. unsafe { std::mem::zeroed() }
+        };
+        // another window of the class may still be open
+        if unsafe { RegisterClassExA(&c) == 0 } && unsafe { GetLastError() } != ERROR_CLASS_ALREADY_EXISTS
+        {
+            return Err(InterceptorError::Window(format!("RegisterClassExA failed ({})", unsafe { GetLastError() })));
+        }
+
+        let ws = WS_OVERLAPPED | WS_CAPTION | WS_BORDER | WS_SYSMENU | WS_MINIMIZEBOX | WS_VISIBLE;
+        let mut rect = RECT
+        {
+            left: 0, top: 0, right: geometry.width as _, bottom: geometry.height as _
+        };
+        unsafe { AdjustWindowRectEx(&mut rect, ws, false as _, 0); }
+
+        // the position is that of the client area
+        let (x, y) = geometry.position.map_or((CW_USEDEFAULT, CW_USEDEFAULT), |(x, y)| (x + rect.left, y + rect.top));
+        let title = std::ffi::CString::new(title).map_err(|_| InterceptorError::Window("window title contains nul".to_owned()))?;
+        let handle = unsafe
+        {
+            CreateWindowExA(0, c.lpszClassName, title.as_ptr(), ws,
+                x, y, rect.right - rect.left, rect.bottom - rect.top,
+                null_mut(), null_mut(), c.hInstance, null_mut()
+            )
+        };
+
+        if handle.is_null()
+        {
+            let e = unsafe { GetLastError() };
+            unsafe { UnregisterClassA(c.lpszClassName, c.hInstance); }
+            return Err(InterceptorError::Window(format!("CreateWindowExA failed ({})", e)));
+        }
+        live_objects::created("window");
+
+        Ok(Win32Window { handle, hinstance: c.hInstance })
+    }
+
+    extern "system" fn wev_callback(wnd: HWND, msg: UINT, wp: WPARAM, lp: LPARAM) -> LRESULT
+    {
+        if msg == WM_QUIT
+        {
+            return 0;
+        }
+
+        unsafe { DefWindowProcA(wnd, msg, wp, lp) }
+    }
+}
+impl Drop for Win32Window
+{
+    fn drop(&mut self)
+    {
+        unsafe
+        {
+            DestroyWindow(self.handle);
+            // fails while another window of the class is open, which unregisters it later
+            UnregisterClassA(WINDOW_CLASS_NAME.as_ptr() as _, self.hinstance);
+        }
+        live_objects::destroyed("window");
+    }
+}
+impl WindowBackend for Win32Window
+{
+    fn surface_extension(&self) -> &'static str { "VK_KHR_win32_surface" }
+    fn presentation_support(&self, instance: &UnityVulkanInstance) -> Result<bool, InterceptorError>
+    {
+        let fp_get_physical_device_presentation_support: PFN_vkGetPhysicalDeviceWin32PresentationSupportKHR = load_instance_proc!(instance, "vkGetPhysicalDeviceWin32PresentationSupportKHR")?;
+
+        Ok(fp_get_physical_device_presentation_support(instance.physical_device, instance.queue_family_index) != 0)
+    }
+    fn create_surface(&self, instance: &UnityVulkanInstance) -> Result<VkSurfaceKHR, InterceptorError>
+    {
+        let fp_create_surface_khr: PFN_vkCreateWin32SurfaceKHR = load_instance_proc!(instance, "vkCreateWin32SurfaceKHR")?;
+        let sinfo = VkWin32SurfaceCreateInfoKHR
+        {
+            hinstance: self.hinstance, hwnd: self.handle,
+            .. Default::default()
+        };
+        let mut sptr = std::mem::MaybeUninit::uninit();
+        vk_check("vkCreateWin32SurfaceKHR", fp_create_surface_khr(instance.instance, &sinfo, std::ptr::null(), sptr.as_mut_ptr()))?;
+
+        Ok(unsafe { sptr.assume_init() })
+    }
+    fn client_extent(&self) -> VkExtent2D
+    {
+        let mut wrect = std::mem::MaybeUninit::uninit();
+        unsafe { GetClientRect(self.handle, wrect.as_mut_ptr()); }
+        let wrect = unsafe { wrect.assume_init() };
+
+        VkExtent2D { width: (wrect.right - wrect.left) as _, height: (wrect.bottom - wrect.top) as _ }
+    }
+}
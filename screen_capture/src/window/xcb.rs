@@ -0,0 +1,307 @@
+//! X11/XCB Window Backend (VK_KHR_xcb_surface)
+#![allow(non_camel_case_types, non_upper_case_globals)]
+
+use bedrock::vk::*;
+use libc::*;
+use log::*;
+use crate::error::{InterceptorError, vk_check};
+use crate::unity::UnityVulkanInstance;
+use crate::live_objects;
+use super::{WindowBackend, WindowGeometry};
+
+// libxcb //
+
+pub enum xcb_connection_t {}
+pub enum xcb_setup_t {}
+pub enum xcb_generic_error_t {}
+pub type xcb_window_t = u32;
+pub type xcb_visualid_t = u32;
+pub type xcb_atom_t = u32;
+
+#[repr(C)]
+pub struct xcb_screen_t
+{
+    pub root: xcb_window_t,
+    pub default_colormap: u32,
+    pub white_pixel: u32,
+    pub black_pixel: u32,
+    pub current_input_masks: u32,
+    pub width_in_pixels: u16,
+    pub height_in_pixels: u16,
+    pub width_in_millimeters: u16,
+    pub height_in_millimeters: u16,
+    pub min_installed_maps: u16,
+    pub max_installed_maps: u16,
+    pub root_visual: xcb_visualid_t,
+    pub backing_stores: u8,
+    pub save_unders: u8,
+    pub root_depth: u8,
+    pub allowed_depths_len: u8
+}
+#[repr(C)]
+pub struct xcb_screen_iterator_t
+{
+    pub data: *mut xcb_screen_t,
+    pub rem: c_int,
+    pub index: c_int
+}
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct xcb_void_cookie_t { pub sequence: c_uint }
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct xcb_get_geometry_cookie_t { pub sequence: c_uint }
+#[repr(C)]
+pub struct xcb_get_geometry_reply_t
+{
+    pub response_type: u8,
+    pub depth: u8,
+    pub sequence: u16,
+    pub length: u32,
+    pub root: xcb_window_t,
+    pub x: i16,
+    pub y: i16,
+    pub width: u16,
+    pub height: u16,
+    pub border_width: u16,
+    pub pad0: [u8; 2]
+}
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct xcb_intern_atom_cookie_t { pub sequence: c_uint }
+#[repr(C)]
+pub struct xcb_intern_atom_reply_t
+{
+    pub response_type: u8,
+    pub pad0: u8,
+    pub sequence: u16,
+    pub length: u32,
+    pub atom: xcb_atom_t
+}
+#[repr(C)]
+pub struct xcb_generic_event_t
+{
+    pub response_type: u8,
+    pub pad0: u8,
+    pub sequence: u16,
+    pub pad: [u32; 7],
+    pub full_sequence: u32
+}
+#[repr(C)]
+pub struct xcb_client_message_event_t
+{
+    pub response_type: u8,
+    pub format: u8,
+    pub sequence: u16,
+    pub window: xcb_window_t,
+    pub type_: xcb_atom_t,
+    /// xcb_client_message_data_t as 32-bit values
+    pub data32: [u32; 5]
+}
+
+const XCB_COPY_FROM_PARENT: u8 = 0;
+const XCB_WINDOW_CLASS_INPUT_OUTPUT: u16 = 1;
+const XCB_CW_BACK_PIXEL: u32 = 2;
+const XCB_CW_EVENT_MASK: u32 = 2048;
+const XCB_EVENT_MASK_EXPOSURE: u32 = 32768;
+const XCB_EVENT_MASK_STRUCTURE_NOTIFY: u32 = 131072;
+const XCB_PROP_MODE_REPLACE: u8 = 0;
+const XCB_CLIENT_MESSAGE: u8 = 33;
+const XCB_ATOM_ATOM: xcb_atom_t = 4;
+const XCB_ATOM_STRING: xcb_atom_t = 31;
+const XCB_ATOM_WM_NAME: xcb_atom_t = 39;
+
+#[link(name = "xcb")]
+extern "C"
+{
+    fn xcb_connect(displayname: *const c_char, screenp: *mut c_int) -> *mut xcb_connection_t;
+    fn xcb_connection_has_error(c: *mut xcb_connection_t) -> c_int;
+    fn xcb_disconnect(c: *mut xcb_connection_t);
+    fn xcb_get_setup(c: *mut xcb_connection_t) -> *const xcb_setup_t;
+    fn xcb_setup_roots_iterator(r: *const xcb_setup_t) -> xcb_screen_iterator_t;
+    fn xcb_screen_next(i: *mut xcb_screen_iterator_t);
+    fn xcb_generate_id(c: *mut xcb_connection_t) -> u32;
+    fn xcb_create_window(c: *mut xcb_connection_t, depth: u8, wid: xcb_window_t, parent: xcb_window_t,
+        x: i16, y: i16, width: u16, height: u16, border_width: u16, class: u16, visual: xcb_visualid_t,
+        value_mask: u32, value_list: *const c_void) -> xcb_void_cookie_t;
+    fn xcb_change_property(c: *mut xcb_connection_t, mode: u8, window: xcb_window_t, property: xcb_atom_t, type_: xcb_atom_t,
+        format: u8, data_len: u32, data: *const c_void) -> xcb_void_cookie_t;
+    fn xcb_map_window(c: *mut xcb_connection_t, window: xcb_window_t) -> xcb_void_cookie_t;
+    fn xcb_unmap_window(c: *mut xcb_connection_t, window: xcb_window_t) -> xcb_void_cookie_t;
+    fn xcb_destroy_window(c: *mut xcb_connection_t, window: xcb_window_t) -> xcb_void_cookie_t;
+    fn xcb_get_geometry(c: *mut xcb_connection_t, drawable: xcb_window_t) -> xcb_get_geometry_cookie_t;
+    fn xcb_get_geometry_reply(c: *mut xcb_connection_t, cookie: xcb_get_geometry_cookie_t, e: *mut *mut xcb_generic_error_t)
+        -> *mut xcb_get_geometry_reply_t;
+    fn xcb_intern_atom(c: *mut xcb_connection_t, only_if_exists: u8, name_len: u16, name: *const c_char) -> xcb_intern_atom_cookie_t;
+    fn xcb_intern_atom_reply(c: *mut xcb_connection_t, cookie: xcb_intern_atom_cookie_t, e: *mut *mut xcb_generic_error_t)
+        -> *mut xcb_intern_atom_reply_t;
+    fn xcb_poll_for_event(c: *mut xcb_connection_t) -> *mut xcb_generic_event_t;
+    fn xcb_flush(c: *mut xcb_connection_t) -> c_int;
+}
+
+/// The atom named `name`, or 0 if the server could not give one
+fn intern_atom(con: *mut xcb_connection_t, name: &str) -> xcb_atom_t
+{
+    let reply = unsafe
+    {
+        let cookie = xcb_intern_atom(con, 0, name.len() as _, name.as_ptr() as *const _);
+        xcb_intern_atom_reply(con, cookie, std::ptr::null_mut())
+    };
+    if reply.is_null() { return 0; }
+    let atom = unsafe { (*reply).atom };
+    unsafe { free(reply as *mut _); }
+
+    atom
+}
+
+// VK_KHR_xcb_surface //
+
+const VK_STRUCTURE_TYPE_XCB_SURFACE_CREATE_INFO_KHR: VkStructureType = 1000005000;
+
+#[repr(C)]
+pub struct VkXcbSurfaceCreateInfoKHR
+{
+    pub sType: VkStructureType,
+    pub pNext: *const c_void,
+    pub flags: VkFlags,
+    pub connection: *mut xcb_connection_t,
+    pub window: xcb_window_t
+}
+pub type PFN_vkCreateXcbSurfaceKHR = extern "system" fn(instance: VkInstance, pCreateInfo: *const VkXcbSurfaceCreateInfoKHR,
+    pAllocator: *const VkAllocationCallbacks, pSurface: *mut VkSurfaceKHR) -> VkResult;
+pub type PFN_vkGetPhysicalDeviceXcbPresentationSupportKHR = extern "system" fn(physicalDevice: VkPhysicalDevice,
+    queueFamilyIndex: u32, connection: *mut xcb_connection_t, visual_id: xcb_visualid_t) -> VkBool32;
+
+pub struct XcbWindow
+{
+    con: *mut xcb_connection_t,
+    handle: xcb_window_t,
+    visual: xcb_visualid_t,
+    wm_protocols: xcb_atom_t,
+    wm_delete_window: xcb_atom_t,
+    closed: bool
+}
+impl XcbWindow
+{
+    pub fn new(title: &str, geometry: &WindowGeometry) -> Result<Self, InterceptorError>
+    {
+        trace!("Interceptor: XcbWindow::new");
+
+        let mut screen_index = 0;
+        let con = unsafe { xcb_connect(std::ptr::null(), &mut screen_index) };
+        if con.is_null() || unsafe { xcb_connection_has_error(con) } != 0
+        {
+            // xcb_connect never returns null; an errored connection still has to be freed
+            if !con.is_null() { unsafe { xcb_disconnect(con); } }
+            return Err(InterceptorError::Window("unable to connect to X server".to_owned()));
+        }
+        let screen = unsafe
+        {
+            let mut iter = xcb_setup_roots_iterator(xcb_get_setup(con));
+            for _ in 0 .. screen_index { xcb_screen_next(&mut iter); }
+            &*iter.data
+        };
+
+        // window managers may still place it elsewhere
+        let (x, y) = geometry.position.unwrap_or((0, 0));
+        let handle = unsafe { xcb_generate_id(con) };
+        let values = [screen.black_pixel, XCB_EVENT_MASK_EXPOSURE | XCB_EVENT_MASK_STRUCTURE_NOTIFY];
+        // asks the window manager for a message instead of killing the connection (and Unity with it) on close
+        let wm_protocols = intern_atom(con, "WM_PROTOCOLS");
+        let wm_delete_window = intern_atom(con, "WM_DELETE_WINDOW");
+        unsafe
+        {
+            xcb_create_window(con, XCB_COPY_FROM_PARENT, handle, screen.root,
+                x as _, y as _, geometry.width as _, geometry.height as _, 0, XCB_WINDOW_CLASS_INPUT_OUTPUT, screen.root_visual,
+                XCB_CW_BACK_PIXEL | XCB_CW_EVENT_MASK, values.as_ptr() as *const _);
+            xcb_change_property(con, XCB_PROP_MODE_REPLACE, handle, XCB_ATOM_WM_NAME, XCB_ATOM_STRING, 8,
+                title.len() as _, title.as_ptr() as *const _);
+            if wm_protocols != 0 && wm_delete_window != 0
+            {
+                xcb_change_property(con, XCB_PROP_MODE_REPLACE, handle, wm_protocols, XCB_ATOM_ATOM, 32,
+                    1, &wm_delete_window as *const xcb_atom_t as *const _);
+            }
+            xcb_map_window(con, handle);
+            xcb_flush(con);
+        }
+
+        live_objects::created("window");
+
+        Ok(XcbWindow { con, handle, visual: screen.root_visual, wm_protocols, wm_delete_window, closed: false })
+    }
+}
+impl Drop for XcbWindow
+{
+    fn drop(&mut self)
+    {
+        unsafe
+        {
+            xcb_destroy_window(self.con, self.handle);
+            xcb_flush(self.con);
+            xcb_disconnect(self.con);
+        }
+        live_objects::destroyed("window");
+    }
+}
+impl WindowBackend for XcbWindow
+{
+    fn surface_extension(&self) -> &'static str { "VK_KHR_xcb_surface" }
+    fn presentation_support(&self, instance: &UnityVulkanInstance) -> Result<bool, InterceptorError>
+    {
+        let fp_get_physical_device_presentation_support: PFN_vkGetPhysicalDeviceXcbPresentationSupportKHR = load_instance_proc!(instance, "vkGetPhysicalDeviceXcbPresentationSupportKHR")?;
+
+        Ok(fp_get_physical_device_presentation_support(instance.physical_device, instance.queue_family_index, self.con, self.visual) != 0)
+    }
+    fn create_surface(&self, instance: &UnityVulkanInstance) -> Result<VkSurfaceKHR, InterceptorError>
+    {
+        let fp_create_surface_khr: PFN_vkCreateXcbSurfaceKHR = load_instance_proc!(instance, "vkCreateXcbSurfaceKHR")?;
+        let sinfo = VkXcbSurfaceCreateInfoKHR
+        {
+            sType: VK_STRUCTURE_TYPE_XCB_SURFACE_CREATE_INFO_KHR,
+            pNext: std::ptr::null(),
+            flags: 0,
+            connection: self.con,
+            window: self.handle
+        };
+        let mut sptr = std::mem::MaybeUninit::uninit();
+        vk_check("vkCreateXcbSurfaceKHR", fp_create_surface_khr(instance.instance, &sinfo, std::ptr::null(), sptr.as_mut_ptr()))?;
+
+        Ok(unsafe { sptr.assume_init() })
+    }
+    fn client_extent(&self) -> VkExtent2D
+    {
+        let reply = unsafe
+        {
+            let cookie = xcb_get_geometry(self.con, self.handle);
+            xcb_get_geometry_reply(self.con, cookie, std::ptr::null_mut())
+        };
+        if reply.is_null() { return VkExtent2D { width: 0, height: 0 }; }
+        let extent = unsafe { VkExtent2D { width: (*reply).width as _, height: (*reply).height as _ } };
+        unsafe { free(reply as *mut _); }
+
+        extent
+    }
+    fn process_events(&mut self) -> bool
+    {
+        loop
+        {
+            let ev = unsafe { xcb_poll_for_event(self.con) };
+            if ev.is_null() { break; }
+            // the most significant bit marks events sent by another client, as window managers do
+            if unsafe { (*ev).response_type } & 0x7f == XCB_CLIENT_MESSAGE
+            {
+                let m = unsafe { &*(ev as *const xcb_client_message_event_t) };
+                if !self.closed && self.wm_protocols != 0 && m.type_ == self.wm_protocols && m.data32[0] == self.wm_delete_window
+                {
+                    info!("Interceptor: the window has been closed");
+                    self.closed = true;
+                    unsafe { xcb_unmap_window(self.con, self.handle); xcb_flush(self.con); }
+                }
+            }
+            // nothing else is handled: the size is queried each frame
+            unsafe { free(ev as *mut _); }
+        }
+
+        !self.closed
+    }
+}
@@ -0,0 +1,501 @@
+//! Fake Unity host driving the plugin through its exported entry points
+#![allow(dead_code)]
+
+pub mod vk_stub;
+
+use bedrock::vk::*;
+use lazy_static::*;
+use libc::*;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::sync::{Mutex, MutexGuard};
+use RenderingInterceptor::unity::*;
+
+/// Every call the plugin made into the host, in order
+#[derive(Clone, Debug, PartialEq)]
+pub enum HostCall
+{
+    GetInterface(UnityInterfaceGUID),
+    RegisterDeviceEventCallback,
+    UnregisterDeviceEventCallback,
+    GetRenderer,
+    ReserveEventIdRange(c_int),
+    ConfigureEvent
+    {
+        event_id: c_int,
+        render_pass_precondition: UnityVulkanEventRenderPassPreCondition,
+        graphics_queue_access: UnityVulkanGraphicsQueueAccess,
+        flags: UnityVulkanEventConfigFlags
+    },
+    Instance,
+    AccessRenderBufferTexture
+    {
+        render_buffer: usize,
+        layout: VkImageLayout,
+        access_mode: UnityVulkanResourceAccessMode
+    },
+    AccessRenderBufferResolveTexture
+    {
+        render_buffer: usize,
+        layout: VkImageLayout,
+        access_mode: UnityVulkanResourceAccessMode
+    },
+    AccessTexture
+    {
+        texture: usize,
+        layout: VkImageLayout,
+        access_mode: UnityVulkanResourceAccessMode
+    },
+    InterceptVulkanApi(String),
+    InterceptInitialization,
+    ConfigureSwapchain(UnityVulkanSwapchainMode)
+}
+
+/// The image returned for every render buffer access
+pub const RENDER_BUFFER_IMAGE: usize = 0x0800;
+pub const RENDER_BUFFER_WIDTH: u32 = 640;
+pub const RENDER_BUFFER_HEIGHT: u32 = 480;
+pub const RENDER_BUFFER_FORMAT: VkFormat = VK_FORMAT_B8G8R8A8_SRGB;
+/// The image returned for resolve texture accesses, when enabled
+pub const RESOLVE_TEXTURE_IMAGE: usize = 0x0900;
+/// The image returned for every texture access, except for DEPTH_TEXTURE
+pub const TEXTURE_IMAGE: usize = 0x0a00;
+pub const TEXTURE_WIDTH: u32 = 256;
+pub const TEXTURE_HEIGHT: u32 = 128;
+/// A native texture pointer that is accessed as a depth-only image
+pub const DEPTH_TEXTURE: usize = 0x6000;
+/// The surface Unity's own swapchain is created for
+pub const UNITY_SURFACE: usize = 0x0b00;
+/// The semaphore Unity's rendering signals before presenting
+pub const UNITY_RENDER_DONE: usize = 0x0c00;
+
+struct HostState
+{
+    renderer: UnityGfxRenderer,
+    device_event_callbacks: Vec<IUnityGraphicsDeviceEventCallback>,
+    calls: Vec<HostCall>,
+    /// sample count of the render buffer image
+    render_buffer_samples: VkSampleCountFlags,
+    /// whether the render buffer has a resolve texture
+    resolve_texture: bool,
+    /// Vulkan commands Unity calls a replacement for
+    intercepted: HashMap<String, PFN_vkVoidFunction>,
+    /// callbacks wrapping vkGetInstanceProcAddr before Vulkan is initialized, with their userdata
+    init_callbacks: Vec<(UnityVulkanInitCallback, usize)>,
+    /// the queue `instance` returns, changed by a device reset
+    graphics_queue: usize,
+    /// whether the vkGetInstanceProcAddr `instance` returns resolves commands to their interceptors, like the player's
+    resolve_intercepted: bool
+}
+lazy_static!{
+    static ref STATE: Mutex<HostState> = Mutex::new(HostState
+    {
+        renderer: UnityGfxRenderer::Vulkan, device_event_callbacks: Vec::new(), calls: Vec::new(),
+        render_buffer_samples: VK_SAMPLE_COUNT_1_BIT, resolve_texture: false, intercepted: HashMap::new(),
+        init_callbacks: Vec::new(), graphics_queue: vk_stub::FAKE_QUEUE, resolve_intercepted: false
+    });
+    /// the plugin keeps process-global state, so only one host can exist at a time
+    static ref HOST_LOCK: Mutex<()> = Mutex::new(());
+}
+fn record(c: HostCall) { STATE.lock().unwrap().calls.push(c); }
+
+// IUnityInterfaces //
+
+extern "system" fn get_interface(guid: UnityInterfaceGUID) -> *mut IUnityInterface
+{
+    record(HostCall::GetInterface(guid));
+    if guid == IUnityGraphics::GUID { &GRAPHICS as *const _ as *mut _ }
+    else if guid == IUnityGraphicsVulkan::GUID { &GRAPHICS_VULKAN as *const _ as *mut _ }
+    else { std::ptr::null_mut() }
+}
+extern "system" fn register_interface(_: UnityInterfaceGUID, _: *mut IUnityInterface) {}
+extern "system" fn get_interface_split(high: c_longlong, low: c_longlong) -> *mut IUnityInterface
+{
+    get_interface(UnityInterfaceGUID { guid_high: high as _, guid_low: low as _ })
+}
+extern "system" fn register_interface_split(_: c_longlong, _: c_longlong, _: *mut IUnityInterface) {}
+
+// IUnityGraphics //
+
+extern "system" fn get_renderer() -> UnityGfxRenderer
+{
+    let mut st = STATE.lock().unwrap();
+    st.calls.push(HostCall::GetRenderer);
+    st.renderer
+}
+extern "system" fn register_device_event_callback(callback: IUnityGraphicsDeviceEventCallback)
+{
+    let mut st = STATE.lock().unwrap();
+    st.calls.push(HostCall::RegisterDeviceEventCallback);
+    st.device_event_callbacks.push(callback);
+}
+extern "system" fn unregister_device_event_callback(callback: IUnityGraphicsDeviceEventCallback)
+{
+    let mut st = STATE.lock().unwrap();
+    st.calls.push(HostCall::UnregisterDeviceEventCallback);
+    st.device_event_callbacks.retain(|&c| c as usize != callback as usize);
+}
+extern "system" fn reserve_event_id_range(count: c_int) -> c_int
+{
+    record(HostCall::ReserveEventIdRange(count));
+    // Unity hands out ids after the ones built-in plugins use
+    1000
+}
+static GRAPHICS: IUnityGraphics = IUnityGraphics
+{
+    get_renderer, register_device_event_callback, unregister_device_event_callback, reserve_event_id_range
+};
+
+// IUnityGraphicsVulkan //
+
+extern "system" fn intercept_initialization(f: UnityVulkanInitCallback, userdata: *mut c_void) -> bool
+{
+    let mut st = STATE.lock().unwrap();
+    st.calls.push(HostCall::InterceptInitialization);
+    st.init_callbacks.push((f, userdata as usize));
+    true
+}
+extern "system" fn intercept_vulkan_api(name: *const c_char, f: PFN_vkVoidFunction) -> Option<PFN_vkVoidFunction>
+{
+    let name = unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned();
+    let mut st = STATE.lock().unwrap();
+    st.calls.push(HostCall::InterceptVulkanApi(name.clone()));
+    // returns what Unity would have called so far
+    let previous = st.intercepted.get(&name).cloned().or_else(|| vk_stub::resolve(name.as_bytes()))?;
+    st.intercepted.insert(name, f);
+    Some(previous)
+}
+/// The function Unity calls for the Vulkan command `name`
+fn unity_entry(name: &str) -> PFN_vkVoidFunction
+{
+    let intercepted = STATE.lock().unwrap().intercepted.get(name).cloned();
+    intercepted.or_else(|| vk_stub::resolve(name.as_bytes())).expect("unknown Vulkan command")
+}
+extern "system" fn configure_event(event_id: c_int, config: *const UnityVulkanPluginEventConfig)
+{
+    let config = unsafe { &*config };
+    record(HostCall::ConfigureEvent
+    {
+        event_id,
+        render_pass_precondition: config.render_pass_precondition,
+        graphics_queue_access: config.graphics_queue_access,
+        flags: config.flags
+    });
+}
+/// vkGetInstanceProcAddr resolving the intercepted commands to their interceptors
+extern "system" fn intercepted_instance_proc_addr(instance: VkInstance, name: *const c_char) -> Option<PFN_vkVoidFunction>
+{
+    let key = unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned();
+    let intercepted = STATE.lock().unwrap().intercepted.get(&key).cloned();
+    intercepted.or_else(|| vk_stub::get_instance_proc_addr(instance, name))
+}
+extern "system" fn instance() -> UnityVulkanInstance
+{
+    record(HostCall::Instance);
+    let (graphics_queue, resolve_intercepted) = { let st = STATE.lock().unwrap(); (st.graphics_queue, st.resolve_intercepted) };
+    let get_instance_proc_addr: PFN_vkGetInstanceProcAddr =
+        if resolve_intercepted { intercepted_instance_proc_addr } else { vk_stub::get_instance_proc_addr };
+    UnityVulkanInstance
+    {
+        pipeline_cache: std::ptr::null_mut(),
+        instance: vk_stub::FAKE_INSTANCE as _,
+        physical_device: vk_stub::FAKE_PHYSICAL_DEVICE as _,
+        device: vk_stub::FAKE_DEVICE as _,
+        graphics_queue: graphics_queue as _,
+        get_instance_proc_addr,
+        queue_family_index: 0,
+        _resv: [std::ptr::null_mut(); 8]
+    }
+}
+extern "system" fn command_recording_state(_: *mut UnityVulkanRecordingState, _: UnityVulkanGraphicsQueueAccess) -> bool { false }
+/// Fills a fake color image with the given layout
+pub fn fake_image(image: usize, layout: VkImageLayout) -> UnityVulkanImage
+{
+    UnityVulkanImage
+    {
+        memory: UnityVulkanMemory
+        {
+            memory: std::ptr::null_mut(), offset: 0, size: 0, mapped: std::ptr::null_mut(),
+            flags: VK_MEMORY_PROPERTY_DEVICE_LOCAL_BIT, memory_type_index: 0, _resv: [std::ptr::null_mut(); 4]
+        },
+        image: image as _,
+        layout,
+        aspect: VK_IMAGE_ASPECT_COLOR_BIT,
+        usage: VK_IMAGE_USAGE_TRANSFER_SRC_BIT | VK_IMAGE_USAGE_COLOR_ATTACHMENT_BIT,
+        format: RENDER_BUFFER_FORMAT,
+        extent: VkExtent3D { width: RENDER_BUFFER_WIDTH, height: RENDER_BUFFER_HEIGHT, depth: 1 },
+        tiling: VK_IMAGE_TILING_OPTIMAL,
+        type_: VK_IMAGE_TYPE_2D,
+        samples: VK_SAMPLE_COUNT_1_BIT,
+        layers: 1,
+        mip_count: 1,
+        _resv: [std::ptr::null_mut(); 4]
+    }
+}
+extern "system" fn access_texture(texture: *mut c_void, _: *const VkImageSubresource, layout: VkImageLayout,
+    _: VkPipelineStageFlags, _: VkAccessFlags, access_mode: UnityVulkanResourceAccessMode, out_image: *mut UnityVulkanImage) -> bool
+{
+    record(HostCall::AccessTexture { texture: texture as usize, layout, access_mode });
+    if texture.is_null() { return false; }
+
+    let image = UnityVulkanImage
+    {
+        extent: VkExtent3D { width: TEXTURE_WIDTH, height: TEXTURE_HEIGHT, depth: 1 },
+        .. fake_image(TEXTURE_IMAGE, layout)
+    };
+    let image = if texture as usize == DEPTH_TEXTURE
+    {
+        UnityVulkanImage { aspect: VK_IMAGE_ASPECT_DEPTH_BIT, format: VK_FORMAT_D32_SFLOAT, .. image }
+    }
+    else { image };
+    unsafe { out_image.write(image); }
+    true
+}
+extern "system" fn access_render_buffer_texture(rb: UnityRenderBuffer, _: *const VkImageSubresource, layout: VkImageLayout,
+    _: VkPipelineStageFlags, _: VkAccessFlags, access_mode: UnityVulkanResourceAccessMode, out_image: *mut UnityVulkanImage) -> bool
+{
+    record(HostCall::AccessRenderBufferTexture { render_buffer: rb as usize, layout, access_mode });
+    if rb.is_null() { return false; }
+
+    let image = UnityVulkanImage { samples: STATE.lock().unwrap().render_buffer_samples, .. fake_image(RENDER_BUFFER_IMAGE, layout) };
+    unsafe { out_image.write(image); }
+    true
+}
+extern "system" fn access_render_buffer_resolve_texture(rb: UnityRenderBuffer, _: *const VkImageSubresource, layout: VkImageLayout,
+    _: VkPipelineStageFlags, _: VkAccessFlags, access_mode: UnityVulkanResourceAccessMode, out_image: *mut UnityVulkanImage) -> bool
+{
+    record(HostCall::AccessRenderBufferResolveTexture { render_buffer: rb as usize, layout, access_mode });
+    if rb.is_null() || !STATE.lock().unwrap().resolve_texture { return false; }
+
+    unsafe { out_image.write(fake_image(RESOLVE_TEXTURE_IMAGE, layout)); }
+    true
+}
+extern "system" fn access_buffer(_: *mut c_void, _: VkPipelineStageFlags, _: VkAccessFlags,
+    _: UnityVulkanResourceAccessMode, _: *mut UnityVulkanBuffer) -> bool { false }
+extern "system" fn ensure_outside_render_pass() {}
+extern "system" fn ensure_inside_render_pass() {}
+extern "system" fn access_queue(_: UnityRenderingEventAndData, _: c_int, _: *mut c_void, _: bool) {}
+extern "system" fn configure_swapchain(config: *const UnityVulkanSwapchainConfiguration) -> bool
+{
+    record(HostCall::ConfigureSwapchain(unsafe { (*config).mode }));
+    true
+}
+static GRAPHICS_VULKAN: IUnityGraphicsVulkan = IUnityGraphicsVulkan
+{
+    intercept_initialization, intercept_vulkan_api, configure_event, instance, command_recording_state,
+    access_texture, access_render_buffer_texture, access_render_buffer_resolve_texture, access_buffer,
+    ensure_outside_render_pass, ensure_inside_render_pass, access_queue, configure_swapchain
+};
+
+/// A fake Unity player. Holding one serializes tests that touch the plugin.
+pub struct MockUnityHost
+{
+    interfaces: Box<IUnityInterfaces>,
+    _lock: MutexGuard<'static, ()>
+}
+impl MockUnityHost
+{
+    pub fn new(renderer: UnityGfxRenderer) -> Self
+    {
+        // a panicking test must not poison the others
+        let lock = HOST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        {
+            let mut st = STATE.lock().unwrap_or_else(|e| e.into_inner());
+            st.renderer = renderer;
+            st.device_event_callbacks.clear();
+            st.calls.clear();
+            st.render_buffer_samples = VK_SAMPLE_COUNT_1_BIT;
+            st.resolve_texture = false;
+            st.intercepted.clear();
+            st.init_callbacks.clear();
+            st.graphics_queue = vk_stub::FAKE_QUEUE;
+            st.resolve_intercepted = false;
+        }
+        // no window system in tests
+        std::env::set_var(RenderingInterceptor::window::HEADLESS_ENV_NAME, "320x180");
+        std::env::remove_var(RenderingInterceptor::window::OFFSCREEN_ENV_NAME);
+        std::env::remove_var(RenderingInterceptor::window::PRESENT_MODE_ENV_NAME);
+        vk_stub::reset();
+        vk_stub::label_handle(RENDER_BUFFER_IMAGE, "render_buffer");
+        vk_stub::label_handle(RESOLVE_TEXTURE_IMAGE, "resolve_texture");
+        vk_stub::label_handle(TEXTURE_IMAGE, "texture");
+        vk_stub::label_handle(UNITY_SURFACE, "unity_surface");
+        vk_stub::label_handle(UNITY_RENDER_DONE, "unity_render_done");
+        RenderingInterceptor::error::clear_last_error();
+        RenderingInterceptor::settings::reset();
+
+        MockUnityHost
+        {
+            interfaces: Box::new(IUnityInterfaces
+            {
+                get_interface, register_interface, get_interface_split, register_interface_split
+            }),
+            _lock: lock
+        }
+    }
+    pub fn vulkan() -> Self { Self::new(UnityGfxRenderer::Vulkan) }
+
+    pub fn interfaces_ptr(&mut self) -> *mut IUnityInterfaces { &mut *self.interfaces }
+
+    /// Loads the plugin like Unity does when the library is first used
+    pub fn load_plugin(&mut self)
+    {
+        RenderingInterceptor::UnityPluginLoad(self.interfaces_ptr());
+    }
+    pub fn unload_plugin(&mut self)
+    {
+        RenderingInterceptor::UnityPluginUnload();
+    }
+    /// Sends a device event to every registered callback
+    pub fn fire_device_event(&self, event_type: UnityGfxDeviceEventType)
+    {
+        let callbacks = STATE.lock().unwrap().device_event_callbacks.clone();
+        for cb in callbacks { cb(event_type); }
+    }
+    pub fn registered_device_event_callbacks(&self) -> usize
+    {
+        STATE.lock().unwrap().device_event_callbacks.len()
+    }
+    /// Resets the device like Unity does on a resolution change or a device loss, recreating the graphics queue as `queue`
+    pub fn reset_device(&self, queue: usize)
+    {
+        self.fire_device_event(UnityGfxDeviceEventType::BeforeReset);
+        STATE.lock().unwrap().graphics_queue = queue;
+        self.fire_device_event(UnityGfxDeviceEventType::AfterReset);
+    }
+    /// The callbacks registered now, to be called after they have been unregistered
+    pub fn device_event_callbacks(&self) -> Vec<IUnityGraphicsDeviceEventCallback>
+    {
+        STATE.lock().unwrap().device_event_callbacks.clone()
+    }
+
+    /// Does what NativeRenderInteceptor.cs does every frame
+    pub fn set_render_buffer(&self, rb: usize)
+    {
+        RenderingInterceptor::set_render_buffer(rb as UnityRenderBuffer);
+    }
+    /// Makes the render buffer multisampled, with or without a resolve texture kept by Unity
+    pub fn set_render_buffer_samples(&self, samples: VkSampleCountFlags, resolve_texture: bool)
+    {
+        let mut st = STATE.lock().unwrap();
+        st.render_buffer_samples = samples;
+        st.resolve_texture = resolve_texture;
+    }
+    /// Makes the instance given to the plugin resolve the commands it intercepted to its own hooks, as the player does
+    pub fn resolve_intercepted_commands(&self) { STATE.lock().unwrap().resolve_intercepted = true; }
+    /// The id the plugin reserved for an event kind, as C# queries it
+    pub fn event_id(&self, kind: c_int) -> c_int { RenderingInterceptor::plugin_event_id(kind) }
+    pub fn issue_plugin_event(&self, event_id: c_int)
+    {
+        (RenderingInterceptor::rendering_event_ptr())(event_id);
+    }
+    pub fn issue_capture_event(&self) { self.issue_plugin_event(self.event_id(RenderingInterceptor::event::EVENT_CAPTURE)); }
+    pub fn issue_capture_event_and_data(&self, data: Option<&RenderingInterceptor::event::CaptureEventData>)
+    {
+        self.issue_plugin_event_and_data(self.event_id(RenderingInterceptor::event::EVENT_CAPTURE), data);
+    }
+    /// Does what `GL.IssuePluginEventAndData` does
+    pub fn issue_plugin_event_and_data(&self, event_id: c_int, data: Option<&RenderingInterceptor::event::CaptureEventData>)
+    {
+        let data = data.map_or(std::ptr::null_mut(), |d| d as *const _ as *mut c_void);
+        (RenderingInterceptor::rendering_event_and_data_ptr())(event_id, data);
+    }
+    /// Creates the instance and the device like Unity does at startup, through the vkGetInstanceProcAddr
+    /// the registered callbacks return. Unity requests VK_KHR_surface and VK_KHR_swapchain only.
+    /// Returns the results of vkCreateInstance and vkCreateDevice.
+    pub fn initialize_vulkan(&self) -> (VkResult, VkResult)
+    {
+        let callbacks = STATE.lock().unwrap().init_callbacks.clone();
+        let get_instance_proc_addr = callbacks.into_iter()
+            .fold(vk_stub::get_instance_proc_addr as PFN_vkGetInstanceProcAddr, |f, (cb, userdata)| cb(f, userdata as _));
+        let resolve = |instance: VkInstance, name: &[u8]| get_instance_proc_addr(instance, name.as_ptr() as _).expect("entry point not resolved");
+
+        let create_instance: PFN_vkCreateInstance = unsafe { std::mem::transmute(resolve(std::ptr::null_mut(), b"vkCreateInstance\0")) };
+        let surface_extension = b"VK_KHR_surface\0".as_ptr() as *const c_char;
+        let mut instance = std::ptr::null_mut();
+        let ir = create_instance(&VkInstanceCreateInfo
+        {
+            enabledExtensionCount: 1, ppEnabledExtensionNames: &surface_extension, .. Default::default()
+        }, std::ptr::null(), &mut instance);
+        if ir != VK_SUCCESS { return (ir, VK_ERROR_INITIALIZATION_FAILED); }
+
+        let create_device: PFN_vkCreateDevice = unsafe { std::mem::transmute(resolve(instance, b"vkCreateDevice\0")) };
+        let swapchain_extension = b"VK_KHR_swapchain\0".as_ptr() as *const c_char;
+        let mut device = std::ptr::null_mut();
+        let dr = create_device(vk_stub::FAKE_PHYSICAL_DEVICE as _, &VkDeviceCreateInfo
+        {
+            enabledExtensionCount: 1, ppEnabledExtensionNames: &swapchain_extension, .. Default::default()
+        }, std::ptr::null(), &mut device);
+        (ir, dr)
+    }
+    /// Creates the swapchain of the game window like Unity does, through vkCreateSwapchainKHR as intercepted
+    pub fn create_swapchain(&self, width: u32, height: u32) -> VkSwapchainKHR
+    {
+        let create: PFN_vkCreateSwapchainKHR = unsafe { std::mem::transmute(unity_entry("vkCreateSwapchainKHR")) };
+        let info = VkSwapchainCreateInfoKHR
+        {
+            surface: UNITY_SURFACE as _,
+            minImageCount: vk_stub::SWAPCHAIN_IMAGE_COUNT,
+            imageFormat: VK_FORMAT_B8G8R8A8_UNORM,
+            imageColorSpace: VK_COLOR_SPACE_SRGB_NONLINEAR_KHR,
+            imageExtent: VkExtent2D { width, height },
+            imageArrayLayers: 1,
+            imageUsage: VK_IMAGE_USAGE_COLOR_ATTACHMENT_BIT,
+            imageSharingMode: VK_SHARING_MODE_EXCLUSIVE,
+            preTransform: VK_SURFACE_TRANSFORM_IDENTITY_BIT_KHR,
+            compositeAlpha: VK_COMPOSITE_ALPHA_OPAQUE_BIT_KHR,
+            presentMode: VK_PRESENT_MODE_FIFO_KHR,
+            .. Default::default()
+        };
+        let mut swapchain = std::ptr::null_mut();
+        assert_eq!(create(vk_stub::FAKE_DEVICE as _, &info, std::ptr::null(), &mut swapchain), VK_SUCCESS);
+        swapchain
+    }
+    pub fn destroy_swapchain(&self, swapchain: VkSwapchainKHR)
+    {
+        let destroy: PFN_vkDestroySwapchainKHR = unsafe { std::mem::transmute(unity_entry("vkDestroySwapchainKHR")) };
+        destroy(vk_stub::FAKE_DEVICE as _, swapchain, std::ptr::null());
+    }
+    /// Presents an image of Unity's swapchain at the end of a frame, through vkQueuePresentKHR as intercepted
+    pub fn present(&self, swapchain: VkSwapchainKHR, image_index: u32) -> VkResult
+    {
+        let present: PFN_vkQueuePresentKHR = unsafe { std::mem::transmute(unity_entry("vkQueuePresentKHR")) };
+        let render_done = UNITY_RENDER_DONE as VkSemaphore;
+        present(vk_stub::FAKE_QUEUE as _, &VkPresentInfoKHR
+        {
+            waitSemaphoreCount: 1,
+            pWaitSemaphores: &render_done,
+            swapchainCount: 1,
+            pSwapchains: &swapchain,
+            pImageIndices: &image_index,
+            .. Default::default()
+        })
+    }
+
+    /// Reads the diagnostic the way NativeRenderInteceptor.cs does
+    pub fn last_error(&self) -> Option<String>
+    {
+        let mut buffer = [0u8; 256];
+        let len = RenderingInterceptor::error::last_error_message(buffer.as_mut_ptr(), buffer.len() as _) as usize;
+        if len == 0 { return None; }
+        Some(String::from_utf8_lossy(&buffer[.. len.min(buffer.len())]).into_owned())
+    }
+
+    pub fn calls(&self) -> Vec<HostCall> { STATE.lock().unwrap().calls.clone() }
+    pub fn clear_calls(&self) { STATE.lock().unwrap().calls.clear(); }
+    pub fn configured_events(&self) -> Vec<HostCall>
+    {
+        self.calls().into_iter().filter(|c| match c { HostCall::ConfigureEvent { .. } => true, _ => false }).collect()
+    }
+}
+impl Drop for MockUnityHost
+{
+    fn drop(&mut self)
+    {
+        // leave no interceptor behind for the next test
+        let callbacks = STATE.lock().unwrap_or_else(|e| e.into_inner()).device_event_callbacks.clone();
+        for cb in callbacks { cb(UnityGfxDeviceEventType::Shutdown); }
+        // and no hooks
+        RenderingInterceptor::UnityPluginUnload();
+    }
+}
@@ -0,0 +1,727 @@
+//! Recording Vulkan stub resolved through a fake vkGetInstanceProcAddr.
+//! Every object is a fake handle, every command succeeds immediately and is appended to a
+//! human readable trace. Handles are printed by labels (`semaphore0`, `swapchain_image1`, ...)
+//! assigned in creation order, so traces are stable across runs.
+
+use bedrock::vk::*;
+use lazy_static::*;
+use libc::*;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::CStr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use RenderingInterceptor::extensions::{VkPhysicalDeviceFeatures2KHR, VkPhysicalDeviceTimelineSemaphoreFeaturesKHR, VK_STRUCTURE_TYPE_PHYSICAL_DEVICE_TIMELINE_SEMAPHORE_FEATURES_KHR};
+
+pub const FAKE_INSTANCE: usize = 0x10;
+pub const FAKE_PHYSICAL_DEVICE: usize = 0x20;
+pub const FAKE_DEVICE: usize = 0x30;
+pub const FAKE_QUEUE: usize = 0x40;
+pub const SWAPCHAIN_IMAGE_COUNT: u32 = 2;
+
+static NEXT_HANDLE: AtomicUsize = AtomicUsize::new(0x1000);
+static NEXT_ACQUIRE: AtomicUsize = AtomicUsize::new(0);
+/// Issues a unique non-null handle value
+pub fn new_handle<T>() -> *mut T { NEXT_HANDLE.fetch_add(0x10, Ordering::SeqCst) as *mut T }
+
+lazy_static!{
+    /// backing storage of VkDeviceMemory objects; the handle is the address of the bytes
+    static ref MEMORIES: Mutex<HashMap<usize, Vec<u8>>> = Mutex::new(HashMap::new());
+    static ref BUFFER_SIZES: Mutex<HashMap<usize, VkDeviceSize>> = Mutex::new(HashMap::new());
+    /// bytes of created images, as if they were linear and 4 bytes per texel
+    static ref IMAGE_SIZES: Mutex<HashMap<usize, VkDeviceSize>> = Mutex::new(HashMap::new());
+    static ref RECORDER: Mutex<Recorder> = Mutex::new(Recorder::default());
+}
+
+// Recording //
+
+#[derive(Default)]
+struct Recorder
+{
+    labels: HashMap<usize, String>,
+    counters: HashMap<&'static str, usize>,
+    trace: Vec<String>,
+    /// currentExtent reported by the surface; None lets the swapchain decide
+    surface_extent: Option<(u32, u32)>,
+    /// formats reported by the surface instead of B8G8R8A8_SRGB
+    surface_formats: Option<Vec<VkSurfaceFormatKHR>>,
+    acquire_results: VecDeque<VkResult>,
+    present_results: VecDeque<VkResult>,
+    /// entry points resolved as null, like an extension that is not enabled
+    hidden_entry_points: Vec<String>,
+    instance_extensions: Vec<String>,
+    device_extensions: Vec<String>,
+    /// extensions that are reported but fail creation, like ones with unmet dependencies
+    rejected_extensions: Vec<String>,
+    timeline_semaphore: bool,
+    /// entry points made to fail, with the number of calls that still succeed before
+    failing_calls: HashMap<String, usize>,
+    /// whether the fence of the next vkQueueSubmit is held
+    hold_next_fence: bool,
+    /// fences reported unsignaled until they are waited for
+    held_fences: Vec<usize>
+}
+fn recorder() -> std::sync::MutexGuard<'static, Recorder>
+{
+    RECORDER.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Forgets every label and recorded call and restarts the swapchain image rotation
+pub fn reset()
+{
+    {
+        let mut r = recorder();
+        *r = Recorder::default();
+    }
+    NEXT_ACQUIRE.store(0, Ordering::SeqCst);
+    label_handle(FAKE_INSTANCE, "instance");
+    label_handle(FAKE_PHYSICAL_DEVICE, "physical_device");
+    label_handle(FAKE_DEVICE, "device");
+    label_handle(FAKE_QUEUE, "queue");
+}
+/// Gives a handle a fixed label (e.g. images owned by the host)
+pub fn label_handle(h: usize, label: &str) { recorder().labels.insert(h, label.to_owned()); }
+/// Labels a newly created object by its kind and creation order
+fn label_new(kind: &'static str, h: usize) -> String
+{
+    let mut r = recorder();
+    let n = { let c = r.counters.entry(kind).or_insert(0); *c += 1; *c - 1 };
+    let l = format!("{}{}", kind, n);
+    r.labels.insert(h, l.clone());
+    l
+}
+fn label<T>(h: *const T) -> String
+{
+    if h.is_null() { return "null".to_owned(); }
+    recorder().labels.get(&(h as usize)).cloned().unwrap_or_else(|| format!("{:#x}", h as usize))
+}
+fn labels<T>(p: *const *mut T, count: u32) -> String
+{
+    let v: Vec<_> = (0 .. count as usize).map(|i| label(unsafe { *p.add(i) })).collect();
+    format!("[{}]", v.join(", "))
+}
+fn record(line: String) { recorder().trace.push(line); }
+
+pub fn trace() -> Vec<String> { recorder().trace.clone() }
+pub fn clear_trace() { recorder().trace.clear(); }
+
+/// Makes the surface report a fixed current extent, like a resized window
+pub fn set_surface_extent(extent: Option<(u32, u32)>) { recorder().surface_extent = extent; }
+/// Makes the surface report `formats` (possibly none)
+pub fn set_surface_formats(formats: &[VkSurfaceFormatKHR]) { recorder().surface_formats = Some(formats.to_vec()); }
+/// The next vkAcquireNextImageKHR returns `r` (VK_SUBOPTIMAL_KHR still acquires an image)
+pub fn push_acquire_result(r: VkResult) { recorder().acquire_results.push_back(r); }
+/// Makes vkGetInstanceProcAddr return null for `name`
+pub fn hide_entry_point(name: &str) { recorder().hidden_entry_points.push(name.to_owned()); }
+/// The next vkQueuePresentKHR returns `r`
+pub fn push_present_result(r: VkResult) { recorder().present_results.push_back(r); }
+/// Extensions the driver reports for the instance and for the physical device
+pub fn set_available_extensions(instance: &[&str], device: &[&str])
+{
+    let mut r = recorder();
+    r.instance_extensions = instance.iter().map(|&n| n.to_owned()).collect();
+    r.device_extensions = device.iter().map(|&n| n.to_owned()).collect();
+}
+/// Makes instance or device creation fail with VK_ERROR_EXTENSION_NOT_PRESENT when `name` is enabled
+pub fn reject_extension(name: &str) { recorder().rejected_extensions.push(name.to_owned()); }
+/// Whether the physical device has the timelineSemaphore feature
+pub fn set_timeline_semaphore_support(supported: bool) { recorder().timeline_semaphore = supported; }
+/// Makes one call to `name` return VK_ERROR_OUT_OF_DEVICE_MEMORY, after `successes` more calls succeed
+pub fn fail_call(name: &str, successes: usize) { recorder().failing_calls.insert(name.to_owned(), successes); }
+/// Keeps the fence of the next vkQueueSubmit unsignaled until it is waited for, like a copy still in flight
+pub fn hold_next_fence() { recorder().hold_next_fence = true; }
+/// The result a call to `name` has to fail with, if any
+fn injected_failure(name: &str) -> Option<VkResult>
+{
+    let mut r = recorder();
+    let n = r.failing_calls.get_mut(name)?;
+    if *n > 0 { *n -= 1; return None; }
+    r.failing_calls.remove(name);
+    r.trace.push(format!("{}() -> VK_ERROR_OUT_OF_DEVICE_MEMORY", name));
+    Some(VK_ERROR_OUT_OF_DEVICE_MEMORY)
+}
+
+fn result_name(r: VkResult) -> String
+{
+    match r
+    {
+        VK_SUCCESS => "SUCCESS".to_owned(),
+        VK_SUBOPTIMAL_KHR => "SUBOPTIMAL_KHR".to_owned(),
+        VK_ERROR_OUT_OF_DATE_KHR => "ERROR_OUT_OF_DATE_KHR".to_owned(),
+        VK_ERROR_DEVICE_LOST => "ERROR_DEVICE_LOST".to_owned(),
+        VK_ERROR_SURFACE_LOST_KHR => "ERROR_SURFACE_LOST_KHR".to_owned(),
+        VK_ERROR_EXTENSION_NOT_PRESENT => "ERROR_EXTENSION_NOT_PRESENT".to_owned(),
+        VK_ERROR_FEATURE_NOT_PRESENT => "ERROR_FEATURE_NOT_PRESENT".to_owned(),
+        x => format!("{}", x)
+    }
+}
+
+fn layout_name(l: VkImageLayout) -> String
+{
+    match l
+    {
+        VK_IMAGE_LAYOUT_UNDEFINED => "UNDEFINED".to_owned(),
+        VK_IMAGE_LAYOUT_GENERAL => "GENERAL".to_owned(),
+        VK_IMAGE_LAYOUT_COLOR_ATTACHMENT_OPTIMAL => "COLOR_ATTACHMENT_OPTIMAL".to_owned(),
+        VK_IMAGE_LAYOUT_SHADER_READ_ONLY_OPTIMAL => "SHADER_READ_ONLY_OPTIMAL".to_owned(),
+        VK_IMAGE_LAYOUT_TRANSFER_SRC_OPTIMAL => "TRANSFER_SRC_OPTIMAL".to_owned(),
+        VK_IMAGE_LAYOUT_TRANSFER_DST_OPTIMAL => "TRANSFER_DST_OPTIMAL".to_owned(),
+        VK_IMAGE_LAYOUT_PRESENT_SRC_KHR => "PRESENT_SRC_KHR".to_owned(),
+        x => format!("{}", x)
+    }
+}
+fn flag_names(flags: u32, table: &[(u32, &str)]) -> String
+{
+    if flags == 0 { return "0".to_owned(); }
+    let mut names: Vec<String> = table.iter().filter(|&&(b, _)| flags & b != 0).map(|&(_, n)| n.to_owned()).collect();
+    let rest = table.iter().fold(flags, |f, &(b, _)| f & !b);
+    if rest != 0 { names.push(format!("{:#x}", rest)); }
+    names.join("|")
+}
+fn stage_names(s: VkPipelineStageFlags) -> String
+{
+    flag_names(s, &[
+        (VK_PIPELINE_STAGE_TOP_OF_PIPE_BIT, "TOP_OF_PIPE"),
+        (VK_PIPELINE_STAGE_COLOR_ATTACHMENT_OUTPUT_BIT, "COLOR_ATTACHMENT_OUTPUT"),
+        (VK_PIPELINE_STAGE_TRANSFER_BIT, "TRANSFER"),
+        (VK_PIPELINE_STAGE_BOTTOM_OF_PIPE_BIT, "BOTTOM_OF_PIPE"),
+        (VK_PIPELINE_STAGE_HOST_BIT, "HOST"),
+        (VK_PIPELINE_STAGE_ALL_COMMANDS_BIT, "ALL_COMMANDS")
+    ])
+}
+fn access_names(a: VkAccessFlags) -> String
+{
+    flag_names(a, &[
+        (VK_ACCESS_COLOR_ATTACHMENT_WRITE_BIT, "COLOR_ATTACHMENT_WRITE"),
+        (VK_ACCESS_TRANSFER_READ_BIT, "TRANSFER_READ"),
+        (VK_ACCESS_TRANSFER_WRITE_BIT, "TRANSFER_WRITE"),
+        (VK_ACCESS_HOST_READ_BIT, "HOST_READ"),
+        (VK_ACCESS_MEMORY_READ_BIT, "MEMORY_READ"),
+        (VK_ACCESS_MEMORY_WRITE_BIT, "MEMORY_WRITE")
+    ])
+}
+fn offsets(o: &[VkOffset3D; 2]) -> String
+{
+    format!("({},{})-({},{})", o[0].x, o[0].y, o[1].x, o[1].y)
+}
+
+/// Writes the count, or the values if the output array is given (the two-call enumeration idiom)
+unsafe fn enumerate<T: Clone>(values: &[T], count: *mut u32, out: *mut T) -> VkResult
+{
+    if out.is_null() { *count = values.len() as _; return VK_SUCCESS; }
+    let n = (*count as usize).min(values.len());
+    for (i, v) in values[..n].iter().enumerate() { *out.add(i) = v.clone(); }
+    *count = n as _;
+    if n < values.len() { VK_INCOMPLETE } else { VK_SUCCESS }
+}
+
+// Instance and Device //
+
+fn extension_properties(names: &[String]) -> Vec<VkExtensionProperties>
+{
+    names.iter().map(|n|
+    {
+        let mut p: VkExtensionProperties = unsafe { std::mem::zeroed() };
+        for (d, &s) in p.extensionName.iter_mut().zip(n.as_bytes()) { *d = s as _; }
+        p.specVersion = 1;
+        p
+    }).collect()
+}
+/// Creation result for the enabled extensions, which have to be known and not rejected
+fn extensions_result(names: &[String], known: &[String]) -> VkResult
+{
+    let r = recorder();
+    if names.iter().any(|n| !known.contains(n) || r.rejected_extensions.contains(n)) { VK_ERROR_EXTENSION_NOT_PRESENT } else { VK_SUCCESS }
+}
+fn extension_names(names: *const *const c_char, count: u32) -> Vec<String>
+{
+    (0 .. count as usize).map(|i| unsafe { CStr::from_ptr(*names.add(i)) }.to_string_lossy().into_owned()).collect()
+}
+/// The timeline semaphore feature structure in a pNext chain
+unsafe fn chained_timeline_semaphore(mut next: *const c_void) -> Option<*mut VkPhysicalDeviceTimelineSemaphoreFeaturesKHR>
+{
+    while !next.is_null()
+    {
+        let s = next as *mut VkPhysicalDeviceTimelineSemaphoreFeaturesKHR;
+        if (*s).sType == VK_STRUCTURE_TYPE_PHYSICAL_DEVICE_TIMELINE_SEMAPHORE_FEATURES_KHR { return Some(s); }
+        next = (*s).pNext;
+    }
+    None
+}
+
+extern "system" fn enumerate_instance_extension_properties(_: *const c_char, count: *mut u32, out: *mut VkExtensionProperties) -> VkResult
+{
+    let props = extension_properties(&recorder().instance_extensions);
+    unsafe { enumerate(&props, count, out) }
+}
+extern "system" fn enumerate_device_extension_properties(_: VkPhysicalDevice, _: *const c_char, count: *mut u32, out: *mut VkExtensionProperties) -> VkResult
+{
+    let props = extension_properties(&recorder().device_extensions);
+    unsafe { enumerate(&props, count, out) }
+}
+extern "system" fn get_physical_device_features2(_: VkPhysicalDevice, features: *mut VkPhysicalDeviceFeatures2KHR)
+{
+    let supported = recorder().timeline_semaphore;
+    if let Some(t) = unsafe { chained_timeline_semaphore((*features).pNext) } { unsafe { (*t).timelineSemaphore = supported as _; } }
+}
+extern "system" fn create_instance(info: *const VkInstanceCreateInfo, _: *const VkAllocationCallbacks, out: *mut VkInstance) -> VkResult
+{
+    let info = unsafe { &*info };
+    let names = extension_names(info.ppEnabledExtensionNames, info.enabledExtensionCount);
+    let known = recorder().instance_extensions.clone();
+    let r = extensions_result(&names, &known);
+    let result = if r == VK_SUCCESS { String::new() } else { format!(" -> {}", result_name(r)) };
+    record(format!("vkCreateInstance([{}]){}", names.join(", "), result));
+    if r == VK_SUCCESS { unsafe { *out = FAKE_INSTANCE as _; } }
+    r
+}
+extern "system" fn create_device(pd: VkPhysicalDevice, info: *const VkDeviceCreateInfo, _: *const VkAllocationCallbacks, out: *mut VkDevice) -> VkResult
+{
+    let info = unsafe { &*info };
+    let names = extension_names(info.ppEnabledExtensionNames, info.enabledExtensionCount);
+    let timeline_semaphore = unsafe { chained_timeline_semaphore(info.pNext) }.map_or(false, |t| unsafe { (*t).timelineSemaphore != 0 });
+    let (known, supported) = { let r = recorder(); (r.device_extensions.clone(), r.timeline_semaphore) };
+    let r = match extensions_result(&names, &known)
+    {
+        VK_SUCCESS if timeline_semaphore && !supported => VK_ERROR_FEATURE_NOT_PRESENT,
+        r => r
+    };
+    let result = if r == VK_SUCCESS { String::new() } else { format!(" -> {}", result_name(r)) };
+    let features = if timeline_semaphore { ", timelineSemaphore" } else { "" };
+    record(format!("vkCreateDevice({}, [{}]{}){}", label(pd), names.join(", "), features, result));
+    if r == VK_SUCCESS { unsafe { *out = FAKE_DEVICE as _; } }
+    r
+}
+
+// Synchronization //
+
+extern "system" fn create_semaphore(_: VkDevice, _: *const VkSemaphoreCreateInfo, _: *const VkAllocationCallbacks, out: *mut VkSemaphore) -> VkResult
+{
+    if let Some(r) = injected_failure("vkCreateSemaphore") { return r; }
+    let h: VkSemaphore = new_handle();
+    record(format!("vkCreateSemaphore() -> {}", label_new("semaphore", h as _)));
+    unsafe { *out = h; }
+    VK_SUCCESS
+}
+extern "system" fn destroy_semaphore(_: VkDevice, h: VkSemaphore, _: *const VkAllocationCallbacks)
+{
+    record(format!("vkDestroySemaphore({})", label(h)));
+}
+extern "system" fn create_fence(_: VkDevice, _: *const VkFenceCreateInfo, _: *const VkAllocationCallbacks, out: *mut VkFence) -> VkResult
+{
+    if let Some(r) = injected_failure("vkCreateFence") { return r; }
+    let h: VkFence = new_handle();
+    record(format!("vkCreateFence() -> {}", label_new("fence", h as _)));
+    unsafe { *out = h; }
+    VK_SUCCESS
+}
+extern "system" fn destroy_fence(_: VkDevice, h: VkFence, _: *const VkAllocationCallbacks)
+{
+    record(format!("vkDestroyFence({})", label(h)));
+}
+extern "system" fn wait_for_fences(_: VkDevice, count: u32, fences: *const VkFence, _: VkBool32, _: u64) -> VkResult
+{
+    record(format!("vkWaitForFences({})", labels(fences, count)));
+    let waited: Vec<_> = (0 .. count as usize).map(|i| unsafe { *fences.add(i) } as usize).collect();
+    recorder().held_fences.retain(|f| !waited.contains(f));
+    VK_SUCCESS
+}
+extern "system" fn reset_fences(_: VkDevice, count: u32, fences: *const VkFence) -> VkResult
+{
+    record(format!("vkResetFences({})", labels(fences, count)));
+    VK_SUCCESS
+}
+extern "system" fn get_fence_status(_: VkDevice, h: VkFence) -> VkResult
+{
+    record(format!("vkGetFenceStatus({})", label(h)));
+    if recorder().held_fences.contains(&(h as usize)) { VK_NOT_READY } else { VK_SUCCESS }
+}
+extern "system" fn device_wait_idle(_: VkDevice) -> VkResult
+{
+    record("vkDeviceWaitIdle()".to_owned());
+    VK_SUCCESS
+}
+
+// Queue //
+
+extern "system" fn queue_submit(q: VkQueue, count: u32, submits: *const VkSubmitInfo, fence: VkFence) -> VkResult
+{
+    if let Some(r) = injected_failure("vkQueueSubmit") { return r; }
+    for i in 0 .. count as usize
+    {
+        let s = unsafe { &*submits.add(i) };
+        let waits: Vec<_> = (0 .. s.waitSemaphoreCount as usize).map(|w| unsafe
+        {
+            format!("{} @ {}", label(*s.pWaitSemaphores.add(w)), stage_names(*s.pWaitDstStageMask.add(w)))
+        }).collect();
+        record(format!("vkQueueSubmit({}, wait [{}], {}, signal {}, {})", label(q), waits.join(", "),
+            labels(s.pCommandBuffers, s.commandBufferCount), labels(s.pSignalSemaphores, s.signalSemaphoreCount),
+            if i + 1 == count as usize { label(fence) } else { "null".to_owned() }));
+    }
+    let mut r = recorder();
+    if !fence.is_null() && std::mem::replace(&mut r.hold_next_fence, false) { r.held_fences.push(fence as usize); }
+    VK_SUCCESS
+}
+extern "system" fn queue_present(q: VkQueue, info: *const VkPresentInfoKHR) -> VkResult
+{
+    let p = unsafe { &*info };
+    let targets: Vec<_> = (0 .. p.swapchainCount as usize).map(|i| unsafe
+    {
+        format!("{} #{}", label(*p.pSwapchains.add(i)), *p.pImageIndices.add(i))
+    }).collect();
+    let r = recorder().present_results.pop_front().unwrap_or(VK_SUCCESS);
+    let result = if r == VK_SUCCESS { String::new() } else { format!(" -> {}", result_name(r)) };
+    record(format!("vkQueuePresentKHR({}, wait {}, [{}]){}", label(q), labels(p.pWaitSemaphores, p.waitSemaphoreCount), targets.join(", "), result));
+    r
+}
+
+// Surface and Swapchain //
+
+extern "system" fn create_headless_surface(_: VkInstance, _: *const c_void, _: *const VkAllocationCallbacks, out: *mut VkSurfaceKHR) -> VkResult
+{
+    let h: VkSurfaceKHR = new_handle();
+    record(format!("vkCreateHeadlessSurfaceEXT() -> {}", label_new("surface", h as _)));
+    unsafe { *out = h; }
+    VK_SUCCESS
+}
+extern "system" fn destroy_surface(_: VkInstance, h: VkSurfaceKHR, _: *const VkAllocationCallbacks)
+{
+    record(format!("vkDestroySurfaceKHR({})", label(h)));
+}
+extern "system" fn get_surface_support(_: VkPhysicalDevice, _: u32, _: VkSurfaceKHR, out: *mut VkBool32) -> VkResult
+{
+    unsafe { *out = 1; }
+    VK_SUCCESS
+}
+extern "system" fn get_surface_formats(_: VkPhysicalDevice, _: VkSurfaceKHR, count: *mut u32, out: *mut VkSurfaceFormatKHR) -> VkResult
+{
+    let formats = recorder().surface_formats.clone()
+        .unwrap_or_else(|| vec![VkSurfaceFormatKHR { format: VK_FORMAT_B8G8R8A8_SRGB, colorSpace: VK_COLOR_SPACE_SRGB_NONLINEAR_KHR }]);
+    unsafe { enumerate(&formats, count, out) }
+}
+extern "system" fn get_surface_present_modes(_: VkPhysicalDevice, _: VkSurfaceKHR, count: *mut u32, out: *mut VkPresentModeKHR) -> VkResult
+{
+    unsafe { enumerate(&[VK_PRESENT_MODE_FIFO_KHR], count, out) }
+}
+extern "system" fn get_surface_capabilities(_: VkPhysicalDevice, _: VkSurfaceKHR, out: *mut VkSurfaceCapabilitiesKHR) -> VkResult
+{
+    unsafe
+    {
+        let mut caps: VkSurfaceCapabilitiesKHR = std::mem::zeroed();
+        caps.minImageCount = SWAPCHAIN_IMAGE_COUNT;
+        caps.maxImageCount = SWAPCHAIN_IMAGE_COUNT;
+        // headless surfaces let the swapchain decide the extent
+        let (w, h) = recorder().surface_extent.unwrap_or((0xffff_ffff, 0xffff_ffff));
+        caps.currentExtent = VkExtent2D { width: w, height: h };
+        caps.minImageExtent = VkExtent2D { width: 1, height: 1 };
+        caps.maxImageExtent = VkExtent2D { width: 16384, height: 16384 };
+        caps.maxImageArrayLayers = 1;
+        caps.supportedTransforms = VK_SURFACE_TRANSFORM_IDENTITY_BIT_KHR;
+        caps.currentTransform = VK_SURFACE_TRANSFORM_IDENTITY_BIT_KHR;
+        caps.supportedCompositeAlpha = VK_COMPOSITE_ALPHA_OPAQUE_BIT_KHR;
+        caps.supportedUsageFlags = VK_IMAGE_USAGE_TRANSFER_DST_BIT | VK_IMAGE_USAGE_TRANSFER_SRC_BIT;
+        *out = caps;
+    }
+    VK_SUCCESS
+}
+extern "system" fn create_swapchain(_: VkDevice, info: *const VkSwapchainCreateInfoKHR, _: *const VkAllocationCallbacks, out: *mut VkSwapchainKHR) -> VkResult
+{
+    let h: VkSwapchainKHR = new_handle();
+    let info = unsafe { &*info };
+    record(format!("vkCreateSwapchainKHR({}, {}x{}, min {} images, old {}) -> {}", label(info.surface),
+        info.imageExtent.width, info.imageExtent.height, info.minImageCount, label(info.oldSwapchain), label_new("swapchain", h as _)));
+    unsafe { *out = h; }
+    VK_SUCCESS
+}
+extern "system" fn destroy_swapchain(_: VkDevice, h: VkSwapchainKHR, _: *const VkAllocationCallbacks)
+{
+    record(format!("vkDestroySwapchainKHR({})", label(h)));
+}
+extern "system" fn get_swapchain_images(_: VkDevice, sc: VkSwapchainKHR, count: *mut u32, out: *mut VkImage) -> VkResult
+{
+    // derived from the swapchain so repeated queries agree; swapchain handles are 0x10 apart
+    let images: Vec<VkImage> = (1 ..= SWAPCHAIN_IMAGE_COUNT as usize).map(|i| (sc as usize + i) as VkImage).collect();
+    if !out.is_null()
+    {
+        for &i in &images
+        {
+            if !recorder().labels.contains_key(&(i as usize)) { label_new("swapchain_image", i as _); }
+        }
+    }
+    unsafe { enumerate(&images, count, out) }
+}
+extern "system" fn acquire_next_image(_: VkDevice, sc: VkSwapchainKHR, _: u64, sem: VkSemaphore, fence: VkFence, index: *mut u32) -> VkResult
+{
+    let r = recorder().acquire_results.pop_front().unwrap_or(VK_SUCCESS);
+    if r != VK_SUCCESS && r != VK_SUBOPTIMAL_KHR
+    {
+        record(format!("vkAcquireNextImageKHR({}, {}, {}) -> {}", label(sc), label(sem), label(fence), result_name(r)));
+        return r;
+    }
+    let i = NEXT_ACQUIRE.fetch_add(1, Ordering::SeqCst) % SWAPCHAIN_IMAGE_COUNT as usize;
+    let result = if r == VK_SUCCESS { String::new() } else { format!(" {}", result_name(r)) };
+    record(format!("vkAcquireNextImageKHR({}, {}, {}) -> #{}{}", label(sc), label(sem), label(fence), i, result));
+    unsafe { *index = i as _; }
+    r
+}
+
+// Command Buffers //
+
+extern "system" fn create_command_pool(_: VkDevice, _: *const VkCommandPoolCreateInfo, _: *const VkAllocationCallbacks, out: *mut VkCommandPool) -> VkResult
+{
+    if let Some(r) = injected_failure("vkCreateCommandPool") { return r; }
+    let h: VkCommandPool = new_handle();
+    record(format!("vkCreateCommandPool() -> {}", label_new("command_pool", h as _)));
+    unsafe { *out = h; }
+    VK_SUCCESS
+}
+extern "system" fn destroy_command_pool(_: VkDevice, h: VkCommandPool, _: *const VkAllocationCallbacks)
+{
+    record(format!("vkDestroyCommandPool({})", label(h)));
+}
+extern "system" fn reset_command_pool(_: VkDevice, h: VkCommandPool, flags: VkCommandPoolResetFlags) -> VkResult
+{
+    record(format!("vkResetCommandPool({}, {:#x})", label(h), flags));
+    VK_SUCCESS
+}
+extern "system" fn allocate_command_buffers(_: VkDevice, info: *const VkCommandBufferAllocateInfo, out: *mut VkCommandBuffer) -> VkResult
+{
+    if let Some(r) = injected_failure("vkAllocateCommandBuffers") { return r; }
+    let info = unsafe { &*info };
+    let mut created = Vec::new();
+    for i in 0 .. info.commandBufferCount as usize
+    {
+        let h: VkCommandBuffer = new_handle();
+        created.push(label_new("command_buffer", h as _));
+        unsafe { *out.add(i) = h; }
+    }
+    record(format!("vkAllocateCommandBuffers({}) -> [{}]", label(info.commandPool), created.join(", ")));
+    VK_SUCCESS
+}
+extern "system" fn begin_command_buffer(cb: VkCommandBuffer, _: *const VkCommandBufferBeginInfo) -> VkResult
+{
+    if let Some(r) = injected_failure("vkBeginCommandBuffer") { return r; }
+    record(format!("vkBeginCommandBuffer({})", label(cb)));
+    VK_SUCCESS
+}
+extern "system" fn end_command_buffer(cb: VkCommandBuffer) -> VkResult
+{
+    if let Some(r) = injected_failure("vkEndCommandBuffer") { return r; }
+    record(format!("vkEndCommandBuffer({})", label(cb)));
+    VK_SUCCESS
+}
+extern "system" fn cmd_pipeline_barrier(cb: VkCommandBuffer, src_stage: VkPipelineStageFlags, dst_stage: VkPipelineStageFlags, _: VkDependencyFlags,
+    memory_barrier_count: u32, _: *const VkMemoryBarrier,
+    buffer_barrier_count: u32, buffer_barriers: *const VkBufferMemoryBarrier,
+    image_barrier_count: u32, image_barriers: *const VkImageMemoryBarrier)
+{
+    let mut barriers = Vec::new();
+    for _ in 0 .. memory_barrier_count { barriers.push("memory".to_owned()); }
+    for i in 0 .. buffer_barrier_count as usize
+    {
+        let b = unsafe { &*buffer_barriers.add(i) };
+        barriers.push(format!("{}: {} -> {}", label(b.buffer), access_names(b.srcAccessMask), access_names(b.dstAccessMask)));
+    }
+    for i in 0 .. image_barrier_count as usize
+    {
+        let b = unsafe { &*image_barriers.add(i) };
+        barriers.push(format!("{}: {} -> {}, {} -> {}", label(b.image), layout_name(b.oldLayout), layout_name(b.newLayout),
+            access_names(b.srcAccessMask), access_names(b.dstAccessMask)));
+    }
+    record(format!("vkCmdPipelineBarrier({}, {} -> {}, {})", label(cb), stage_names(src_stage), stage_names(dst_stage), barriers.join("; ")));
+}
+extern "system" fn cmd_blit_image(cb: VkCommandBuffer, src: VkImage, src_layout: VkImageLayout, dst: VkImage, dst_layout: VkImageLayout,
+    count: u32, regions: *const VkImageBlit, filter: VkFilter)
+{
+    let rs: Vec<_> = (0 .. count as usize).map(|i|
+    {
+        let r = unsafe { &*regions.add(i) };
+        format!("{} -> {}", offsets(&r.srcOffsets), offsets(&r.dstOffsets))
+    }).collect();
+    record(format!("vkCmdBlitImage({}, {}: {} -> {}: {}, [{}], {})", label(cb), label(src), layout_name(src_layout),
+        label(dst), layout_name(dst_layout), rs.join(", "), if filter == VK_FILTER_LINEAR { "LINEAR" } else { "NEAREST" }));
+}
+extern "system" fn cmd_clear_color_image(cb: VkCommandBuffer, image: VkImage, layout: VkImageLayout, color: *const VkClearColorValue,
+    _: u32, _: *const VkImageSubresourceRange)
+{
+    let c = unsafe { (*color).float32 };
+    record(format!("vkCmdClearColorImage({}, {}: {}, ({}, {}, {}, {}))", label(cb), label(image), layout_name(layout), c[0], c[1], c[2], c[3]));
+}
+extern "system" fn cmd_copy_image_to_buffer(cb: VkCommandBuffer, src: VkImage, src_layout: VkImageLayout, dst: VkBuffer,
+    count: u32, regions: *const VkBufferImageCopy)
+{
+    let rs: Vec<_> = (0 .. count as usize).map(|i|
+    {
+        let r = unsafe { &*regions.add(i) };
+        format!("({},{}) {}x{} @{}", r.imageOffset.x, r.imageOffset.y, r.imageExtent.width, r.imageExtent.height, r.bufferOffset)
+    }).collect();
+    record(format!("vkCmdCopyImageToBuffer({}, {}: {} -> {}, [{}])", label(cb), label(src), layout_name(src_layout), label(dst), rs.join(", ")));
+}
+extern "system" fn cmd_resolve_image(cb: VkCommandBuffer, src: VkImage, src_layout: VkImageLayout, dst: VkImage, dst_layout: VkImageLayout,
+    count: u32, regions: *const VkImageResolve)
+{
+    let rs: Vec<_> = (0 .. count as usize).map(|i|
+    {
+        let r = unsafe { &*regions.add(i) };
+        format!("({},{}) {}x{}", r.srcOffset.x, r.srcOffset.y, r.extent.width, r.extent.height)
+    }).collect();
+    record(format!("vkCmdResolveImage({}, {}: {} -> {}: {}, [{}])", label(cb), label(src), layout_name(src_layout),
+        label(dst), layout_name(dst_layout), rs.join(", ")));
+}
+/// Only the number of regions and the first and last of them are recorded; transpositions issue one per row
+extern "system" fn cmd_copy_buffer_to_image(cb: VkCommandBuffer, src: VkBuffer, dst: VkImage, dst_layout: VkImageLayout,
+    count: u32, regions: *const VkBufferImageCopy)
+{
+    let region = |i: usize|
+    {
+        let r = unsafe { &*regions.add(i) };
+        format!("@{} row {} -> ({},{}) {}x{}", r.bufferOffset, r.bufferRowLength, r.imageOffset.x, r.imageOffset.y, r.imageExtent.width, r.imageExtent.height)
+    };
+    let rs = if count == 0 { String::new() } else { format!("{} .. {}", region(0), region(count as usize - 1)) };
+    record(format!("vkCmdCopyBufferToImage({}, {} -> {}: {}, {} regions [{}])", label(cb), label(src), label(dst), layout_name(dst_layout), count, rs));
+}
+
+// Memory //
+
+extern "system" fn get_memory_properties(_: VkPhysicalDevice, out: *mut VkPhysicalDeviceMemoryProperties)
+{
+    unsafe
+    {
+        let mut props: VkPhysicalDeviceMemoryProperties = std::mem::zeroed();
+        props.memoryTypeCount = 1;
+        props.memoryTypes[0].propertyFlags = VK_MEMORY_PROPERTY_HOST_VISIBLE_BIT | VK_MEMORY_PROPERTY_HOST_COHERENT_BIT | VK_MEMORY_PROPERTY_HOST_CACHED_BIT;
+        props.memoryHeapCount = 1;
+        props.memoryHeaps[0].size = 1 << 30;
+        *out = props;
+    }
+}
+extern "system" fn create_buffer(_: VkDevice, info: *const VkBufferCreateInfo, _: *const VkAllocationCallbacks, out: *mut VkBuffer) -> VkResult
+{
+    if let Some(r) = injected_failure("vkCreateBuffer") { return r; }
+    let b: VkBuffer = new_handle();
+    let size = unsafe { (*info).size };
+    BUFFER_SIZES.lock().unwrap().insert(b as usize, size);
+    record(format!("vkCreateBuffer({} bytes) -> {}", size, label_new("buffer", b as _)));
+    unsafe { *out = b; }
+    VK_SUCCESS
+}
+extern "system" fn destroy_buffer(_: VkDevice, b: VkBuffer, _: *const VkAllocationCallbacks)
+{
+    record(format!("vkDestroyBuffer({})", label(b)));
+    BUFFER_SIZES.lock().unwrap().remove(&(b as usize));
+}
+extern "system" fn get_buffer_memory_requirements(_: VkDevice, b: VkBuffer, out: *mut VkMemoryRequirements)
+{
+    let size = BUFFER_SIZES.lock().unwrap().get(&(b as usize)).cloned().unwrap_or(0);
+    unsafe { *out = VkMemoryRequirements { size, alignment: 16, memoryTypeBits: 1 }; }
+}
+extern "system" fn create_image(_: VkDevice, info: *const VkImageCreateInfo, _: *const VkAllocationCallbacks, out: *mut VkImage) -> VkResult
+{
+    let i: VkImage = new_handle();
+    let extent = unsafe { (*info).extent };
+    IMAGE_SIZES.lock().unwrap().insert(i as usize, extent.width as VkDeviceSize * extent.height as VkDeviceSize * 4);
+    record(format!("vkCreateImage({}x{}) -> {}", extent.width, extent.height, label_new("image", i as _)));
+    unsafe { *out = i; }
+    VK_SUCCESS
+}
+extern "system" fn destroy_image(_: VkDevice, i: VkImage, _: *const VkAllocationCallbacks)
+{
+    record(format!("vkDestroyImage({})", label(i)));
+    IMAGE_SIZES.lock().unwrap().remove(&(i as usize));
+}
+extern "system" fn get_image_memory_requirements(_: VkDevice, i: VkImage, out: *mut VkMemoryRequirements)
+{
+    let size = IMAGE_SIZES.lock().unwrap().get(&(i as usize)).cloned().unwrap_or(0);
+    unsafe { *out = VkMemoryRequirements { size, alignment: 16, memoryTypeBits: 1 }; }
+}
+extern "system" fn allocate_memory(_: VkDevice, info: *const VkMemoryAllocateInfo, _: *const VkAllocationCallbacks, out: *mut VkDeviceMemory) -> VkResult
+{
+    if let Some(r) = injected_failure("vkAllocateMemory") { return r; }
+    let mut bytes = vec![0u8; unsafe { (*info).allocationSize } as usize];
+    let h = bytes.as_mut_ptr() as usize;
+    MEMORIES.lock().unwrap().insert(h, bytes);
+    record(format!("vkAllocateMemory({} bytes) -> {}", unsafe { (*info).allocationSize }, label_new("memory", h)));
+    unsafe { *out = h as VkDeviceMemory; }
+    VK_SUCCESS
+}
+extern "system" fn free_memory(_: VkDevice, m: VkDeviceMemory, _: *const VkAllocationCallbacks)
+{
+    record(format!("vkFreeMemory({})", label(m)));
+    MEMORIES.lock().unwrap().remove(&(m as usize));
+}
+extern "system" fn bind_buffer_memory(_: VkDevice, _: VkBuffer, _: VkDeviceMemory, _: VkDeviceSize) -> VkResult
+{
+    injected_failure("vkBindBufferMemory").unwrap_or(VK_SUCCESS)
+}
+extern "system" fn bind_image_memory(_: VkDevice, _: VkImage, _: VkDeviceMemory, _: VkDeviceSize) -> VkResult { VK_SUCCESS }
+extern "system" fn map_memory(_: VkDevice, m: VkDeviceMemory, offset: VkDeviceSize, _: VkDeviceSize, _: VkMemoryMapFlags, out: *mut *mut c_void) -> VkResult
+{
+    if let Some(r) = injected_failure("vkMapMemory") { return r; }
+    unsafe { *out = (m as *mut u8).add(offset as usize) as *mut c_void; }
+    VK_SUCCESS
+}
+
+/// Resolves the stub entry points by name
+pub fn resolve(name: &[u8]) -> Option<PFN_vkVoidFunction>
+{
+    macro_rules! entry { ($f: expr) => { Some(unsafe { std::mem::transmute($f as *const c_void) }) } }
+
+    match name
+    {
+        b"vkEnumerateInstanceExtensionProperties" => entry!(enumerate_instance_extension_properties),
+        b"vkEnumerateDeviceExtensionProperties" => entry!(enumerate_device_extension_properties),
+        b"vkGetPhysicalDeviceFeatures2KHR" => entry!(get_physical_device_features2),
+        b"vkCreateInstance" => entry!(create_instance),
+        b"vkCreateDevice" => entry!(create_device),
+        b"vkCreateSemaphore" => entry!(create_semaphore),
+        b"vkDestroySemaphore" => entry!(destroy_semaphore),
+        b"vkCreateFence" => entry!(create_fence),
+        b"vkDestroyFence" => entry!(destroy_fence),
+        b"vkWaitForFences" => entry!(wait_for_fences),
+        b"vkResetFences" => entry!(reset_fences),
+        b"vkGetFenceStatus" => entry!(get_fence_status),
+        b"vkDeviceWaitIdle" => entry!(device_wait_idle),
+        b"vkQueueSubmit" => entry!(queue_submit),
+        b"vkQueuePresentKHR" => entry!(queue_present),
+        b"vkCreateHeadlessSurfaceEXT" => entry!(create_headless_surface),
+        b"vkDestroySurfaceKHR" => entry!(destroy_surface),
+        b"vkGetPhysicalDeviceSurfaceSupportKHR" => entry!(get_surface_support),
+        b"vkGetPhysicalDeviceSurfaceFormatsKHR" => entry!(get_surface_formats),
+        b"vkGetPhysicalDeviceSurfacePresentModesKHR" => entry!(get_surface_present_modes),
+        b"vkGetPhysicalDeviceSurfaceCapabilitiesKHR" => entry!(get_surface_capabilities),
+        b"vkCreateSwapchainKHR" => entry!(create_swapchain),
+        b"vkDestroySwapchainKHR" => entry!(destroy_swapchain),
+        b"vkGetSwapchainImagesKHR" => entry!(get_swapchain_images),
+        b"vkAcquireNextImageKHR" => entry!(acquire_next_image),
+        b"vkCreateCommandPool" => entry!(create_command_pool),
+        b"vkDestroyCommandPool" => entry!(destroy_command_pool),
+        b"vkResetCommandPool" => entry!(reset_command_pool),
+        b"vkAllocateCommandBuffers" => entry!(allocate_command_buffers),
+        b"vkBeginCommandBuffer" => entry!(begin_command_buffer),
+        b"vkEndCommandBuffer" => entry!(end_command_buffer),
+        b"vkCmdPipelineBarrier" => entry!(cmd_pipeline_barrier),
+        b"vkCmdBlitImage" => entry!(cmd_blit_image),
+        b"vkCmdClearColorImage" => entry!(cmd_clear_color_image),
+        b"vkCmdCopyImageToBuffer" => entry!(cmd_copy_image_to_buffer),
+        b"vkCmdCopyBufferToImage" => entry!(cmd_copy_buffer_to_image),
+        b"vkCmdResolveImage" => entry!(cmd_resolve_image),
+        b"vkGetPhysicalDeviceMemoryProperties" => entry!(get_memory_properties),
+        b"vkCreateBuffer" => entry!(create_buffer),
+        b"vkDestroyBuffer" => entry!(destroy_buffer),
+        b"vkGetBufferMemoryRequirements" => entry!(get_buffer_memory_requirements),
+        b"vkAllocateMemory" => entry!(allocate_memory),
+        b"vkFreeMemory" => entry!(free_memory),
+        b"vkBindBufferMemory" => entry!(bind_buffer_memory),
+        b"vkCreateImage" => entry!(create_image),
+        b"vkDestroyImage" => entry!(destroy_image),
+        b"vkGetImageMemoryRequirements" => entry!(get_image_memory_requirements),
+        b"vkBindImageMemory" => entry!(bind_image_memory),
+        b"vkMapMemory" => entry!(map_memory),
+        _ => None
+    }
+}
+pub extern "system" fn get_instance_proc_addr(_: VkInstance, name: *const c_char) -> Option<PFN_vkVoidFunction>
+{
+    let name = unsafe { CStr::from_ptr(name) }.to_bytes();
+    if recorder().hidden_entry_points.iter().any(|h| h.as_bytes() == name) { return None; }
+    resolve(name)
+}
@@ -0,0 +1,883 @@
+//! Plugin behaviour observed from a fake Unity host
+
+mod common;
+
+use bedrock::vk::*;
+use common::*;
+use RenderingInterceptor::unity::*;
+use RenderingInterceptor::extensions;
+
+#[test]
+fn load_registers_device_event_callback()
+{
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+
+    assert_eq!(host.registered_device_event_callbacks(), 1);
+    assert!(host.calls().contains(&HostCall::GetInterface(IUnityGraphics::GUID)));
+    assert!(host.calls().contains(&HostCall::RegisterDeviceEventCallback));
+}
+
+#[test]
+fn unload_unregisters_device_event_callback()
+{
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    host.fire_device_event(UnityGfxDeviceEventType::Shutdown);
+    host.unload_plugin();
+
+    assert_eq!(host.registered_device_event_callbacks(), 0);
+    assert!(host.calls().contains(&HostCall::UnregisterDeviceEventCallback));
+}
+
+#[test]
+fn non_vulkan_renderer_is_ignored()
+{
+    let mut host = MockUnityHost::new(UnityGfxRenderer::D3D11);
+    host.load_plugin();
+
+    assert!(host.calls().contains(&HostCall::GetRenderer));
+    assert!(host.configured_events().is_empty());
+    assert!(!host.calls().contains(&HostCall::Instance));
+}
+
+#[test]
+fn initialize_configures_capture_event()
+{
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+
+    let event = |event_id, render_pass_precondition, graphics_queue_access, flags| HostCall::ConfigureEvent
+    {
+        event_id, render_pass_precondition, graphics_queue_access, flags
+    };
+    use UnityVulkanEventRenderPassPreCondition::*;
+    use UnityVulkanGraphicsQueueAccess::*;
+    assert_eq!(host.configured_events(), vec![
+        event(1000, EnsureOutside, Allow, UnityVulkanEventConfigFlags::FlushCommandBuffers),
+        event(1001, EnsureOutside, Allow, UnityVulkanEventConfigFlags::FlushCommandBuffers),
+        event(1002, DontCare, DontCare, UnityVulkanEventConfigFlags::empty()),
+        event(1003, DontCare, DontCare, UnityVulkanEventConfigFlags::empty()),
+        event(1004, EnsureOutside, Allow, UnityVulkanEventConfigFlags::FlushCommandBuffers)
+    ]);
+}
+
+#[test]
+fn capture_event_accesses_render_buffer_for_transfer()
+{
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    host.clear_calls();
+
+    host.set_render_buffer(0x4000);
+    host.issue_capture_event();
+
+    assert_eq!(host.calls(), vec![HostCall::AccessRenderBufferTexture
+    {
+        render_buffer: 0x4000,
+        layout: VK_IMAGE_LAYOUT_TRANSFER_SRC_OPTIMAL,
+        access_mode: UnityVulkanResourceAccessMode::PipelineBarrier
+    }]);
+}
+
+#[test]
+fn multisampled_render_buffer_asks_for_resolve_texture()
+{
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    host.clear_calls();
+    host.set_render_buffer_samples(VK_SAMPLE_COUNT_4_BIT, true);
+
+    host.set_render_buffer(0x4000);
+    host.issue_capture_event();
+
+    assert_eq!(host.calls()[1 ..], [HostCall::AccessRenderBufferResolveTexture
+    {
+        render_buffer: 0x4000,
+        layout: VK_IMAGE_LAYOUT_TRANSFER_SRC_OPTIMAL,
+        access_mode: UnityVulkanResourceAccessMode::PipelineBarrier
+    }]);
+    assert_eq!(host.last_error(), None);
+}
+
+#[test]
+fn unknown_event_is_ignored()
+{
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    host.clear_calls();
+
+    host.set_render_buffer(0x4000);
+    host.issue_plugin_event(host.event_id(RenderingInterceptor::event::EVENT_KIND_COUNT - 1) + 1);
+    host.issue_plugin_event(host.event_id(0) - 1);
+
+    assert!(host.calls().is_empty());
+}
+
+#[test]
+fn shutdown_stops_capturing()
+{
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    host.fire_device_event(UnityGfxDeviceEventType::Shutdown);
+    host.clear_calls();
+
+    host.set_render_buffer(0x4000);
+    host.issue_capture_event();
+
+    assert!(host.calls().is_empty());
+}
+
+#[test]
+fn missing_render_buffer_is_reported_not_panicked()
+{
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+
+    host.set_render_buffer(0);
+    host.issue_capture_event();
+
+    assert_eq!(host.last_error().as_ref().map(|s| s as &str), Some("unable to access the render buffer texture"));
+    // still running
+    host.clear_calls();
+    host.set_render_buffer(0x4000);
+    host.issue_capture_event();
+    assert_eq!(host.calls().len(), 1);
+}
+
+#[test]
+fn initialization_failure_leaves_plugin_inactive()
+{
+    let mut host = MockUnityHost::vulkan();
+    common::vk_stub::hide_entry_point("vkCreateSwapchainKHR");
+    host.load_plugin();
+
+    assert_eq!(host.last_error().as_ref().map(|s| s as &str), Some("Vulkan entry point vkCreateSwapchainKHR is not available"));
+    host.clear_calls();
+    host.set_render_buffer(0x4000);
+    host.issue_capture_event();
+    assert!(host.calls().is_empty());
+    assert!(!RenderingInterceptor::set_sink_enabled(RenderingInterceptor::WINDOW_SINK_ID, false));
+}
+
+#[test]
+fn no_error_is_reported_after_successful_frames()
+{
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    host.set_render_buffer(0x4000);
+    host.issue_capture_event();
+
+    assert_eq!(host.last_error(), None);
+}
+
+#[test]
+fn load_intercepts_presentation_and_unload_restores_it()
+{
+    let intercepted = |host: &MockUnityHost| -> Vec<String>
+    {
+        host.calls().into_iter().filter_map(|c| match c { HostCall::InterceptVulkanApi(name) => Some(name), _ => None }).collect()
+    };
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    assert_eq!(intercepted(&host), ["vkQueuePresentKHR", "vkCreateSwapchainKHR", "vkDestroySwapchainKHR"]);
+
+    host.clear_calls();
+    host.unload_plugin();
+    assert_eq!(intercepted(&host), ["vkQueuePresentKHR", "vkCreateSwapchainKHR", "vkDestroySwapchainKHR"]);
+
+    // Unity calls the driver directly again
+    let swapchain = host.create_swapchain(64, 64);
+    common::vk_stub::clear_trace();
+    host.present(swapchain, 0);
+    assert_eq!(common::vk_stub::trace(), ["vkQueuePresentKHR(queue, wait [unity_render_done], [swapchain1 #0])"]);
+}
+
+#[test]
+fn vulkan_initialization_enables_available_extensions()
+{
+    let names = |v: &[&str]| v.iter().map(|&n| n.to_owned()).collect::<Vec<_>>();
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    assert!(host.calls().contains(&HostCall::InterceptInitialization));
+
+    common::vk_stub::set_available_extensions(
+        &["VK_KHR_surface", "VK_EXT_headless_surface", "VK_EXT_debug_utils", "VK_KHR_get_physical_device_properties2"],
+        &["VK_KHR_swapchain", "VK_KHR_timeline_semaphore", "VK_KHR_external_memory"]);
+    common::vk_stub::set_timeline_semaphore_support(true);
+    common::vk_stub::clear_trace();
+    assert_eq!(host.initialize_vulkan(), (VK_SUCCESS, VK_SUCCESS));
+
+    assert_eq!(common::vk_stub::trace(), [
+        "vkCreateInstance([VK_KHR_surface, VK_EXT_headless_surface, VK_EXT_debug_utils, VK_KHR_get_physical_device_properties2])",
+        "vkCreateDevice(physical_device, [VK_KHR_swapchain, VK_KHR_external_memory, VK_KHR_timeline_semaphore], timelineSemaphore)"
+    ]);
+    assert_eq!(extensions::enabled(), Some(extensions::EnabledExtensions
+    {
+        instance: names(&["VK_KHR_surface", "VK_EXT_headless_surface", "VK_EXT_debug_utils", "VK_KHR_get_physical_device_properties2"]),
+        device: names(&["VK_KHR_swapchain", "VK_KHR_external_memory", "VK_KHR_timeline_semaphore"]),
+        timeline_semaphore: true
+    }));
+}
+
+#[test]
+fn additions_the_driver_rejects_are_dropped()
+{
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    common::vk_stub::set_available_extensions(&["VK_KHR_surface", "VK_KHR_get_physical_device_properties2"], &["VK_KHR_swapchain", "VK_KHR_timeline_semaphore"]);
+    common::vk_stub::set_timeline_semaphore_support(true);
+    common::vk_stub::reject_extension("VK_KHR_timeline_semaphore");
+    common::vk_stub::clear_trace();
+    assert_eq!(host.initialize_vulkan(), (VK_SUCCESS, VK_SUCCESS));
+
+    assert_eq!(common::vk_stub::trace()[1 ..], [
+        "vkCreateDevice(physical_device, [VK_KHR_swapchain, VK_KHR_timeline_semaphore], timelineSemaphore) -> ERROR_EXTENSION_NOT_PRESENT",
+        "vkCreateDevice(physical_device, [VK_KHR_swapchain])"
+    ]);
+    let enabled = extensions::enabled().unwrap();
+    assert_eq!(enabled.device, ["VK_KHR_swapchain"]);
+    assert!(!enabled.timeline_semaphore);
+}
+
+#[test]
+fn window_needs_its_surface_extension()
+{
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    common::vk_stub::set_available_extensions(&["VK_KHR_surface"], &["VK_KHR_swapchain"]);
+    host.initialize_vulkan();
+    host.fire_device_event(UnityGfxDeviceEventType::Initialize);
+
+    assert_eq!(host.last_error().as_ref().map(|s| s as &str), Some("Vulkan extension VK_EXT_headless_surface is not enabled"));
+}
+
+#[test]
+fn surface_without_formats_is_reported_not_panicked()
+{
+    use RenderingInterceptor::live_objects;
+    let mut host = MockUnityHost::vulkan();
+    common::vk_stub::set_surface_formats(&[]);
+    host.load_plugin();
+
+    assert_eq!(host.last_error().as_ref().map(|s| s as &str), Some("the graphics queue cannot present to the window system"));
+    assert_eq!(live_objects::live(), Vec::<(&str, isize)>::new());
+}
+
+#[test]
+fn offscreen_mode_leaves_presentation_to_the_plugin()
+{
+    let mut host = MockUnityHost::vulkan();
+    std::env::set_var(RenderingInterceptor::window::OFFSCREEN_ENV_NAME, "1");
+    host.load_plugin();
+
+    assert!(host.calls().contains(&HostCall::ConfigureSwapchain(UnityVulkanSwapchainMode::Offscreen)));
+    assert!(RenderingInterceptor::unity_renders_offscreen());
+    // Unity has no swapchain whose presentation could be captured
+    assert!(!host.calls().iter().any(|c| match c { HostCall::InterceptVulkanApi(_) => true, _ => false }));
+
+    common::vk_stub::clear_trace();
+    host.set_render_buffer(0x4000);
+    host.issue_capture_event();
+    assert!(common::vk_stub::trace().iter().any(|l| l.starts_with("vkQueuePresentKHR(queue") && l.contains("swapchain0")));
+}
+
+#[test]
+fn unity_keeps_its_swapchain_by_default()
+{
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+
+    assert!(!host.calls().iter().any(|c| match c { HostCall::ConfigureSwapchain(_) => true, _ => false }));
+    assert!(!RenderingInterceptor::unity_renders_offscreen());
+}
+
+#[test]
+fn shutdown_destroys_every_object()
+{
+    use RenderingInterceptor::live_objects;
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    // every part that owns objects: readback, MSAA resolve, rotation and presented frame capture
+    assert!(RenderingInterceptor::set_readback_callback(Some(Box::new(|_| ()))));
+    assert!(RenderingInterceptor::set_sink_transform(RenderingInterceptor::WINDOW_SINK_ID, RenderingInterceptor::transform::TRANSFORM_ROTATE_90));
+    host.set_render_buffer(0x4000);
+    host.set_render_buffer_samples(VK_SAMPLE_COUNT_4_BIT, false);
+    host.issue_capture_event();
+    host.issue_capture_event();
+    let swapchain = host.create_swapchain(1280, 720);
+    host.present(swapchain, 0);
+    if cfg!(debug_assertions) { assert!(!live_objects::live().is_empty()); }
+
+    host.fire_device_event(UnityGfxDeviceEventType::Shutdown);
+
+    assert_eq!(live_objects::live(), Vec::<(&str, isize)>::new());
+    assert_eq!(RenderingInterceptor::live_object_count(), 0);
+    host.destroy_swapchain(swapchain);
+}
+
+#[test]
+fn play_mode_cycles_leave_nothing_alive()
+{
+    use RenderingInterceptor::live_objects;
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    host.set_render_buffer(0x4000);
+    host.issue_capture_event();
+    let first = live_objects::live();
+    host.fire_device_event(UnityGfxDeviceEventType::Shutdown);
+
+    for _ in 0 .. 3
+    {
+        host.fire_device_event(UnityGfxDeviceEventType::Initialize);
+        host.issue_capture_event();
+        assert_eq!(live_objects::live(), first);
+        host.fire_device_event(UnityGfxDeviceEventType::Shutdown);
+        assert_eq!(live_objects::live(), Vec::<(&str, isize)>::new());
+    }
+    assert_eq!(host.last_error(), None);
+}
+
+#[test]
+fn failing_to_create_a_frame_slot_leaves_nothing_alive()
+{
+    use RenderingInterceptor::live_objects;
+    let mut host = MockUnityHost::vulkan();
+    // the fence of the first mirror frame slot, after its semaphores
+    common::vk_stub::fail_call("vkCreateFence", 0);
+    host.load_plugin();
+
+    assert!(host.last_error().is_some());
+    assert_eq!(live_objects::live(), Vec::<(&str, isize)>::new());
+}
+
+#[test]
+fn missing_entry_point_of_the_staging_pool_leaves_nothing_alive()
+{
+    use RenderingInterceptor::live_objects;
+    let mut host = MockUnityHost::vulkan();
+    // only the staging pool of the screenshot sink loads it, after the mirror window is set up
+    common::vk_stub::hide_entry_point("vkGetFenceStatus");
+    host.load_plugin();
+
+    assert!(host.last_error().is_some());
+    assert_eq!(live_objects::live(), Vec::<(&str, isize)>::new());
+}
+
+#[test]
+fn failing_object_creation_at_initialization_leaves_nothing_alive()
+{
+    use RenderingInterceptor::live_objects;
+    for &call in &["vkCreateFence", "vkCreateCommandPool", "vkAllocateCommandBuffers"]
+    {
+        for successes in 0 .. 8
+        {
+            let mut host = MockUnityHost::vulkan();
+            common::vk_stub::fail_call(call, successes);
+            host.load_plugin();
+
+            if host.last_error().is_some()
+            {
+                assert_eq!(live_objects::live(), Vec::<(&str, isize)>::new(), "{} failing after {} calls", call, successes);
+            }
+        }
+    }
+}
+
+#[test]
+fn failing_to_create_the_msaa_resolver_leaves_nothing_alive()
+{
+    use RenderingInterceptor::live_objects;
+    // one fence per resolve slot
+    for successes in 0 .. 2
+    {
+        let mut host = MockUnityHost::vulkan();
+        host.load_plugin();
+        host.set_render_buffer(0x4000);
+        host.set_render_buffer_samples(VK_SAMPLE_COUNT_4_BIT, false);
+        // the resolver is created with the first multisampled capture
+        common::vk_stub::fail_call("vkCreateFence", successes);
+        host.issue_capture_event();
+        assert!(host.last_error().is_some());
+
+        host.fire_device_event(UnityGfxDeviceEventType::Shutdown);
+        assert_eq!(live_objects::live(), Vec::<(&str, isize)>::new(), "vkCreateFence failing after {} calls", successes);
+    }
+}
+
+#[test]
+fn failed_resolve_recording_is_not_submitted()
+{
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    host.set_render_buffer(0x4000);
+    host.set_render_buffer_samples(VK_SAMPLE_COUNT_4_BIT, false);
+    // the resolve is recorded before any output
+    common::vk_stub::fail_call("vkBeginCommandBuffer", 0);
+    common::vk_stub::clear_trace();
+    host.issue_capture_event();
+
+    assert!(host.last_error().map_or(false, |e| e.starts_with("vkBeginCommandBuffer failed")));
+    assert!(!common::vk_stub::trace().iter().any(|l| l.starts_with("vkCmdResolveImage") || l.starts_with("vkQueueSubmit")));
+}
+
+#[test]
+fn failing_to_create_the_present_capture_leaves_nothing_alive()
+{
+    use RenderingInterceptor::live_objects;
+    // one semaphore per present slot
+    for successes in 0 .. 3
+    {
+        let mut host = MockUnityHost::vulkan();
+        host.load_plugin();
+        let swapchain = host.create_swapchain(1280, 720);
+        // the present capture is created with the first presented frame
+        common::vk_stub::fail_call("vkCreateSemaphore", successes);
+        host.present(swapchain, 0);
+        assert!(host.last_error().is_some());
+
+        host.fire_device_event(UnityGfxDeviceEventType::Shutdown);
+        assert_eq!(live_objects::live(), Vec::<(&str, isize)>::new(), "vkCreateSemaphore failing after {} calls", successes);
+        host.destroy_swapchain(swapchain);
+    }
+}
+
+#[test]
+fn device_events_from_the_render_thread_reach_the_plugin()
+{
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    host.clear_calls();
+
+    // Unity loads the plugin on the main thread and sends the other events on the render thread
+    std::thread::scope(|s| s.spawn(||
+    {
+        host.fire_device_event(UnityGfxDeviceEventType::Shutdown);
+        host.fire_device_event(UnityGfxDeviceEventType::Initialize);
+        host.set_render_buffer(0x4000);
+        host.issue_capture_event();
+    }).join().unwrap());
+
+    let calls = host.calls();
+    assert!(calls.contains(&HostCall::GetRenderer));
+    assert!(calls.contains(&HostCall::Instance));
+    assert!(calls.iter().any(|c| match c { HostCall::AccessRenderBufferTexture { render_buffer: 0x4000, .. } => true, _ => false }));
+    assert_eq!(host.last_error(), None);
+}
+
+#[test]
+fn plugin_context_survives_device_resets()
+{
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+
+    std::thread::scope(|s| s.spawn(||
+    {
+        host.fire_device_event(UnityGfxDeviceEventType::BeforeReset);
+        host.fire_device_event(UnityGfxDeviceEventType::AfterReset);
+    }).join().unwrap());
+    host.clear_calls();
+    std::thread::scope(|s| s.spawn(|| host.fire_device_event(UnityGfxDeviceEventType::Initialize)).join().unwrap());
+
+    assert!(host.calls().contains(&HostCall::GetRenderer));
+    assert_eq!(host.last_error(), None);
+}
+
+#[test]
+fn unload_clears_the_plugin_context()
+{
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    let callbacks = host.device_event_callbacks();
+    host.fire_device_event(UnityGfxDeviceEventType::Shutdown);
+    std::thread::scope(|s| s.spawn(|| RenderingInterceptor::UnityPluginUnload()).join().unwrap());
+    host.clear_calls();
+
+    // a callback Unity still had in flight
+    std::thread::scope(|s| s.spawn(|| for cb in &callbacks { cb(UnityGfxDeviceEventType::Initialize); }).join().unwrap());
+
+    assert!(host.calls().is_empty());
+    assert_eq!(host.last_error(), None);
+}
+
+/// Counts the frames it is given
+struct CountingSink(std::sync::Arc<std::sync::atomic::AtomicUsize>);
+impl RenderingInterceptor::sink::FrameSink for CountingSink
+{
+    fn name(&self) -> &str { "counting" }
+    fn process(&mut self, _frame: &UnityVulkanImage) -> RenderingInterceptor::sink::SinkResult
+    {
+        self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+#[test]
+fn before_reset_releases_every_device_object()
+{
+    use RenderingInterceptor::live_objects;
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    assert!(RenderingInterceptor::set_readback_callback(Some(Box::new(|_| ()))));
+    host.set_render_buffer(0x4000);
+    host.issue_capture_event();
+
+    host.fire_device_event(UnityGfxDeviceEventType::BeforeReset);
+    assert_eq!(live_objects::live(), Vec::<(&str, isize)>::new());
+    host.clear_calls();
+    host.issue_capture_event();
+    assert!(host.calls().is_empty());
+
+    host.fire_device_event(UnityGfxDeviceEventType::AfterReset);
+    host.set_render_buffer(0x4000);
+    host.issue_capture_event();
+    assert!(host.calls().iter().any(|c| match c { HostCall::AccessRenderBufferTexture { .. } => true, _ => false }));
+    assert_eq!(host.last_error(), None);
+}
+
+#[test]
+fn device_reset_rebuilds_on_the_new_device_with_the_same_configuration()
+{
+    use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    let delivered = Arc::new(AtomicUsize::new(0));
+    let d = delivered.clone();
+    assert!(RenderingInterceptor::set_readback_callback(Some(Box::new(move |_| { d.fetch_add(1, Ordering::SeqCst); }))));
+    let readback = RenderingInterceptor::readback_sink_id().unwrap();
+    assert!(RenderingInterceptor::set_sink_transform(RenderingInterceptor::WINDOW_SINK_ID, RenderingInterceptor::transform::TRANSFORM_ROTATE_90));
+    let counted = Arc::new(AtomicUsize::new(0));
+    let disabled = Arc::new(AtomicUsize::new(0));
+    let counting = RenderingInterceptor::add_sink(Box::new(CountingSink(counted.clone()))).unwrap();
+    let disabled_sink = RenderingInterceptor::add_sink(Box::new(CountingSink(disabled.clone()))).unwrap();
+    assert!(RenderingInterceptor::set_sink_enabled(disabled_sink, false));
+
+    common::vk_stub::label_handle(0x0d00, "new_queue");
+    host.reset_device(0x0d00);
+    host.set_render_buffer(0x4000);
+    common::vk_stub::clear_trace();
+    for _ in 0 .. 3 { host.issue_capture_event(); }
+
+    let trace = common::vk_stub::trace();
+    assert!(trace.iter().any(|l| l.starts_with("vkQueueSubmit(new_queue")));
+    assert!(!trace.iter().any(|l| l.starts_with("vkQueueSubmit(queue,")));
+    // the window still rotates its frames
+    assert!(trace.iter().any(|l| l.starts_with("vkCmdCopyBufferToImage")));
+    assert_eq!(RenderingInterceptor::readback_sink_id(), Some(readback));
+    assert!(delivered.load(Ordering::SeqCst) > 0);
+    assert_eq!(counted.load(Ordering::SeqCst), 3);
+    assert_eq!(disabled.load(Ordering::SeqCst), 0);
+    assert!(RenderingInterceptor::remove_sink(counting).is_some());
+    assert_eq!(host.last_error(), None);
+}
+
+fn event_data(render_buffer: usize, target: u32) -> RenderingInterceptor::event::CaptureEventData
+{
+    RenderingInterceptor::event::CaptureEventData
+    {
+        render_buffer: render_buffer as _, target,
+        region_x: 0, region_y: 0, region_width: 0, region_height: 0, frame_tag: 0
+    }
+}
+
+#[test]
+fn event_data_brings_its_own_render_buffer()
+{
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    host.clear_calls();
+
+    host.issue_capture_event_and_data(Some(&event_data(0x5000, RenderingInterceptor::event::ALL_SINKS)));
+
+    assert!(host.calls().iter().any(|c| match c { HostCall::AccessRenderBufferTexture { render_buffer: 0x5000, .. } => true, _ => false }));
+    assert_eq!(host.last_error(), None);
+}
+
+#[test]
+fn event_data_feeds_its_target_only()
+{
+    use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    let (a, b) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+    let sink_a = RenderingInterceptor::add_sink(Box::new(CountingSink(a.clone()))).unwrap();
+    RenderingInterceptor::add_sink(Box::new(CountingSink(b.clone()))).unwrap();
+
+    host.issue_capture_event_and_data(Some(&event_data(0x4000, sink_a)));
+    assert_eq!((a.load(Ordering::SeqCst), b.load(Ordering::SeqCst)), (1, 0));
+    host.issue_capture_event_and_data(Some(&event_data(0x4000, RenderingInterceptor::event::ALL_SINKS)));
+    assert_eq!((a.load(Ordering::SeqCst), b.load(Ordering::SeqCst)), (2, 1));
+}
+
+#[test]
+fn event_data_region_and_tag_apply_to_that_event_only()
+{
+    use std::sync::{Arc, Mutex};
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    let delivered = Arc::new(Mutex::new(Vec::new()));
+    let d = delivered.clone();
+    assert!(RenderingInterceptor::set_readback_callback(Some(Box::new(move |f|
+    {
+        d.lock().unwrap().push((f.extent.width, f.extent.height, f.tag));
+    }))));
+    let tagged = RenderingInterceptor::event::CaptureEventData
+    {
+        region_x: 10, region_y: 20, region_width: 100, region_height: 50, frame_tag: 42,
+        .. event_data(0x4000, RenderingInterceptor::event::ALL_SINKS)
+    };
+
+    host.issue_capture_event_and_data(Some(&tagged));
+    host.set_render_buffer(0x4000);
+    host.issue_capture_event();
+    host.issue_capture_event();
+
+    assert_eq!(*delivered.lock().unwrap(), [(100, 50, 42), (RENDER_BUFFER_WIDTH, RENDER_BUFFER_HEIGHT, 0)]);
+    assert_eq!(RenderingInterceptor::settings::current().capture_region, None);
+}
+
+#[test]
+fn unusable_event_data_is_reported()
+{
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+
+    host.issue_capture_event_and_data(None);
+    assert_eq!(host.last_error().as_ref().map(|s| s as &str), Some("invalid capture event data: no data"));
+    host.issue_capture_event_and_data(Some(&event_data(0x4000, 99)));
+    assert_eq!(host.last_error().as_ref().map(|s| s as &str), Some("invalid capture event data: no sink has the target id"));
+    let negative = RenderingInterceptor::event::CaptureEventData { region_x: -1, region_width: 10, region_height: 10, .. event_data(0x4000, 0) };
+    host.issue_capture_event_and_data(Some(&negative));
+    assert_eq!(host.last_error().as_ref().map(|s| s as &str), Some("invalid capture event data: negative offset or empty region"));
+}
+
+#[test]
+fn load_reserves_an_event_id_per_kind()
+{
+    use RenderingInterceptor::event::*;
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+
+    assert!(host.calls().contains(&HostCall::ReserveEventIdRange(EVENT_KIND_COUNT)));
+    let ids: Vec<_> = (0 .. EVENT_KIND_COUNT).map(|k| host.event_id(k)).collect();
+    assert_eq!(ids, [1000, 1001, 1002, 1003, 1004]);
+    assert_eq!(host.event_id(EVENT_KIND_COUNT), -1);
+    assert_eq!(host.event_id(-1), -1);
+}
+
+#[test]
+fn unload_forgets_the_event_ids()
+{
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    host.unload_plugin();
+
+    assert_eq!(host.event_id(RenderingInterceptor::event::EVENT_CAPTURE), -1);
+}
+
+#[test]
+fn screenshot_event_feeds_the_screenshot_sink_only()
+{
+    use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    let counted = Arc::new(AtomicUsize::new(0));
+    RenderingInterceptor::add_sink(Box::new(CountingSink(counted.clone()))).unwrap();
+    host.set_render_buffer(0x4000);
+    host.clear_calls();
+
+    host.issue_plugin_event(host.event_id(RenderingInterceptor::event::EVENT_SCREENSHOT));
+
+    assert!(host.calls().iter().any(|c| match c { HostCall::AccessRenderBufferTexture { render_buffer: 0x4000, .. } => true, _ => false }));
+    assert_eq!(counted.load(Ordering::SeqCst), 0);
+    assert_eq!(host.last_error(), None);
+}
+
+#[test]
+fn screenshot_is_written_without_a_further_frame()
+{
+    use RenderingInterceptor::screenshot::*;
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    host.set_render_buffer(0x4000);
+    let path = std::env::temp_dir().join("rendering_interceptor_single_frame_screenshot.png");
+    let cpath = std::ffi::CString::new(path.to_str().unwrap()).unwrap();
+    assert!(RenderingInterceptor::request_screenshot(cpath.as_ptr()));
+
+    host.issue_plugin_event(host.event_id(RenderingInterceptor::event::EVENT_SCREENSHOT));
+    assert_ne!(RenderingInterceptor::screenshot_status(), SCREENSHOT_STATUS_PENDING);
+
+    while RenderingInterceptor::screenshot_status() == SCREENSHOT_STATUS_WRITING { std::thread::sleep(std::time::Duration::from_millis(1)); }
+    assert_eq!(RenderingInterceptor::screenshot_status(), SCREENSHOT_STATUS_COMPLETED);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn recording_events_turn_the_readback_on_and_off()
+{
+    use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+    use RenderingInterceptor::event::*;
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    let delivered = Arc::new(AtomicUsize::new(0));
+    let d = delivered.clone();
+    assert!(RenderingInterceptor::set_readback_callback(Some(Box::new(move |_| { d.fetch_add(1, Ordering::SeqCst); }))));
+    host.set_render_buffer(0x4000);
+
+    host.issue_plugin_event(host.event_id(EVENT_STOP_RECORDING));
+    for _ in 0 .. 3 { host.issue_capture_event(); }
+    host.issue_plugin_event(host.event_id(EVENT_FLUSH));
+    assert_eq!(delivered.load(Ordering::SeqCst), 0);
+
+    host.issue_plugin_event(host.event_id(EVENT_START_RECORDING));
+    host.issue_capture_event();
+    host.issue_plugin_event(host.event_id(EVENT_FLUSH));
+    assert_eq!(delivered.load(Ordering::SeqCst), 1);
+    assert_eq!(host.last_error(), None);
+}
+
+#[test]
+fn flush_event_delivers_the_frames_in_flight()
+{
+    use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    let delivered = Arc::new(AtomicUsize::new(0));
+    let d = delivered.clone();
+    assert!(RenderingInterceptor::set_readback_callback(Some(Box::new(move |_| { d.fetch_add(1, Ordering::SeqCst); }))));
+    host.set_render_buffer(0x4000);
+    host.issue_capture_event();
+    assert_eq!(delivered.load(Ordering::SeqCst), 0);
+    common::vk_stub::clear_trace();
+
+    host.issue_plugin_event(host.event_id(RenderingInterceptor::event::EVENT_FLUSH));
+
+    assert_eq!(delivered.load(Ordering::SeqCst), 1);
+    assert!(common::vk_stub::trace().iter().any(|l| l.starts_with("vkWaitForFences(")));
+    assert_eq!(host.last_error(), None);
+}
+
+#[test]
+fn readback_delivers_frames_in_order_when_copies_finish_out_of_order()
+{
+    use std::sync::{Arc, Mutex};
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    let delivered = Arc::new(Mutex::new(Vec::new()));
+    let d = delivered.clone();
+    assert!(RenderingInterceptor::set_readback_callback(Some(Box::new(move |f| d.lock().unwrap().push(f.frame_number)))));
+    // the readback is the only output submitting
+    assert!(RenderingInterceptor::set_sink_enabled(RenderingInterceptor::WINDOW_SINK_ID, false));
+    host.set_render_buffer(0x4000);
+
+    // the copy in the first slot is still in flight when the one in the second slot finishes
+    common::vk_stub::hold_next_fence();
+    host.issue_capture_event();
+    host.issue_capture_event();
+    host.issue_capture_event();
+    assert_eq!(*delivered.lock().unwrap(), Vec::<u64>::new());
+
+    host.issue_plugin_event(host.event_id(RenderingInterceptor::event::EVENT_FLUSH));
+    let frames = delivered.lock().unwrap().clone();
+    assert_eq!(frames.len(), 3);
+    assert!(frames.windows(2).all(|w| w[0] < w[1]), "delivered out of order: {:?}", frames);
+}
+
+#[test]
+fn failed_readback_submission_leaves_the_slot_free()
+{
+    use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+    use RenderingInterceptor::event::EVENT_FLUSH;
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    let delivered = Arc::new(AtomicUsize::new(0));
+    let d = delivered.clone();
+    assert!(RenderingInterceptor::set_readback_callback(Some(Box::new(move |_| { d.fetch_add(1, Ordering::SeqCst); }))));
+    // the readback is the only output submitting
+    assert!(RenderingInterceptor::set_sink_enabled(RenderingInterceptor::WINDOW_SINK_ID, false));
+    host.set_render_buffer(0x4000);
+
+    common::vk_stub::fail_call("vkQueueSubmit", 0);
+    host.issue_capture_event();
+    common::vk_stub::clear_trace();
+    host.issue_plugin_event(host.event_id(EVENT_FLUSH));
+    assert_eq!(delivered.load(Ordering::SeqCst), 0);
+    assert!(!common::vk_stub::trace().iter().any(|l| l.starts_with("vkWaitForFences(")));
+
+    host.issue_capture_event();
+    host.issue_plugin_event(host.event_id(EVENT_FLUSH));
+    assert_eq!(delivered.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn failed_staging_buffer_mapping_frees_the_buffer()
+{
+    use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+    use RenderingInterceptor::live_objects;
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    let delivered = Arc::new(AtomicUsize::new(0));
+    let d = delivered.clone();
+    assert!(RenderingInterceptor::set_readback_callback(Some(Box::new(move |_| { d.fetch_add(1, Ordering::SeqCst); }))));
+    host.set_render_buffer(0x4000);
+
+    common::vk_stub::fail_call("vkMapMemory", 0);
+    host.issue_capture_event();
+    host.issue_plugin_event(host.event_id(RenderingInterceptor::event::EVENT_FLUSH));
+    assert_eq!(delivered.load(Ordering::SeqCst), 0);
+    assert!(!live_objects::live().iter().any(|&(kind, _)| kind == "buffer"));
+
+    host.issue_capture_event();
+    host.issue_plugin_event(host.event_id(RenderingInterceptor::event::EVENT_FLUSH));
+    assert_eq!(delivered.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn failed_present_capture_still_signals_the_presentation()
+{
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    // no sink submits between the two halves of the present capture
+    assert!(RenderingInterceptor::set_sink_enabled(RenderingInterceptor::WINDOW_SINK_ID, false));
+    let swapchain = host.create_swapchain(1280, 720);
+    common::vk_stub::clear_trace();
+
+    // the transition back to PRESENT_SRC_KHR, after the one waiting for Unity's semaphore
+    common::vk_stub::fail_call("vkQueueSubmit", 1);
+    host.present(swapchain, 0);
+
+    let trace = common::vk_stub::trace();
+    assert_eq!(trace.iter().filter(|l| l.contains("unity_render_done")).count(), 1);
+    let present = trace.iter().find(|l| l.starts_with("vkQueuePresentKHR(")).unwrap();
+    assert!(!present.contains("wait [unity_render_done]"));
+    // the signaling submission still makes the image presentable
+    let barrier = trace.iter().rev().find(|l| l.contains("TRANSFER_SRC_OPTIMAL -> PRESENT_SRC_KHR")).unwrap();
+    let cbuf = &barrier["vkCmdPipelineBarrier(".len() .. barrier.find(',').unwrap()];
+    let submit = trace.iter().rev().find(|l| l.starts_with("vkQueueSubmit(queue")).unwrap();
+    assert!(submit.contains(&format!("[{}], signal [semaphore", cbuf)), "{}", submit);
+    assert!(host.last_error().is_some());
+    host.destroy_swapchain(swapchain);
+}
+
+#[test]
+fn plugin_window_presents_past_the_hooks()
+{
+    let mut host = MockUnityHost::vulkan();
+    // the commands the plugin loads after its hooks are installed resolve to the hooks
+    host.resolve_intercepted_commands();
+    host.load_plugin();
+    let swapchain = host.create_swapchain(1280, 720);
+    host.set_render_buffer(0x4000);
+    common::vk_stub::clear_trace();
+
+    // the window sink presents while the event holds the interceptor
+    host.issue_capture_event();
+    let presents = |trace: &[String]| trace.iter().filter(|l| l.starts_with("vkQueuePresentKHR(")).count();
+    assert_eq!(presents(&common::vk_stub::trace()), 1);
+    // Unity's own frame still goes through the hook
+    common::vk_stub::clear_trace();
+    host.present(swapchain, 0);
+    assert_eq!(presents(&common::vk_stub::trace()), 1);
+    assert_eq!(host.last_error(), None);
+    host.destroy_swapchain(swapchain);
+}
@@ -0,0 +1,102 @@
+//! Placement of the frame in the mirror window for each scaling mode
+
+use bedrock::vk::*;
+use RenderingInterceptor::scaling::*;
+
+fn extent(width: u32, height: u32) -> VkExtent2D { VkExtent2D { width, height } }
+fn whole(width: u32, height: u32) -> Rect { Rect::new(0, 0, width, height) }
+
+#[test]
+fn stretch_maps_whole_frame_to_whole_window()
+{
+    assert_eq!(place(whole(640, 480), extent(320, 180), SCALING_MODE_STRETCH), Some((whole(640, 480), whole(320, 180))));
+}
+
+#[test]
+fn fit_pillarboxes_frame_narrower_than_window()
+{
+    assert_eq!(place(whole(640, 480), extent(320, 180), SCALING_MODE_FIT), Some((whole(640, 480), Rect::new(40, 0, 240, 180))));
+}
+
+#[test]
+fn fit_letterboxes_frame_wider_than_window()
+{
+    assert_eq!(place(whole(1920, 1080), extent(400, 400), SCALING_MODE_FIT), Some((whole(1920, 1080), Rect::new(0, 87, 400, 225))));
+}
+
+#[test]
+fn fit_with_same_aspect_ratio_covers_window()
+{
+    let (_, dst) = place(whole(1920, 1080), extent(1280, 720), SCALING_MODE_FIT).unwrap();
+    assert!(dst.covers(extent(1280, 720)));
+}
+
+#[test]
+fn fill_crops_overflowing_frame_center()
+{
+    assert_eq!(place(whole(640, 480), extent(320, 180), SCALING_MODE_FILL), Some((Rect::new(0, 60, 640, 360), whole(320, 180))));
+    assert_eq!(place(whole(480, 640), extent(400, 400), SCALING_MODE_FILL), Some((Rect::new(0, 80, 480, 480), whole(400, 400))));
+}
+
+#[test]
+fn integer_uses_largest_whole_multiple()
+{
+    assert_eq!(place(whole(100, 50), extent(320, 180), SCALING_MODE_INTEGER), Some((whole(100, 50), Rect::new(10, 15, 300, 150))));
+}
+
+#[test]
+fn integer_and_center_crop_frame_larger_than_window()
+{
+    let expected = Some((Rect::new(160, 150, 320, 180), whole(320, 180)));
+    assert_eq!(place(whole(640, 480), extent(320, 180), SCALING_MODE_INTEGER), expected);
+    assert_eq!(place(whole(640, 480), extent(320, 180), SCALING_MODE_CENTER), expected);
+}
+
+#[test]
+fn center_keeps_pixel_size()
+{
+    assert_eq!(place(whole(100, 50), extent(320, 180), SCALING_MODE_CENTER), Some((whole(100, 50), Rect::new(110, 65, 100, 50))));
+}
+
+#[test]
+fn source_offset_is_kept()
+{
+    assert_eq!(place(Rect::new(10, 20, 100, 50), extent(320, 180), SCALING_MODE_CENTER),
+        Some((Rect::new(10, 20, 100, 50), Rect::new(110, 65, 100, 50))));
+}
+
+#[test]
+fn nothing_visible_yields_none()
+{
+    assert_eq!(place(whole(640, 480), extent(0, 0), SCALING_MODE_FIT), None);
+    assert_eq!(place(whole(0, 480), extent(320, 180), SCALING_MODE_STRETCH), None);
+    // scaled down to less than a texel high
+    assert_eq!(place(whole(10000, 1), extent(100, 100), SCALING_MODE_FIT), None);
+}
+
+#[test]
+fn rect_converts_to_blit_offsets()
+{
+    let o = Rect::new(40, 10, 240, 160).blit_offsets();
+    assert_eq!((o[0].x, o[0].y, o[0].z, o[1].x, o[1].y, o[1].z), (40, 10, 0, 280, 170, 1));
+}
+
+#[test]
+fn pixel_exact_modes_do_not_filter()
+{
+    assert_eq!(filter(SCALING_MODE_STRETCH), VK_FILTER_LINEAR);
+    assert_eq!(filter(SCALING_MODE_FIT), VK_FILTER_LINEAR);
+    assert_eq!(filter(SCALING_MODE_INTEGER), VK_FILTER_NEAREST);
+    assert_eq!(filter(SCALING_MODE_CENTER), VK_FILTER_NEAREST);
+    assert!(!is_valid_mode(-1) && !is_valid_mode(5));
+}
+
+#[test]
+fn rect_intersection_clips_to_overlap()
+{
+    let frame = whole(640, 480);
+    assert_eq!(Rect::new(600, 400, 100, 100).intersection(&frame), Some(Rect::new(600, 400, 40, 80)));
+    assert_eq!(Rect::new(10, 20, 30, 40).intersection(&frame), Some(Rect::new(10, 20, 30, 40)));
+    assert_eq!(Rect::new(640, 0, 10, 10).intersection(&frame), None);
+    assert_eq!(Rect::new(-10, -10, 20, 20).intersection(&frame), Some(Rect::new(0, 0, 10, 10)));
+}
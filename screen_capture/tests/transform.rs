@@ -0,0 +1,129 @@
+//! Flips and rotations checked against a straightforward reference implementation
+
+use bedrock::vk::*;
+use RenderingInterceptor::scaling::Rect;
+use RenderingInterceptor::transform::*;
+
+/// An image as rows of texel ids
+type Image = Vec<Vec<u32>>;
+
+fn numbered(width: u32, height: u32) -> Image
+{
+    (0 .. height).map(|y| (0 .. width).map(|x| y * width + x).collect()).collect()
+}
+/// Flips first, then rotates clockwise a quarter turn at a time
+fn reference(src: &Image, flags: TransformFlags) -> Image
+{
+    let mut img = src.clone();
+    if flags & TRANSFORM_FLIP_X != 0 { for row in &mut img { row.reverse(); } }
+    if flags & TRANSFORM_FLIP_Y != 0 { img.reverse(); }
+    for _ in 0 .. flags & 3
+    {
+        let (w, h) = (img[0].len(), img.len());
+        img = (0 .. w).map(|r| (0 .. h).map(|c| img[h - 1 - c][r]).collect()).collect();
+    }
+    img
+}
+fn all_flags() -> impl Iterator<Item = TransformFlags> { 0 .. 16 }
+
+#[test]
+fn cpu_transform_matches_reference_for_every_combination()
+{
+    let (width, height) = (5, 3);
+    let src = numbered(width, height);
+    // rows padded like a staging buffer with an aligned pitch
+    let row_pitch = width as usize * 4 + 8;
+    let mut bytes = vec![0xffu8; row_pitch * height as usize];
+    for (y, row) in src.iter().enumerate()
+    {
+        for (x, t) in row.iter().enumerate() { bytes[y * row_pitch + x * 4 ..][.. 4].copy_from_slice(&t.to_le_bytes()); }
+    }
+
+    for flags in all_flags()
+    {
+        let t = Transform::from_flags(flags).unwrap();
+        let mut out = Vec::new();
+        let out_pitch = t.apply(&bytes, width, height, row_pitch, 4, &mut out);
+
+        let expected = reference(&src, flags);
+        let (ow, oh) = t.output_extent(width, height);
+        assert_eq!((ow as usize, oh as usize), (expected[0].len(), expected.len()), "flags {}", flags);
+        assert_eq!(out_pitch, ow as usize * 4);
+        let actual: Image = out.chunks(out_pitch)
+            .map(|row| row.chunks(4).map(|t| u32::from_le_bytes([t[0], t[1], t[2], t[3]])).collect()).collect();
+        assert_eq!(actual, expected, "flags {}", flags);
+    }
+}
+
+/// Runs the GPU path on the CPU: the transposition through a buffer (if needed), then a nearest 1:1 blit
+fn simulate_gpu(src: &Image, t: Transform, crop: Rect) -> Image
+{
+    let (width, height) = (src[0].len() as u32, src.len() as u32);
+    let image = if t.transpose
+    {
+        // vkCmdCopyImageToBuffer packs the rows tightly
+        let buffer: Vec<u32> = src.iter().flatten().cloned().collect();
+        let mut image = vec![vec![u32::MAX; height as usize]; width as usize];
+        for r in transpose_regions(width, height, 4)
+        {
+            let start = r.bufferOffset as usize / 4;
+            let row_length = r.bufferRowLength.max(r.imageExtent.width) as usize;
+            for y in 0 .. r.imageExtent.height as usize
+            {
+                for x in 0 .. r.imageExtent.width as usize
+                {
+                    image[r.imageOffset.y as usize + y][r.imageOffset.x as usize + x] = buffer[start + y * row_length + x];
+                }
+            }
+        }
+        image
+    }
+    else { src.clone() };
+
+    let (iw, ih) = (image[0].len() as u32, image.len() as u32);
+    let [o0, o1] = t.blit_source_offsets(Rect::new(0, 0, iw, ih), crop);
+    let sample = |a: i32, b: i32, i: u32, n: u32| (a as f64 + (i as f64 + 0.5) * (b - a) as f64 / n as f64).floor() as usize;
+    (0 .. crop.height).map(|y| (0 .. crop.width).map(|x| image[sample(o0.y, o1.y, y, crop.height)][sample(o0.x, o1.x, x, crop.width)]).collect()).collect()
+}
+
+#[test]
+fn gpu_transposition_and_blit_match_reference()
+{
+    let src = numbered(7, 4);
+    for flags in all_flags()
+    {
+        let t = Transform::from_flags(flags).unwrap();
+        let expected = reference(&src, flags);
+        let (ow, oh) = (expected[0].len() as u32, expected.len() as u32);
+
+        assert_eq!(simulate_gpu(&src, t, Rect::new(0, 0, ow, oh)), expected, "flags {}", flags);
+        // crops select the same part of the transformed image whatever the orientation
+        let cropped: Image = expected[1 ..].iter().map(|row| row[1 .. ow as usize - 1].to_vec()).collect();
+        assert_eq!(simulate_gpu(&src, t, Rect::new(1, 1, ow - 2, oh - 1)), cropped, "flags {}", flags);
+    }
+}
+
+#[test]
+fn equivalent_flags_reduce_to_the_same_transform()
+{
+    let t = |flags| Transform::from_flags(flags).unwrap();
+    assert_eq!(t(TRANSFORM_ROTATE_180), t(TRANSFORM_FLIP_X | TRANSFORM_FLIP_Y));
+    assert_eq!(t(TRANSFORM_ROTATE_90 | TRANSFORM_FLIP_X | TRANSFORM_FLIP_Y), t(TRANSFORM_ROTATE_270));
+    assert!(t(0).is_identity());
+    assert_eq!(t(TRANSFORM_ROTATE_90).output_extent(640, 480), (480, 640));
+}
+
+#[test]
+fn unknown_flags_are_rejected()
+{
+    assert_eq!(Transform::from_flags(16), None);
+    assert_eq!(Transform::from_flags(-1), None);
+}
+
+#[test]
+fn transpose_regions_cover_each_source_row_once()
+{
+    let regions: Vec<VkBufferImageCopy> = transpose_regions(3, 2, 4);
+    assert_eq!(regions.len(), 2);
+    assert_eq!((regions[1].bufferOffset, regions[1].imageOffset.x, regions[1].imageExtent.height), (12, 1, 3));
+}
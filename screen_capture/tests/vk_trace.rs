@@ -0,0 +1,644 @@
+//! Golden traces of the Vulkan commands the plugin emits per frame
+
+mod common;
+
+use bedrock::vk::*;
+use common::*;
+use std::sync::{Arc, Mutex};
+
+/// Loads the plugin, binds a render buffer and forgets the setup calls
+fn initialized_host() -> MockUnityHost
+{
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    host.set_render_buffer(0x4000);
+    vk_stub::clear_trace();
+
+    host
+}
+fn capture_frame(host: &MockUnityHost) -> Vec<String>
+{
+    vk_stub::clear_trace();
+    host.issue_capture_event();
+    vk_stub::trace()
+}
+
+#[test]
+fn setup_creates_window_objects()
+{
+    let mut host = MockUnityHost::vulkan();
+    host.load_plugin();
+    let trace = vk_stub::trace();
+
+    let expected = [
+        "vkCreateHeadlessSurfaceEXT() -> surface0",
+        // one slot per frame in flight
+        "vkCreateSemaphore() -> semaphore0",
+        "vkCreateSemaphore() -> semaphore1",
+        "vkCreateFence() -> fence0",
+        "vkCreateCommandPool() -> command_pool0",
+        "vkAllocateCommandBuffers(command_pool0) -> [command_buffer0]",
+        "vkCreateSemaphore() -> semaphore2",
+        "vkCreateSemaphore() -> semaphore3",
+        "vkCreateFence() -> fence1",
+        "vkCreateCommandPool() -> command_pool1",
+        "vkAllocateCommandBuffers(command_pool1) -> [command_buffer1]",
+        "vkCreateSwapchainKHR(surface0, 320x180, min 2 images, old null) -> swapchain0"
+    ];
+    let window_setup: Vec<_> = trace.iter().take(expected.len()).map(|s| s as &str).collect();
+    assert_eq!(window_setup, expected);
+}
+
+#[test]
+fn first_frame_blits_into_acquired_image()
+{
+    let host = initialized_host();
+
+    assert_eq!(capture_frame(&host), [
+        "vkResetCommandPool(command_pool0, 0x1)",
+        "vkAcquireNextImageKHR(swapchain0, semaphore0, null) -> #0",
+        "vkBeginCommandBuffer(command_buffer0)",
+        "vkCmdPipelineBarrier(command_buffer0, BOTTOM_OF_PIPE -> TRANSFER, swapchain_image0: UNDEFINED -> TRANSFER_DST_OPTIMAL, 0 -> TRANSFER_WRITE)",
+        "vkCmdBlitImage(command_buffer0, render_buffer: TRANSFER_SRC_OPTIMAL -> swapchain_image0: TRANSFER_DST_OPTIMAL, [(0,0)-(640,480) -> (0,0)-(320,180)], LINEAR)",
+        "vkCmdPipelineBarrier(command_buffer0, TRANSFER -> TOP_OF_PIPE, swapchain_image0: TRANSFER_DST_OPTIMAL -> PRESENT_SRC_KHR, TRANSFER_WRITE -> MEMORY_READ)",
+        "vkEndCommandBuffer(command_buffer0)",
+        "vkQueueSubmit(queue, wait [semaphore0 @ TRANSFER], [command_buffer0], signal [semaphore1], fence0)",
+        "vkQueuePresentKHR(queue, wait [semaphore1], [swapchain0 #0])"
+    ]);
+}
+
+#[test]
+fn next_frame_uses_next_slot_without_waiting()
+{
+    let host = initialized_host();
+    capture_frame(&host);
+
+    assert_eq!(capture_frame(&host), [
+        "vkResetCommandPool(command_pool1, 0x1)",
+        "vkAcquireNextImageKHR(swapchain0, semaphore2, null) -> #1",
+        "vkBeginCommandBuffer(command_buffer1)",
+        "vkCmdPipelineBarrier(command_buffer1, BOTTOM_OF_PIPE -> TRANSFER, swapchain_image1: UNDEFINED -> TRANSFER_DST_OPTIMAL, 0 -> TRANSFER_WRITE)",
+        "vkCmdBlitImage(command_buffer1, render_buffer: TRANSFER_SRC_OPTIMAL -> swapchain_image1: TRANSFER_DST_OPTIMAL, [(0,0)-(640,480) -> (0,0)-(320,180)], LINEAR)",
+        "vkCmdPipelineBarrier(command_buffer1, TRANSFER -> TOP_OF_PIPE, swapchain_image1: TRANSFER_DST_OPTIMAL -> PRESENT_SRC_KHR, TRANSFER_WRITE -> MEMORY_READ)",
+        "vkEndCommandBuffer(command_buffer1)",
+        "vkQueueSubmit(queue, wait [semaphore2 @ TRANSFER], [command_buffer1], signal [semaphore3], fence1)",
+        "vkQueuePresentKHR(queue, wait [semaphore3], [swapchain0 #1])"
+    ]);
+}
+
+#[test]
+fn slot_waits_its_previous_submission_before_reuse()
+{
+    let host = initialized_host();
+    capture_frame(&host);
+    capture_frame(&host);
+
+    let third = capture_frame(&host);
+    assert_eq!(third[.. 4], [
+        "vkWaitForFences([fence0])",
+        "vkResetFences([fence0])",
+        "vkResetCommandPool(command_pool0, 0x1)",
+        "vkAcquireNextImageKHR(swapchain0, semaphore0, null) -> #0"
+    ]);
+    assert_eq!(third.last().unwrap(), "vkQueuePresentKHR(queue, wait [semaphore1], [swapchain0 #0])");
+}
+
+#[test]
+fn single_frame_in_flight_recreates_slots_and_waits_every_frame()
+{
+    let host = initialized_host();
+    capture_frame(&host);
+    assert!(!RenderingInterceptor::set_frames_in_flight(0));
+    assert!(!RenderingInterceptor::set_frames_in_flight(RenderingInterceptor::settings::MAX_FRAMES_IN_FLIGHT + 1));
+    assert!(RenderingInterceptor::set_frames_in_flight(1));
+
+    let reconfigured = capture_frame(&host);
+    // fence2 and command_pool2 belong to the screenshot sink
+    // the new slots are created before the old ones are released
+    assert_eq!(reconfigured[.. 16], [
+        "vkCreateSemaphore() -> semaphore4",
+        "vkCreateSemaphore() -> semaphore5",
+        "vkCreateFence() -> fence3",
+        "vkCreateCommandPool() -> command_pool3",
+        "vkAllocateCommandBuffers(command_pool3) -> [command_buffer3]",
+        "vkDeviceWaitIdle()",
+        "vkDestroyCommandPool(command_pool0)",
+        "vkDestroyFence(fence0)",
+        "vkDestroySemaphore(semaphore1)",
+        "vkDestroySemaphore(semaphore0)",
+        "vkDestroyCommandPool(command_pool1)",
+        "vkDestroyFence(fence1)",
+        "vkDestroySemaphore(semaphore3)",
+        "vkDestroySemaphore(semaphore2)",
+        "vkResetCommandPool(command_pool3, 0x1)",
+        "vkAcquireNextImageKHR(swapchain0, semaphore4, null) -> #1"
+    ]);
+
+    let next = capture_frame(&host);
+    assert_eq!(next[.. 4], [
+        "vkWaitForFences([fence3])",
+        "vkResetFences([fence3])",
+        "vkResetCommandPool(command_pool3, 0x1)",
+        "vkAcquireNextImageKHR(swapchain0, semaphore4, null) -> #0"
+    ]);
+}
+
+#[test]
+fn failed_slot_recreation_keeps_the_current_slots()
+{
+    let host = initialized_host();
+    capture_frame(&host);
+    assert!(RenderingInterceptor::set_frames_in_flight(3));
+    // the second new slot cannot get its fence
+    vk_stub::fail_call("vkCreateFence", 1);
+
+    let failed = capture_frame(&host);
+    assert_eq!(failed[.. 12], [
+        "vkCreateSemaphore() -> semaphore4",
+        "vkCreateSemaphore() -> semaphore5",
+        "vkCreateFence() -> fence3",
+        "vkCreateCommandPool() -> command_pool3",
+        "vkAllocateCommandBuffers(command_pool3) -> [command_buffer3]",
+        "vkCreateSemaphore() -> semaphore6",
+        "vkCreateSemaphore() -> semaphore7",
+        "vkCreateFence() -> VK_ERROR_OUT_OF_DEVICE_MEMORY",
+        "vkDestroySemaphore(semaphore7)",
+        "vkDestroySemaphore(semaphore6)",
+        "vkDestroyCommandPool(command_pool3)",
+        "vkDestroyFence(fence3)"
+    ]);
+    assert!(!failed.iter().any(|l| l == "vkDeviceWaitIdle()" || l == "vkDestroyCommandPool(command_pool0)"));
+    assert!(host.last_error().is_some());
+
+    // retried with the next frame
+    let next = capture_frame(&host);
+    assert!(next.contains(&"vkDestroyCommandPool(command_pool0)".to_owned()));
+    assert!(next.iter().any(|l| l.starts_with("vkQueuePresentKHR")));
+}
+
+#[test]
+fn presented_image_is_transitioned_from_present_layout()
+{
+    let host = initialized_host();
+    capture_frame(&host);
+    capture_frame(&host);
+
+    let third = capture_frame(&host);
+    assert_eq!(third[3], "vkAcquireNextImageKHR(swapchain0, semaphore0, null) -> #0");
+    assert_eq!(third[5], "vkCmdPipelineBarrier(command_buffer0, BOTTOM_OF_PIPE -> TRANSFER, swapchain_image0: PRESENT_SRC_KHR -> TRANSFER_DST_OPTIMAL, MEMORY_READ -> TRANSFER_WRITE)");
+}
+
+#[test]
+fn fit_mode_clears_border_before_blit()
+{
+    let host = initialized_host();
+    assert!(RenderingInterceptor::set_scaling_mode(RenderingInterceptor::scaling::SCALING_MODE_FIT));
+
+    assert_eq!(capture_frame(&host)[3 .. 7], [
+        "vkCmdPipelineBarrier(command_buffer0, BOTTOM_OF_PIPE -> TRANSFER, swapchain_image0: UNDEFINED -> TRANSFER_DST_OPTIMAL, 0 -> TRANSFER_WRITE)",
+        "vkCmdClearColorImage(command_buffer0, swapchain_image0: TRANSFER_DST_OPTIMAL, (0, 0, 0, 1))",
+        "vkCmdPipelineBarrier(command_buffer0, TRANSFER -> TRANSFER, swapchain_image0: TRANSFER_DST_OPTIMAL -> TRANSFER_DST_OPTIMAL, TRANSFER_WRITE -> TRANSFER_WRITE)",
+        "vkCmdBlitImage(command_buffer0, render_buffer: TRANSFER_SRC_OPTIMAL -> swapchain_image0: TRANSFER_DST_OPTIMAL, [(0,0)-(640,480) -> (40,0)-(280,180)], LINEAR)"
+    ]);
+}
+
+#[test]
+fn covering_modes_blit_cropped_source_without_clear()
+{
+    let host = initialized_host();
+    assert!(RenderingInterceptor::set_scaling_mode(RenderingInterceptor::scaling::SCALING_MODE_FILL));
+    let fill = capture_frame(&host);
+    assert!(!fill.iter().any(|l| l.starts_with("vkCmdClearColorImage")));
+    assert_eq!(fill[4], "vkCmdBlitImage(command_buffer0, render_buffer: TRANSFER_SRC_OPTIMAL -> swapchain_image0: TRANSFER_DST_OPTIMAL, [(0,60)-(640,420) -> (0,0)-(320,180)], LINEAR)");
+
+    assert!(RenderingInterceptor::set_scaling_mode(RenderingInterceptor::scaling::SCALING_MODE_CENTER));
+    let center = capture_frame(&host);
+    assert_eq!(center[4], "vkCmdBlitImage(command_buffer1, render_buffer: TRANSFER_SRC_OPTIMAL -> swapchain_image1: TRANSFER_DST_OPTIMAL, [(160,150)-(480,330) -> (0,0)-(320,180)], NEAREST)");
+
+    assert!(!RenderingInterceptor::set_scaling_mode(5));
+}
+
+#[test]
+fn capture_region_is_clipped_to_frame_for_window_blit()
+{
+    let host = initialized_host();
+    assert!(RenderingInterceptor::set_capture_region(100, 50, 200, 100));
+    assert_eq!(capture_frame(&host)[4], "vkCmdBlitImage(command_buffer0, render_buffer: TRANSFER_SRC_OPTIMAL -> swapchain_image0: TRANSFER_DST_OPTIMAL, [(100,50)-(300,150) -> (0,0)-(320,180)], LINEAR)");
+
+    assert!(RenderingInterceptor::set_capture_region(600, 400, 100, 100));
+    assert_eq!(capture_frame(&host)[4], "vkCmdBlitImage(command_buffer1, render_buffer: TRANSFER_SRC_OPTIMAL -> swapchain_image1: TRANSFER_DST_OPTIMAL, [(600,400)-(640,480) -> (0,0)-(320,180)], LINEAR)");
+
+    assert!(RenderingInterceptor::set_capture_region(0, 0, 0, 0));
+    assert_eq!(capture_frame(&host)[6], "vkCmdBlitImage(command_buffer0, render_buffer: TRANSFER_SRC_OPTIMAL -> swapchain_image0: TRANSFER_DST_OPTIMAL, [(0,0)-(640,480) -> (0,0)-(320,180)], LINEAR)");
+}
+
+#[test]
+fn capture_region_outside_frame_only_clears_window()
+{
+    let host = initialized_host();
+    assert!(RenderingInterceptor::set_capture_region(640, 0, 100, 100));
+
+    let frame = capture_frame(&host);
+    assert_eq!(frame[4], "vkCmdClearColorImage(command_buffer0, swapchain_image0: TRANSFER_DST_OPTIMAL, (0, 0, 0, 1))");
+    assert!(!frame.iter().any(|l| l.starts_with("vkCmdBlitImage")));
+    assert!(frame.last().unwrap().starts_with("vkQueuePresentKHR"));
+}
+
+#[test]
+fn invalid_capture_region_is_rejected()
+{
+    let _host = initialized_host();
+    assert!(!RenderingInterceptor::set_capture_region(-1, 0, 100, 100));
+    assert!(!RenderingInterceptor::set_capture_region(0, 0, 100, 0));
+    assert!(!RenderingInterceptor::set_capture_region(0, 0, -5, 10));
+    assert_eq!(RenderingInterceptor::settings::current().capture_region, None);
+}
+
+#[test]
+fn out_of_date_acquire_rebuilds_swapchain_and_skips_frame()
+{
+    let host = initialized_host();
+    vk_stub::push_acquire_result(VK_ERROR_OUT_OF_DATE_KHR);
+
+    assert_eq!(capture_frame(&host), [
+        "vkResetCommandPool(command_pool0, 0x1)",
+        "vkAcquireNextImageKHR(swapchain0, semaphore0, null) -> ERROR_OUT_OF_DATE_KHR",
+        "vkDeviceWaitIdle()",
+        "vkCreateSwapchainKHR(surface0, 320x180, min 2 images, old swapchain0) -> swapchain1",
+        "vkDestroySwapchainKHR(swapchain0)"
+    ]);
+    // the slot was not consumed
+    let next = capture_frame(&host);
+    assert_eq!(next[1], "vkAcquireNextImageKHR(swapchain1, semaphore0, null) -> #0");
+    assert_eq!(next[3], "vkCmdPipelineBarrier(command_buffer0, BOTTOM_OF_PIPE -> TRANSFER, swapchain_image2: UNDEFINED -> TRANSFER_DST_OPTIMAL, 0 -> TRANSFER_WRITE)");
+    assert_eq!(next.last().unwrap(), "vkQueuePresentKHR(queue, wait [semaphore1], [swapchain1 #0])");
+}
+
+#[test]
+fn suboptimal_acquire_presents_then_rebuilds()
+{
+    let host = initialized_host();
+    vk_stub::push_acquire_result(VK_SUBOPTIMAL_KHR);
+
+    let first = capture_frame(&host);
+    assert_eq!(first[1], "vkAcquireNextImageKHR(swapchain0, semaphore0, null) -> #0 SUBOPTIMAL_KHR");
+    assert_eq!(first.last().unwrap(), "vkQueuePresentKHR(queue, wait [semaphore1], [swapchain0 #0])");
+
+    let next = capture_frame(&host);
+    assert_eq!(next[.. 5], [
+        "vkDeviceWaitIdle()",
+        "vkCreateSwapchainKHR(surface0, 320x180, min 2 images, old swapchain0) -> swapchain1",
+        "vkDestroySwapchainKHR(swapchain0)",
+        "vkResetCommandPool(command_pool1, 0x1)",
+        "vkAcquireNextImageKHR(swapchain1, semaphore2, null) -> #1"
+    ]);
+}
+
+#[test]
+fn out_of_date_present_rebuilds_before_next_acquire()
+{
+    let host = initialized_host();
+    vk_stub::push_present_result(VK_ERROR_OUT_OF_DATE_KHR);
+
+    let first = capture_frame(&host);
+    assert_eq!(first.last().unwrap(), "vkQueuePresentKHR(queue, wait [semaphore1], [swapchain0 #0]) -> ERROR_OUT_OF_DATE_KHR");
+
+    let next = capture_frame(&host);
+    assert_eq!(next[.. 3], [
+        "vkDeviceWaitIdle()",
+        "vkCreateSwapchainKHR(surface0, 320x180, min 2 images, old swapchain0) -> swapchain1",
+        "vkDestroySwapchainKHR(swapchain0)"
+    ]);
+}
+
+#[test]
+fn resized_surface_rebuilds_swapchain_with_new_extent()
+{
+    let host = initialized_host();
+    capture_frame(&host);
+    vk_stub::set_surface_extent(Some((800, 600)));
+
+    let resized = capture_frame(&host);
+    assert_eq!(resized[.. 5], [
+        "vkDeviceWaitIdle()",
+        "vkCreateSwapchainKHR(surface0, 800x600, min 2 images, old swapchain0) -> swapchain1",
+        "vkDestroySwapchainKHR(swapchain0)",
+        "vkResetCommandPool(command_pool1, 0x1)",
+        "vkAcquireNextImageKHR(swapchain1, semaphore2, null) -> #1"
+    ]);
+    assert!(resized.contains(&"vkCmdBlitImage(command_buffer1, render_buffer: TRANSFER_SRC_OPTIMAL -> swapchain_image3: TRANSFER_DST_OPTIMAL, [(0,0)-(640,480) -> (0,0)-(800,600)], LINEAR)".to_owned()));
+
+    // same size again: no further rebuild
+    assert!(!capture_frame(&host).iter().any(|l| l.starts_with("vkCreateSwapchainKHR")));
+}
+
+#[test]
+fn zero_sized_surface_skips_frames_until_restored()
+{
+    let host = initialized_host();
+    vk_stub::set_surface_extent(Some((0, 0)));
+
+    assert!(capture_frame(&host).is_empty());
+    assert!(capture_frame(&host).is_empty());
+
+    vk_stub::set_surface_extent(Some((320, 180)));
+    let restored = capture_frame(&host);
+    assert_eq!(restored[.. 3], [
+        "vkDeviceWaitIdle()",
+        "vkCreateSwapchainKHR(surface0, 320x180, min 2 images, old swapchain0) -> swapchain1",
+        "vkDestroySwapchainKHR(swapchain0)"
+    ]);
+}
+
+#[test]
+fn disabled_window_emits_nothing()
+{
+    let host = initialized_host();
+    assert!(RenderingInterceptor::set_sink_enabled(RenderingInterceptor::WINDOW_SINK_ID, false));
+
+    assert!(capture_frame(&host).is_empty());
+}
+
+#[test]
+fn readback_copies_after_window_and_delivers_next_frame()
+{
+    let host = initialized_host();
+    let delivered = Arc::new(Mutex::new(Vec::new()));
+    let d = delivered.clone();
+    assert!(RenderingInterceptor::set_readback_callback(Some(Box::new(move |f|
+    {
+        d.lock().unwrap().push((f.frame_number, f.extent.width, f.extent.height, f.data.len()));
+    }))));
+    // readback staging slots are created with the sink: command_pool3 .. 5 (0 and 1 are the window's, 2 the screenshot's)
+    let slot = |l: &&String| l.contains("command_buffer3") || l.contains("fence3");
+
+    let first = capture_frame(&host);
+    let present = first.iter().position(|l| l.starts_with("vkQueuePresentKHR")).unwrap();
+    assert!(first[.. present].iter().all(|l| !slot(&l)), "readback must run after the window sink");
+    let first_slot: Vec<_> = first.iter().filter(slot).map(|s| s as &str).collect();
+    assert_eq!(first_slot, [
+        "vkBeginCommandBuffer(command_buffer3)",
+        "vkCmdCopyImageToBuffer(command_buffer3, render_buffer: TRANSFER_SRC_OPTIMAL -> buffer0, [(0,0) 640x480 @0])",
+        "vkCmdPipelineBarrier(command_buffer3, TRANSFER -> HOST, buffer0: TRANSFER_WRITE -> HOST_READ)",
+        "vkEndCommandBuffer(command_buffer3)",
+        "vkQueueSubmit(queue, wait [], [command_buffer3], signal [], fence3)"
+    ]);
+    assert!(delivered.lock().unwrap().is_empty());
+
+    let second = capture_frame(&host);
+    let second_slot: Vec<_> = second.iter().filter(slot).take(2).map(|s| s as &str).collect();
+    assert_eq!(second_slot, ["vkGetFenceStatus(fence3)", "vkResetFences([fence3])"]);
+    assert_eq!(*delivered.lock().unwrap(), [(0, 640, 480, 640 * 480 * 4)]);
+}
+
+#[test]
+fn readback_copies_capture_region_only()
+{
+    let host = initialized_host();
+    let delivered = Arc::new(Mutex::new(Vec::new()));
+    let d = delivered.clone();
+    assert!(RenderingInterceptor::set_readback_callback(Some(Box::new(move |f|
+    {
+        d.lock().unwrap().push((f.extent.width, f.extent.height, f.row_pitch, f.data.len()));
+    }))));
+    assert!(RenderingInterceptor::set_capture_region(100, 50, 200, 100));
+
+    let first = capture_frame(&host);
+    assert!(first.contains(&"vkCmdCopyImageToBuffer(command_buffer3, render_buffer: TRANSFER_SRC_OPTIMAL -> buffer0, [(100,50) 200x100 @0])".to_owned()));
+    capture_frame(&host);
+    assert_eq!(*delivered.lock().unwrap(), [(200, 100, 200 * 4, 200 * 100 * 4)]);
+
+    // nothing to copy while the region is outside the frame
+    assert!(RenderingInterceptor::set_capture_region(0, 480, 10, 10));
+    assert!(!capture_frame(&host).iter().any(|l| l.starts_with("vkCmdCopyImageToBuffer")));
+}
+
+#[test]
+fn flipped_window_blit_reverses_source_offsets()
+{
+    use RenderingInterceptor::transform::*;
+    let host = initialized_host();
+    assert!(RenderingInterceptor::set_sink_transform(RenderingInterceptor::WINDOW_SINK_ID, TRANSFORM_FLIP_Y));
+    assert_eq!(capture_frame(&host)[4], "vkCmdBlitImage(command_buffer0, render_buffer: TRANSFER_SRC_OPTIMAL -> swapchain_image0: TRANSFER_DST_OPTIMAL, [(0,480)-(640,0) -> (0,0)-(320,180)], LINEAR)");
+
+    assert!(RenderingInterceptor::set_sink_transform(RenderingInterceptor::WINDOW_SINK_ID, TRANSFORM_ROTATE_180));
+    assert_eq!(capture_frame(&host)[4], "vkCmdBlitImage(command_buffer1, render_buffer: TRANSFER_SRC_OPTIMAL -> swapchain_image1: TRANSFER_DST_OPTIMAL, [(640,480)-(0,0) -> (0,0)-(320,180)], LINEAR)");
+}
+
+#[test]
+fn rotated_window_transposes_through_buffer_before_blit()
+{
+    use RenderingInterceptor::transform::*;
+    let host = initialized_host();
+    assert!(RenderingInterceptor::set_sink_transform(RenderingInterceptor::WINDOW_SINK_ID, TRANSFORM_ROTATE_90));
+
+    assert_eq!(capture_frame(&host), [
+        // allocated before the frame is recorded, and kept while the frame size stays the same
+        "vkCreateBuffer(1228800 bytes) -> buffer0",
+        "vkAllocateMemory(1228800 bytes) -> memory0",
+        "vkCreateImage(480x640) -> image0",
+        "vkAllocateMemory(1228800 bytes) -> memory1",
+        "vkResetCommandPool(command_pool0, 0x1)",
+        "vkAcquireNextImageKHR(swapchain0, semaphore0, null) -> #0",
+        "vkBeginCommandBuffer(command_buffer0)",
+        "vkCmdPipelineBarrier(command_buffer0, BOTTOM_OF_PIPE -> TRANSFER, swapchain_image0: UNDEFINED -> TRANSFER_DST_OPTIMAL, 0 -> TRANSFER_WRITE)",
+        "vkCmdPipelineBarrier(command_buffer0, TRANSFER -> TRANSFER, buffer0: TRANSFER_READ -> TRANSFER_WRITE)",
+        "vkCmdCopyImageToBuffer(command_buffer0, render_buffer: TRANSFER_SRC_OPTIMAL -> buffer0, [(0,0) 640x480 @0])",
+        "vkCmdPipelineBarrier(command_buffer0, TRANSFER -> TRANSFER, buffer0: TRANSFER_WRITE -> TRANSFER_READ; image0: UNDEFINED -> TRANSFER_DST_OPTIMAL, 0 -> TRANSFER_WRITE)",
+        "vkCmdCopyBufferToImage(command_buffer0, buffer0 -> image0: TRANSFER_DST_OPTIMAL, 480 regions [@0 row 1 -> (0,0) 1x640 .. @1226240 row 1 -> (479,0) 1x640])",
+        "vkCmdPipelineBarrier(command_buffer0, TRANSFER -> TRANSFER, image0: TRANSFER_DST_OPTIMAL -> TRANSFER_SRC_OPTIMAL, TRANSFER_WRITE -> TRANSFER_READ)",
+        // the rotation is the transposition mirrored left and right
+        "vkCmdBlitImage(command_buffer0, image0: TRANSFER_SRC_OPTIMAL -> swapchain_image0: TRANSFER_DST_OPTIMAL, [(480,0)-(0,640) -> (0,0)-(320,180)], LINEAR)",
+        "vkCmdPipelineBarrier(command_buffer0, TRANSFER -> TOP_OF_PIPE, swapchain_image0: TRANSFER_DST_OPTIMAL -> PRESENT_SRC_KHR, TRANSFER_WRITE -> MEMORY_READ)",
+        "vkEndCommandBuffer(command_buffer0)",
+        "vkQueueSubmit(queue, wait [semaphore0 @ TRANSFER], [command_buffer0], signal [semaphore1], fence0)",
+        "vkQueuePresentKHR(queue, wait [semaphore1], [swapchain0 #0])"
+    ]);
+    let second = capture_frame(&host);
+    assert!(!second.iter().any(|l| l.starts_with("vkCreateImage") || l.starts_with("vkCreateBuffer")));
+    assert!(second.iter().any(|l| l.starts_with("vkCmdBlitImage(command_buffer1, image0: ")));
+}
+
+#[test]
+fn invalid_transform_is_rejected()
+{
+    let _host = initialized_host();
+    assert!(!RenderingInterceptor::set_sink_transform(RenderingInterceptor::WINDOW_SINK_ID, 16));
+    assert!(!RenderingInterceptor::set_sink_transform(42, RenderingInterceptor::transform::TRANSFORM_FLIP_X));
+}
+
+#[test]
+fn readback_delivers_rotated_frame()
+{
+    let host = initialized_host();
+    let delivered = Arc::new(Mutex::new(Vec::new()));
+    let d = delivered.clone();
+    assert!(RenderingInterceptor::set_readback_callback(Some(Box::new(move |f|
+    {
+        d.lock().unwrap().push((f.extent.width, f.extent.height, f.row_pitch, f.data.len()));
+    }))));
+    let id = RenderingInterceptor::readback_sink_id().unwrap();
+    assert!(RenderingInterceptor::set_sink_transform(id, RenderingInterceptor::transform::TRANSFORM_ROTATE_270));
+
+    // the copy itself is untransformed; the texels are moved on the host
+    assert!(capture_frame(&host).contains(&"vkCmdCopyImageToBuffer(command_buffer3, render_buffer: TRANSFER_SRC_OPTIMAL -> buffer0, [(0,0) 640x480 @0])".to_owned()));
+    capture_frame(&host);
+    assert_eq!(*delivered.lock().unwrap(), [(480, 640, 480 * 4, 480 * 640 * 4)]);
+}
+
+#[test]
+fn multisampled_render_buffer_is_read_from_unity_resolve_texture()
+{
+    let host = initialized_host();
+    host.set_render_buffer_samples(VK_SAMPLE_COUNT_4_BIT, true);
+
+    let frame = capture_frame(&host);
+    assert_eq!(frame[4], "vkCmdBlitImage(command_buffer0, resolve_texture: TRANSFER_SRC_OPTIMAL -> swapchain_image0: TRANSFER_DST_OPTIMAL, [(0,0)-(640,480) -> (0,0)-(320,180)], LINEAR)");
+    assert!(!frame.iter().any(|l| l.starts_with("vkCmdResolveImage")));
+}
+
+#[test]
+fn multisampled_render_buffer_without_resolve_texture_is_resolved_before_outputs()
+{
+    let host = initialized_host();
+    host.set_render_buffer_samples(VK_SAMPLE_COUNT_4_BIT, false);
+
+    let first = capture_frame(&host);
+    let resolve_start = first.iter().position(|l| l == "vkResetCommandPool(command_pool3, 0x0)").unwrap();
+    assert_eq!(first[resolve_start ..= resolve_start + 6], [
+        "vkResetCommandPool(command_pool3, 0x0)",
+        "vkBeginCommandBuffer(command_buffer3)",
+        "vkCmdPipelineBarrier(command_buffer3, TRANSFER -> TRANSFER, image0: UNDEFINED -> TRANSFER_DST_OPTIMAL, 0 -> TRANSFER_WRITE)",
+        "vkCmdResolveImage(command_buffer3, render_buffer: TRANSFER_SRC_OPTIMAL -> image0: TRANSFER_DST_OPTIMAL, [(0,0) 640x480])",
+        "vkCmdPipelineBarrier(command_buffer3, TRANSFER -> TRANSFER, image0: TRANSFER_DST_OPTIMAL -> TRANSFER_SRC_OPTIMAL, TRANSFER_WRITE -> TRANSFER_READ)",
+        "vkEndCommandBuffer(command_buffer3)",
+        "vkQueueSubmit(queue, wait [], [command_buffer3], signal [], fence3)"
+    ]);
+    // the resolve target is created once, before anything is resolved into it
+    assert!(first[.. resolve_start].contains(&"vkCreateImage(640x480) -> image0".to_owned()));
+    let blit = first.iter().position(|l| l.starts_with("vkCmdBlitImage")).unwrap();
+    assert!(blit > resolve_start);
+    assert_eq!(first[blit], "vkCmdBlitImage(command_buffer0, image0: TRANSFER_SRC_OPTIMAL -> swapchain_image0: TRANSFER_DST_OPTIMAL, [(0,0)-(640,480) -> (0,0)-(320,180)], LINEAR)");
+
+    // the second resolve slot is used without waiting; the third frame waits for the first resolve
+    let second = capture_frame(&host);
+    assert!(!second.iter().any(|l| l.starts_with("vkCreateImage")));
+    assert!(second.contains(&"vkCmdResolveImage(command_buffer4, render_buffer: TRANSFER_SRC_OPTIMAL -> image0: TRANSFER_DST_OPTIMAL, [(0,0) 640x480])".to_owned()));
+    let third = capture_frame(&host);
+    assert_eq!(third[.. 2], ["vkWaitForFences([fence3])", "vkResetFences([fence3])"]);
+}
+
+#[test]
+fn captured_texture_replaces_render_buffer_for_one_sink_and_one_event()
+{
+    let host = initialized_host();
+    assert!(RenderingInterceptor::capture_texture(0x5000 as _, RenderingInterceptor::WINDOW_SINK_ID));
+
+    assert_eq!(capture_frame(&host)[4], "vkCmdBlitImage(command_buffer0, texture: TRANSFER_SRC_OPTIMAL -> swapchain_image0: TRANSFER_DST_OPTIMAL, [(0,0)-(256,128) -> (0,0)-(320,180)], LINEAR)");
+    assert_eq!(capture_frame(&host)[4], "vkCmdBlitImage(command_buffer1, render_buffer: TRANSFER_SRC_OPTIMAL -> swapchain_image1: TRANSFER_DST_OPTIMAL, [(0,0)-(640,480) -> (0,0)-(320,180)], LINEAR)");
+}
+
+#[test]
+fn captured_texture_goes_to_its_sink_while_others_get_render_buffer()
+{
+    let host = initialized_host();
+    let delivered = Arc::new(Mutex::new(Vec::new()));
+    let d = delivered.clone();
+    assert!(RenderingInterceptor::set_readback_callback(Some(Box::new(move |f|
+    {
+        d.lock().unwrap().push((f.extent.width, f.extent.height));
+    }))));
+    let id = RenderingInterceptor::readback_sink_id().unwrap();
+    assert!(RenderingInterceptor::capture_texture(0x5000 as _, id));
+
+    let frame = capture_frame(&host);
+    assert!(frame.contains(&"vkCmdCopyImageToBuffer(command_buffer3, texture: TRANSFER_SRC_OPTIMAL -> buffer0, [(0,0) 256x128 @0])".to_owned()));
+    assert!(frame.iter().any(|l| l.starts_with("vkCmdBlitImage(command_buffer0, render_buffer: ")));
+    // each sink gets one frame per event
+    assert_eq!(frame.iter().filter(|l| l.starts_with("vkCmdCopyImageToBuffer")).count(), 1);
+    capture_frame(&host);
+    assert_eq!(delivered.lock().unwrap()[0], (256, 128));
+}
+
+#[test]
+fn depth_texture_is_rejected_and_sink_falls_back_to_render_buffer()
+{
+    let host = initialized_host();
+    assert!(RenderingInterceptor::capture_texture(DEPTH_TEXTURE as _, RenderingInterceptor::WINDOW_SINK_ID));
+
+    assert_eq!(capture_frame(&host)[4], "vkCmdBlitImage(command_buffer0, render_buffer: TRANSFER_SRC_OPTIMAL -> swapchain_image0: TRANSFER_DST_OPTIMAL, [(0,0)-(640,480) -> (0,0)-(320,180)], LINEAR)");
+    assert!(host.last_error().unwrap().contains("unsupported image format"));
+}
+
+#[test]
+fn invalid_texture_capture_is_rejected()
+{
+    let _host = initialized_host();
+    assert!(!RenderingInterceptor::capture_texture(std::ptr::null_mut(), RenderingInterceptor::WINDOW_SINK_ID));
+    assert!(!RenderingInterceptor::capture_texture(0x5000 as _, 42));
+}
+
+#[test]
+fn presented_frame_is_captured_without_capture_events()
+{
+    let host = initialized_host();
+    let swapchain = host.create_swapchain(1280, 720);
+    host.present(swapchain, 0);
+
+    vk_stub::clear_trace();
+    host.present(swapchain, 1);
+    assert_eq!(vk_stub::trace(), [
+        // readable after Unity's rendering
+        "vkResetCommandPool(command_pool4, 0x0)",
+        "vkBeginCommandBuffer(command_buffer6)",
+        "vkCmdPipelineBarrier(command_buffer6, TRANSFER -> TRANSFER, swapchain_image3: PRESENT_SRC_KHR -> TRANSFER_SRC_OPTIMAL, 0 -> TRANSFER_READ)",
+        "vkEndCommandBuffer(command_buffer6)",
+        "vkQueueSubmit(queue, wait [unity_render_done @ TRANSFER], [command_buffer6], signal [], null)",
+        "vkResetCommandPool(command_pool1, 0x1)",
+        "vkAcquireNextImageKHR(swapchain0, semaphore2, null) -> #1",
+        "vkBeginCommandBuffer(command_buffer1)",
+        "vkCmdPipelineBarrier(command_buffer1, BOTTOM_OF_PIPE -> TRANSFER, swapchain_image1: UNDEFINED -> TRANSFER_DST_OPTIMAL, 0 -> TRANSFER_WRITE)",
+        "vkCmdBlitImage(command_buffer1, swapchain_image3: TRANSFER_SRC_OPTIMAL -> swapchain_image1: TRANSFER_DST_OPTIMAL, [(0,0)-(1280,720) -> (0,0)-(320,180)], LINEAR)",
+        "vkCmdPipelineBarrier(command_buffer1, TRANSFER -> TOP_OF_PIPE, swapchain_image1: TRANSFER_DST_OPTIMAL -> PRESENT_SRC_KHR, TRANSFER_WRITE -> MEMORY_READ)",
+        "vkEndCommandBuffer(command_buffer1)",
+        "vkQueueSubmit(queue, wait [semaphore2 @ TRANSFER], [command_buffer1], signal [semaphore3], fence1)",
+        "vkQueuePresentKHR(queue, wait [semaphore3], [swapchain0 #1])",
+        // presentable again, and presented after the outputs
+        "vkBeginCommandBuffer(command_buffer7)",
+        "vkCmdPipelineBarrier(command_buffer7, TRANSFER -> BOTTOM_OF_PIPE, swapchain_image3: TRANSFER_SRC_OPTIMAL -> PRESENT_SRC_KHR, 0 -> 0)",
+        "vkEndCommandBuffer(command_buffer7)",
+        "vkQueueSubmit(queue, wait [], [command_buffer7], signal [semaphore5], fence4)",
+        "vkQueuePresentKHR(queue, wait [semaphore5], [swapchain1 #1])"
+    ]);
+}
+
+#[test]
+fn capture_event_takes_precedence_over_presented_frame()
+{
+    let host = initialized_host();
+    let swapchain = host.create_swapchain(1280, 720);
+
+    capture_frame(&host);
+    vk_stub::clear_trace();
+    host.present(swapchain, 0);
+    assert_eq!(vk_stub::trace(), ["vkQueuePresentKHR(queue, wait [unity_render_done], [swapchain1 #0])"]);
+
+    // frames without an event are captured again
+    vk_stub::clear_trace();
+    host.present(swapchain, 1);
+    assert_eq!(vk_stub::trace().last().unwrap(), "vkQueuePresentKHR(queue, wait [semaphore4], [swapchain1 #1])");
+}
+
+#[test]
+fn unknown_or_destroyed_swapchain_is_presented_unchanged()
+{
+    let host = initialized_host();
+    host.present(0xdead0 as _, 0);
+    assert_eq!(vk_stub::trace(), ["vkQueuePresentKHR(queue, wait [unity_render_done], [0xdead0 #0])"]);
+
+    let swapchain = host.create_swapchain(1280, 720);
+    host.destroy_swapchain(swapchain);
+    vk_stub::clear_trace();
+    host.present(swapchain, 0);
+    assert_eq!(vk_stub::trace(), ["vkQueuePresentKHR(queue, wait [unity_render_done], [swapchain1 #0])"]);
+}
@@ -0,0 +1,33 @@
+//! Window options given through the environment
+
+use bedrock::vk::*;
+use RenderingInterceptor::window::*;
+
+#[test]
+fn window_geometry_has_size_and_optional_position()
+{
+    assert_eq!(parse_window_geometry("800x600"), Some(WindowGeometry { width: 800, height: 600, position: None }));
+    assert_eq!(parse_window_geometry("800x600+100+-20"), Some(WindowGeometry { width: 800, height: 600, position: Some((100, -20)) }));
+    assert_eq!(parse_window_geometry("0x600"), None);
+    assert_eq!(parse_window_geometry("800x600+100"), None);
+    assert_eq!(parse_window_geometry("large"), None);
+}
+
+#[test]
+fn present_modes_are_named_like_the_vulkan_enum()
+{
+    assert_eq!(parse_present_mode("mailbox"), Some(VK_PRESENT_MODE_MAILBOX_KHR));
+    assert_eq!(parse_present_mode(" Immediate "), Some(VK_PRESENT_MODE_IMMEDIATE_KHR));
+    assert_eq!(parse_present_mode("fifo_relaxed"), Some(VK_PRESENT_MODE_FIFO_RELAXED_KHR));
+    assert_eq!(parse_present_mode("vsync"), None);
+}
+
+#[test]
+fn requested_present_mode_is_used_only_if_supported()
+{
+    let available = [VK_PRESENT_MODE_IMMEDIATE_KHR, VK_PRESENT_MODE_MAILBOX_KHR, VK_PRESENT_MODE_FIFO_KHR];
+    assert_eq!(choose_present_mode(&available, Some(VK_PRESENT_MODE_IMMEDIATE_KHR)), VK_PRESENT_MODE_IMMEDIATE_KHR);
+    assert_eq!(choose_present_mode(&available, Some(VK_PRESENT_MODE_FIFO_RELAXED_KHR)), VK_PRESENT_MODE_MAILBOX_KHR);
+    assert_eq!(choose_present_mode(&available, None), VK_PRESENT_MODE_MAILBOX_KHR);
+    assert_eq!(choose_present_mode(&[VK_PRESENT_MODE_IMMEDIATE_KHR], None), VK_PRESENT_MODE_IMMEDIATE_KHR);
+}
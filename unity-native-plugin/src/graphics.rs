@@ -0,0 +1,40 @@
+//! IUnityGraphics and the rendering event callbacks
+
+use libc::*;
+use crate::interfaces::UnityInterfaceGUID;
+
+c_enum!{
+    /// The graphics API Unity renders with
+    UnityGfxRenderer: c_int
+    {
+        D3D11 = 2, Null = 4, OpenGLES20 = 8, OpenGLES30 = 11, PS4 = 13, XboxOne = 14,
+        Metal = 16, OpenGLCore = 17, D3D12 = 18, Vulkan = 21, Nvn = 22, XboxOneD3D12 = 23
+    }
+}
+c_enum!{
+    UnityGfxDeviceEventType: c_int
+    {
+        Initialize = 0, Shutdown = 1, BeforeReset = 2, AfterReset = 3
+    }
+}
+
+pub type IUnityGraphicsDeviceEventCallback = extern "system" fn(eventType: UnityGfxDeviceEventType);
+
+#[repr(C)]
+pub struct IUnityGraphics
+{
+    pub get_renderer: extern "system" fn() -> UnityGfxRenderer,
+    pub register_device_event_callback: extern "system" fn(callback: IUnityGraphicsDeviceEventCallback),
+    pub unregister_device_event_callback: extern "system" fn(callback: IUnityGraphicsDeviceEventCallback),
+    pub reserve_event_id_range: extern "system" fn(count: c_int) -> c_int
+}
+impl IUnityGraphics
+{
+    pub const GUID: UnityInterfaceGUID = UnityInterfaceGUID
+    {
+        guid_high: 0x7CBA0A9CA4DDB544u64, guid_low: 0x8C5AD4926EB17B11u64
+    };
+}
+
+pub type UnityRenderingEvent = extern "system" fn(eventId: c_int);
+pub type UnityRenderingEventAndData = extern "system" fn(eventId: c_int, data: *mut c_void);
@@ -0,0 +1,24 @@
+//! IUnityInterfaces: the registry the other interfaces are obtained from
+
+use libc::*;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UnityInterfaceGUID
+{
+    pub guid_high: c_ulonglong,
+    pub guid_low: c_ulonglong
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IUnityInterface {}
+
+#[repr(C)]
+pub struct IUnityInterfaces
+{
+    pub get_interface: extern "system" fn(guid: UnityInterfaceGUID) -> *mut IUnityInterface,
+    pub register_interface: extern "system" fn(guid: UnityInterfaceGUID, ptr: *mut IUnityInterface),
+    pub get_interface_split: extern "system" fn(guid_high: c_longlong, guid_low: c_longlong) -> *mut IUnityInterface,
+    pub register_interface_split: extern "system" fn(guid_high: c_longlong, guid_low: c_longlong, ptr: *mut IUnityInterface)
+}
@@ -0,0 +1,67 @@
+//! Bindings to the Unity native plugin interfaces (IUnityInterfaces, IUnityGraphics and IUnityGraphicsVulkan)
+
+/// Defines a C enum as a transparent newtype with its values as associated constants,
+/// so that values this crate does not know about still pass through the FFI boundary
+macro_rules! c_enum
+{
+    ($(#[$meta: meta])* $name: ident: $repr: ty { $($(#[$vmeta: meta])* $variant: ident = $value: expr),* $(,)? }) =>
+    {
+        $(#[$meta])*
+        #[repr(transparent)]
+        #[derive(Clone, Copy, PartialEq, Eq, Hash)]
+        pub struct $name(pub $repr);
+        #[allow(non_upper_case_globals)]
+        impl $name
+        {
+            $($(#[$vmeta])* pub const $variant: Self = $name($value);)*
+        }
+        impl std::fmt::Debug for $name
+        {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+            {
+                $(if *self == Self::$variant { return f.write_str(stringify!($variant)); })*
+                write!(f, "{}({})", stringify!($name), self.0)
+            }
+        }
+    }
+}
+
+mod interfaces;
+pub use self::interfaces::*;
+mod graphics;
+pub use self::graphics::*;
+mod vulkan;
+pub use self::vulkan::*;
+
+/// Exports `UnityPluginLoad` and `UnityPluginUnload`, forwarding to the given functions.
+///
+/// `load` receives the interface registry Unity passed in (never null); `unload` takes no arguments.
+/// A panic in either is caught at the boundary, since unwinding into Unity is undefined.
+///
+/// ```ignore
+/// fn load(interfaces: &IUnityInterfaces) { /* ... */ }
+/// fn unload() { /* ... */ }
+/// unity_native_plugin::unity_plugin!{ load: load, unload: unload }
+/// ```
+#[macro_export]
+macro_rules! unity_plugin
+{
+    { load: $load: path, unload: $unload: path $(,)? } =>
+    {
+        #[no_mangle]
+        #[allow(non_snake_case, clippy::not_unsafe_ptr_arg_deref)]
+        pub extern "system" fn UnityPluginLoad(interfaces: *mut $crate::IUnityInterfaces)
+        {
+            if let Some(interfaces) = unsafe { interfaces.as_ref() }
+            {
+                let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| $load(interfaces)));
+            }
+        }
+        #[no_mangle]
+        #[allow(non_snake_case)]
+        pub extern "system" fn UnityPluginUnload()
+        {
+            let _ = std::panic::catch_unwind(|| $unload());
+        }
+    }
+}
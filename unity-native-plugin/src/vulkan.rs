@@ -0,0 +1,334 @@
+//! IUnityGraphicsVulkan and a safe wrapper over it
+
+use libc::*;
+use crate::interfaces::*;
+use crate::graphics::UnityRenderingEventAndData;
+use bedrock::vk::*;
+
+#[repr(C)]
+pub struct UnityVulkanInstance
+{
+    pub pipeline_cache: VkPipelineCache,
+    pub instance: VkInstance,
+    pub physical_device: VkPhysicalDevice,
+    pub device: VkDevice,
+    pub graphics_queue: VkQueue,
+    pub get_instance_proc_addr: PFN_vkGetInstanceProcAddr,
+    pub queue_family_index: c_uint,
+    pub _resv: [*mut c_void; 8]
+}
+#[repr(C)]
+pub struct UnityVulkanMemory
+{
+    pub memory: VkDeviceMemory,
+    pub offset: VkDeviceSize,
+    pub size: VkDeviceSize,
+    pub mapped: *mut c_void,
+    pub flags: VkMemoryPropertyFlags,
+    pub memory_type_index: c_uint,
+    pub _resv: [*mut c_void; 4]
+}
+
+c_enum!{
+    UnityVulkanResourceAccessMode: c_uint
+    {
+        ObserveOnly = 0, PipelineBarrier = 1, Recreates = 2
+    }
+}
+
+#[repr(C)]
+pub struct UnityVulkanImage
+{
+    pub memory: UnityVulkanMemory,
+    pub image: VkImage,
+    pub layout: VkImageLayout,
+    pub aspect: VkImageAspectFlags,
+    pub usage: VkImageUsageFlags,
+    pub format: VkFormat,
+    pub extent: VkExtent3D,
+    pub tiling: VkImageTiling,
+    pub type_: VkImageType,
+    pub samples: VkSampleCountFlags,
+    pub layers: c_int,
+    pub mip_count: c_int,
+    pub _resv: [*mut c_void; 4]
+}
+#[repr(C)]
+pub struct UnityVulkanBuffer
+{
+    pub memory: UnityVulkanMemory,
+    pub buffer: VkBuffer,
+    pub size_in_bytes: isize,
+    pub usage: VkBufferUsageFlags,
+    pub _resv: [*mut c_void; 4]
+}
+#[repr(C)]
+pub struct UnityVulkanRecordingState
+{
+    pub command_buffer: VkCommandBuffer,
+    pub command_buffer_level: VkCommandBufferLevel,
+    pub render_pass: VkRenderPass,
+    pub framebuffer: VkFramebuffer,
+    pub sub_pass_index: c_int,
+    pub current_frame_number: c_ulonglong,
+    pub safe_frame_number: c_ulonglong,
+    pub _resv: [*mut c_void; 4]
+}
+
+c_enum!{
+    UnityVulkanEventRenderPassPreCondition: c_int
+    {
+        DontCare = 0, EnsureInside = 1, EnsureOutside = 2
+    }
+}
+c_enum!{
+    UnityVulkanGraphicsQueueAccess: c_uint
+    {
+        DontCare = 0, Allow = 1
+    }
+}
+
+/// `UnityVulkanEventConfigFlagBits` combined with `|`
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub struct UnityVulkanEventConfigFlags(pub u32);
+#[allow(non_upper_case_globals)]
+impl UnityVulkanEventConfigFlags
+{
+    pub const EnsurePreviousFrameSubmission: Self = UnityVulkanEventConfigFlags(1 << 0);
+    pub const FlushCommandBuffers: Self = UnityVulkanEventConfigFlags(1 << 1);
+    pub const SyncWorkerThreads: Self = UnityVulkanEventConfigFlags(1 << 2);
+    pub const ModifiesCommandBuffersState: Self = UnityVulkanEventConfigFlags(1 << 3);
+
+    pub const fn empty() -> Self { UnityVulkanEventConfigFlags(0) }
+    pub fn contains(self, other: Self) -> bool { self.0 & other.0 == other.0 }
+}
+impl std::ops::BitOr for UnityVulkanEventConfigFlags
+{
+    type Output = Self;
+    fn bitor(self, other: Self) -> Self { UnityVulkanEventConfigFlags(self.0 | other.0) }
+}
+impl std::ops::BitOrAssign for UnityVulkanEventConfigFlags
+{
+    fn bitor_assign(&mut self, other: Self) { self.0 |= other.0; }
+}
+
+#[repr(C)]
+#[derive(Clone)]
+pub struct UnityVulkanPluginEventConfig
+{
+    pub render_pass_precondition: UnityVulkanEventRenderPassPreCondition,
+    pub graphics_queue_access: UnityVulkanGraphicsQueueAccess,
+    pub flags: UnityVulkanEventConfigFlags
+}
+
+#[allow(non_upper_case_globals)]
+pub const UnityVulkanWholeImage: *const VkImageSubresource = std::ptr::null();
+pub type UnityVulkanInitCallback = extern "system" fn(get_instance_proc_addr: PFN_vkGetInstanceProcAddr, userdata: *mut c_void) -> PFN_vkGetInstanceProcAddr;
+
+c_enum!{
+    UnityVulkanSwapchainMode: c_uint
+    {
+        Default = 0, Offscreen = 1
+    }
+}
+
+#[repr(C)]
+#[derive(Clone)]
+pub struct UnityVulkanSwapchainConfiguration
+{
+    pub mode: UnityVulkanSwapchainMode
+}
+
+pub enum RenderSurfaceBase {}
+pub type UnityRenderBuffer = *mut RenderSurfaceBase;
+
+#[repr(C)]
+pub struct IUnityGraphicsVulkan
+{
+    pub intercept_initialization: extern "system" fn(func: UnityVulkanInitCallback, userdata: *mut c_void) -> bool,
+    pub intercept_vulkan_api: extern "system" fn(name: *const c_char, func: PFN_vkVoidFunction) -> Option<PFN_vkVoidFunction>,
+    pub configure_event: extern "system" fn(event_id: c_int, plugin_event_config: *const UnityVulkanPluginEventConfig),
+    pub instance: extern "system" fn() -> UnityVulkanInstance,
+    pub command_recording_state: extern "system" fn(out_command_recording_state: *mut UnityVulkanRecordingState, queue_access: UnityVulkanGraphicsQueueAccess) -> bool,
+    pub access_texture: extern "system" fn(native_texture: *mut c_void, sub_resource: *const VkImageSubresource, layout: VkImageLayout,
+        pipeline_stage_flags: VkPipelineStageFlags, access_flags: VkAccessFlags, access_mode: UnityVulkanResourceAccessMode, out_image: *mut UnityVulkanImage) -> bool,
+    pub access_render_buffer_texture: extern "system" fn(native_render_buffer: UnityRenderBuffer, sub_resource: *const VkImageSubresource, layout: VkImageLayout,
+        pipeline_stage_flags: VkPipelineStageFlags, access_flags: VkAccessFlags, access_mode: UnityVulkanResourceAccessMode, out_image: *mut UnityVulkanImage) -> bool,
+    pub access_render_buffer_resolve_texture: extern "system" fn(native_render_buffer: UnityRenderBuffer, sub_resource: *const VkImageSubresource, layout: VkImageLayout,
+        pipeline_stage_flags: VkPipelineStageFlags, access_flags: VkAccessFlags, access_mode: UnityVulkanResourceAccessMode, out_image: *mut UnityVulkanImage) -> bool,
+    pub access_buffer: extern "system" fn(native_buffer: *mut c_void ,pipeline_stage_flags: VkPipelineStageFlags, access_flags: VkAccessFlags,
+        access_mode: UnityVulkanResourceAccessMode, out_buffer: *mut UnityVulkanBuffer) -> bool,
+    pub ensure_outside_render_pass: extern "system" fn(),
+    pub ensure_inside_render_pass: extern "system" fn(),
+    pub access_queue: extern "system" fn(_: UnityRenderingEventAndData, event_id: c_int, user_data: *mut c_void, flush: bool),
+    pub configure_swapchain: extern "system" fn(swap_chain_config: *const UnityVulkanSwapchainConfiguration) -> bool
+}
+impl IUnityGraphicsVulkan
+{
+    pub const GUID: UnityInterfaceGUID = UnityInterfaceGUID
+    {
+        guid_high: 0x95355348d4ef4e11u64, guid_low: 0x9789313dfcffcc87u64
+    };
+}
+
+use std::ptr::NonNull;
+use std::ffi::CStr;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+#[repr(transparent)]
+#[derive(Clone, Copy)]
+pub struct UnityGraphicsVulkanRef(NonNull<IUnityGraphicsVulkan>);
+impl UnityGraphicsVulkanRef
+{
+    pub fn from_ptr(p: *mut IUnityGraphicsVulkan) -> Option<Self>
+    {
+        NonNull::new(p).map(UnityGraphicsVulkanRef)
+    }
+    pub fn from_interfaces(ifs: *mut IUnityInterfaces) -> Option<Self>
+    {
+        Self::from_ptr(unsafe { ((*ifs).get_interface)(IUnityGraphicsVulkan::GUID) as *mut IUnityGraphicsVulkan })
+    }
+
+    // Interception //
+
+    /// Lets `f` replace the vkGetInstanceProcAddr Unity creates its Vulkan instance with (it receives the loader's one).
+    /// Only effective when called from `UnityPluginLoad`. Returns false if Unity refused the callback.
+    /// The closure is kept alive for the rest of the process once accepted.
+    pub fn intercept_initialization<F>(&self, f: F) -> bool
+        where F: FnMut(PFN_vkGetInstanceProcAddr) -> PFN_vkGetInstanceProcAddr + Send + 'static
+    {
+        extern "system" fn trampoline<F>(get_instance_proc_addr: PFN_vkGetInstanceProcAddr, userdata: *mut c_void) -> PFN_vkGetInstanceProcAddr
+            where F: FnMut(PFN_vkGetInstanceProcAddr) -> PFN_vkGetInstanceProcAddr
+        {
+            let f = unsafe { &mut *(userdata as *mut F) };
+            // unwinding into Unity is undefined; a panicking hook leaves the instance uninstrumented
+            catch_unwind(AssertUnwindSafe(|| f(get_instance_proc_addr))).unwrap_or(get_instance_proc_addr)
+        }
+
+        let userdata = Box::into_raw(Box::new(f));
+        let accepted = unsafe { (self.0.as_ref().intercept_initialization)(trampoline::<F>, userdata as _) };
+        if !accepted { drop(unsafe { Box::from_raw(userdata) }); }
+        accepted
+    }
+    /// Replaces the Vulkan command `name` for Unity's own calls with `f`.
+    /// Returns the function Unity used so far, which `f` should forward to; None if Unity does not know the command.
+    pub fn intercept_vulkan_api(&self, name: &CStr, f: PFN_vkVoidFunction) -> Option<PFN_vkVoidFunction>
+    {
+        unsafe { (self.0.as_ref().intercept_vulkan_api)(name.as_ptr(), f) }
+    }
+
+    pub fn configure_event(&self, event_id: c_int, plugin_event_config: &UnityVulkanPluginEventConfig)
+    {
+        unsafe { (self.0.as_ref().configure_event)(event_id, plugin_event_config as _); }
+    }
+    
+    pub fn instance(&self) -> UnityVulkanInstance
+    {
+        unsafe { (self.0.as_ref().instance)() }
+    }
+    /// The command buffer Unity is recording into, None if it is not recording (or the queue access is not allowed)
+    pub fn command_recording_state(&self, queue_access: UnityVulkanGraphicsQueueAccess) -> Option<UnityVulkanRecordingState>
+    {
+        let mut os = std::mem::MaybeUninit::uninit();
+        let result = unsafe { (self.0.as_ref().command_recording_state)(os.as_mut_ptr(), queue_access) };
+        if result { Some(unsafe { os.assume_init() }) } else { None }
+    }
+    /// Selects who presents the frames (`UnityVulkanSwapchainMode`). Returns false if Unity refused the configuration.
+    pub fn configure_swapchain(&self, config: &UnityVulkanSwapchainConfiguration) -> bool
+    {
+        unsafe { (self.0.as_ref().configure_swapchain)(config as _) }
+    }
+
+    // Resource Access //
+
+    pub fn access_texture(&self,
+        native_texture: *mut c_void,
+        sub_resource: Option<&VkImageSubresource>,
+        layout: VkImageLayout,
+        pipeline_stage_flags: VkPipelineStageFlags,
+        access_flags: VkAccessFlags,
+        access_mode: UnityVulkanResourceAccessMode) -> Option<UnityVulkanImage>
+    {
+        let mut oi = std::mem::MaybeUninit::uninit();
+        let result = unsafe
+        {
+            (self.0.as_ref().access_texture)(native_texture,
+                sub_resource.map(|p| p as _).unwrap_or(std::ptr::null()), layout,
+                pipeline_stage_flags, access_flags, access_mode, oi.as_mut_ptr())
+        };
+        if result { Some(unsafe { oi.assume_init() }) } else { None }
+    }
+    pub fn access_render_buffer_texture(&self,
+        native_render_buffer: UnityRenderBuffer,
+        sub_resource: Option<&VkImageSubresource>,
+        layout: VkImageLayout,
+        pipeline_stage_flags: VkPipelineStageFlags,
+        access_flags: VkAccessFlags,
+        access_mode: UnityVulkanResourceAccessMode) -> Option<UnityVulkanImage>
+    {
+        let mut oi = std::mem::MaybeUninit::uninit();
+        let result = unsafe
+        {
+            (self.0.as_ref().access_render_buffer_texture)(native_render_buffer,
+                sub_resource.map(|p| p as _).unwrap_or(std::ptr::null()), layout,
+                pipeline_stage_flags, access_flags, access_mode, oi.as_mut_ptr())
+        };
+        if result { Some(unsafe { oi.assume_init() }) } else { None }
+    }
+    /// The single-sampled texture a multisampled render buffer is resolved into, if Unity has one
+    pub fn access_render_buffer_resolve_texture(&self,
+        native_render_buffer: UnityRenderBuffer,
+        sub_resource: Option<&VkImageSubresource>,
+        layout: VkImageLayout,
+        pipeline_stage_flags: VkPipelineStageFlags,
+        access_flags: VkAccessFlags,
+        access_mode: UnityVulkanResourceAccessMode) -> Option<UnityVulkanImage>
+    {
+        let mut oi = std::mem::MaybeUninit::uninit();
+        let result = unsafe
+        {
+            (self.0.as_ref().access_render_buffer_resolve_texture)(native_render_buffer,
+                sub_resource.map(|p| p as _).unwrap_or(std::ptr::null()), layout,
+                pipeline_stage_flags, access_flags, access_mode, oi.as_mut_ptr())
+        };
+        if result { Some(unsafe { oi.assume_init() }) } else { None }
+    }
+    pub fn access_buffer(&self,
+        native_buffer: *mut c_void,
+        pipeline_stage_flags: VkPipelineStageFlags,
+        access_flags: VkAccessFlags,
+        access_mode: UnityVulkanResourceAccessMode) -> Option<UnityVulkanBuffer>
+    {
+        let mut ob = std::mem::MaybeUninit::uninit();
+        let result = unsafe { (self.0.as_ref().access_buffer)(native_buffer, pipeline_stage_flags, access_flags, access_mode, ob.as_mut_ptr()) };
+        if result { Some(unsafe { ob.assume_init() }) } else { None }
+    }
+
+    // Queue Access //
+
+    /// Has Unity call `f(event_id)` on the render thread while the graphics queue may be used
+    /// (after submitting its own pending commands if `flush`). `f` is called once; it is leaked if Unity never calls it.
+    pub fn access_queue<F: FnOnce(c_int) + Send + 'static>(&self, f: F, event_id: c_int, flush: bool)
+    {
+        extern "system" fn trampoline<F: FnOnce(c_int)>(event_id: c_int, data: *mut c_void)
+        {
+            let f = unsafe { Box::from_raw(data as *mut F) };
+            // unwinding into Unity is undefined
+            let _ = catch_unwind(AssertUnwindSafe(move || f(event_id)));
+        }
+
+        unsafe { (self.0.as_ref().access_queue)(trampoline::<F>, event_id, Box::into_raw(Box::new(f)) as _, flush); }
+    }
+
+    // RenderPass Controls //
+
+    pub fn ensure_outside_render_pass(&self)
+    {
+        unsafe { (self.0.as_ref().ensure_outside_render_pass)(); }
+    }
+    pub fn ensure_inside_render_pass(&self)
+    {
+        unsafe { (self.0.as_ref().ensure_inside_render_pass)(); }
+    }
+}
@@ -0,0 +1,58 @@
+//! The exported entry points and the typed enums
+
+use libc::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use unity_native_plugin::*;
+
+static LOADED: AtomicUsize = AtomicUsize::new(0);
+static UNLOADED: AtomicUsize = AtomicUsize::new(0);
+
+fn load(interfaces: &IUnityInterfaces)
+{
+    LOADED.store(interfaces as *const _ as usize, Ordering::SeqCst);
+}
+fn unload()
+{
+    UNLOADED.fetch_add(1, Ordering::SeqCst);
+    panic!("unload failed");
+}
+unity_plugin!{ load: load, unload: unload }
+
+extern "system" fn get_interface(_: UnityInterfaceGUID) -> *mut IUnityInterface { std::ptr::null_mut() }
+extern "system" fn register_interface(_: UnityInterfaceGUID, _: *mut IUnityInterface) {}
+extern "system" fn get_interface_split(_: c_longlong, _: c_longlong) -> *mut IUnityInterface { std::ptr::null_mut() }
+extern "system" fn register_interface_split(_: c_longlong, _: c_longlong, _: *mut IUnityInterface) {}
+
+#[test]
+fn generated_entry_points_forward_and_contain_panics()
+{
+    let mut interfaces = IUnityInterfaces { get_interface, register_interface, get_interface_split, register_interface_split };
+
+    // a null registry never reaches the plugin
+    UnityPluginLoad(std::ptr::null_mut());
+    assert_eq!(LOADED.load(Ordering::SeqCst), 0);
+    UnityPluginLoad(&mut interfaces);
+    assert_eq!(LOADED.load(Ordering::SeqCst), &interfaces as *const _ as usize);
+
+    UnityPluginUnload();
+    assert_eq!(UNLOADED.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn enums_keep_unknown_values()
+{
+    assert_eq!(UnityGfxRenderer(21), UnityGfxRenderer::Vulkan);
+    assert_eq!(format!("{:?}", UnityGfxRenderer::Vulkan), "Vulkan");
+    assert_eq!(format!("{:?}", UnityGfxRenderer(99)), "UnityGfxRenderer(99)");
+    assert_eq!(format!("{:?}", UnityGfxDeviceEventType::AfterReset), "AfterReset");
+}
+
+#[test]
+fn event_config_flags_combine()
+{
+    let flags = UnityVulkanEventConfigFlags::EnsurePreviousFrameSubmission | UnityVulkanEventConfigFlags::FlushCommandBuffers;
+    assert_eq!(flags.0, 3);
+    assert!(flags.contains(UnityVulkanEventConfigFlags::FlushCommandBuffers));
+    assert!(!flags.contains(UnityVulkanEventConfigFlags::SyncWorkerThreads));
+    assert_eq!(UnityVulkanEventConfigFlags::empty(), UnityVulkanEventConfigFlags::default());
+}
@@ -0,0 +1,195 @@
+//! The safe wrapper over IUnityGraphicsVulkan, against a vtable that records what it was given
+
+use bedrock::vk::*;
+use lazy_static::*;
+use libc::*;
+use std::ffi::CString;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use unity_native_plugin::*;
+
+/// What the fake vtable was called with. Each test uses its own fields, so tests may run in parallel.
+#[derive(Default)]
+struct FakeState
+{
+    init_callback: Option<(UnityVulkanInitCallback, usize)>,
+    queue_access: Option<(UnityRenderingEventAndData, c_int, usize, bool)>,
+    swapchain_mode: Option<UnityVulkanSwapchainMode>,
+    intercepted: Vec<String>
+}
+lazy_static!{
+    static ref STATE: Mutex<FakeState> = Mutex::new(FakeState::default());
+}
+fn state() -> std::sync::MutexGuard<'static, FakeState> { STATE.lock().unwrap_or_else(|e| e.into_inner()) }
+
+const TEXTURE: usize = 0x100;
+const BUFFER: usize = 0x200;
+
+extern "system" fn original_function() {}
+extern "system" fn hook_function() {}
+extern "system" fn loader_get_instance_proc_addr(_: VkInstance, _: *const c_char) -> Option<PFN_vkVoidFunction> { None }
+extern "system" fn hooked_get_instance_proc_addr(_: VkInstance, _: *const c_char) -> Option<PFN_vkVoidFunction> { Some(hook_function) }
+
+extern "system" fn intercept_initialization(callback: UnityVulkanInitCallback, userdata: *mut c_void) -> bool
+{
+    state().init_callback = Some((callback, userdata as usize));
+    true
+}
+extern "system" fn intercept_vulkan_api(name: *const c_char, _: PFN_vkVoidFunction) -> Option<PFN_vkVoidFunction>
+{
+    let name = unsafe { std::ffi::CStr::from_ptr(name) }.to_string_lossy().into_owned();
+    let known = name == "vkQueuePresentKHR";
+    state().intercepted.push(name);
+    if known { Some(original_function) } else { None }
+}
+extern "system" fn configure_event(_: c_int, _: *const UnityVulkanPluginEventConfig) {}
+extern "system" fn instance() -> UnityVulkanInstance { unsafe { std::mem::zeroed() } }
+extern "system" fn command_recording_state(out: *mut UnityVulkanRecordingState, queue_access: UnityVulkanGraphicsQueueAccess) -> bool
+{
+    if queue_access != UnityVulkanGraphicsQueueAccess::Allow { return false; }
+    let mut s: UnityVulkanRecordingState = unsafe { std::mem::zeroed() };
+    s.current_frame_number = 42;
+    unsafe { out.write(s); }
+    true
+}
+fn image(image: usize, layout: VkImageLayout) -> UnityVulkanImage
+{
+    let mut i: UnityVulkanImage = unsafe { std::mem::zeroed() };
+    i.image = image as _;
+    i.layout = layout;
+    i
+}
+extern "system" fn access_texture(texture: *mut c_void, sub_resource: *const VkImageSubresource, layout: VkImageLayout,
+    _: VkPipelineStageFlags, _: VkAccessFlags, _: UnityVulkanResourceAccessMode, out: *mut UnityVulkanImage) -> bool
+{
+    // the whole image is requested with a null subresource
+    if texture as usize != TEXTURE || !sub_resource.is_null() { return false; }
+    unsafe { out.write(image(TEXTURE, layout)); }
+    true
+}
+extern "system" fn access_render_buffer_texture(_: UnityRenderBuffer, _: *const VkImageSubresource, _: VkImageLayout,
+    _: VkPipelineStageFlags, _: VkAccessFlags, _: UnityVulkanResourceAccessMode, _: *mut UnityVulkanImage) -> bool { false }
+extern "system" fn access_render_buffer_resolve_texture(rb: UnityRenderBuffer, sub_resource: *const VkImageSubresource, layout: VkImageLayout,
+    _: VkPipelineStageFlags, _: VkAccessFlags, _: UnityVulkanResourceAccessMode, out: *mut UnityVulkanImage) -> bool
+{
+    if rb.is_null() || unsafe { (*sub_resource).mipLevel } != 0 { return false; }
+    unsafe { out.write(image(rb as usize + 1, layout)); }
+    true
+}
+extern "system" fn access_buffer(buffer: *mut c_void, _: VkPipelineStageFlags, _: VkAccessFlags,
+    _: UnityVulkanResourceAccessMode, out: *mut UnityVulkanBuffer) -> bool
+{
+    if buffer as usize != BUFFER { return false; }
+    let mut b: UnityVulkanBuffer = unsafe { std::mem::zeroed() };
+    b.size_in_bytes = 1024;
+    unsafe { out.write(b); }
+    true
+}
+extern "system" fn ensure_outside_render_pass() {}
+extern "system" fn ensure_inside_render_pass() {}
+extern "system" fn access_queue(callback: UnityRenderingEventAndData, event_id: c_int, user_data: *mut c_void, flush: bool)
+{
+    state().queue_access = Some((callback, event_id, user_data as usize, flush));
+}
+extern "system" fn configure_swapchain(config: *const UnityVulkanSwapchainConfiguration) -> bool
+{
+    let mode = unsafe { (*config).mode };
+    state().swapchain_mode = Some(mode);
+    mode == UnityVulkanSwapchainMode::Offscreen
+}
+
+static GRAPHICS_VULKAN: IUnityGraphicsVulkan = IUnityGraphicsVulkan
+{
+    intercept_initialization, intercept_vulkan_api, configure_event, instance, command_recording_state,
+    access_texture, access_render_buffer_texture, access_render_buffer_resolve_texture, access_buffer,
+    ensure_outside_render_pass, ensure_inside_render_pass, access_queue, configure_swapchain
+};
+fn vulkan() -> UnityGraphicsVulkanRef
+{
+    UnityGraphicsVulkanRef::from_ptr(&GRAPHICS_VULKAN as *const _ as *mut _).unwrap()
+}
+
+#[test]
+fn null_interface_is_none()
+{
+    assert!(UnityGraphicsVulkanRef::from_ptr(std::ptr::null_mut()).is_none());
+}
+
+#[test]
+fn intercept_initialization_calls_closure_through_userdata()
+{
+    let calls = std::sync::Arc::new(AtomicUsize::new(0));
+    let c = calls.clone();
+    assert!(vulkan().intercept_initialization(move |gipa|
+    {
+        assert_eq!(gipa as usize, loader_get_instance_proc_addr as usize);
+        c.fetch_add(1, Ordering::SeqCst);
+        hooked_get_instance_proc_addr
+    }));
+
+    // as Unity does when it creates the instance
+    let (callback, userdata) = state().init_callback.take().unwrap();
+    let replaced = callback(loader_get_instance_proc_addr, userdata as _);
+    assert_eq!(replaced as usize, hooked_get_instance_proc_addr as usize);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    // a panicking hook keeps the loader's function
+    assert!(vulkan().intercept_initialization(|_| panic!("hook failed")));
+
+    let (callback, userdata) = state().init_callback.take().unwrap();
+    assert_eq!(callback(loader_get_instance_proc_addr, userdata as _) as usize, loader_get_instance_proc_addr as usize);
+}
+
+#[test]
+fn intercept_vulkan_api_returns_previous_function_or_none()
+{
+    let present = CString::new("vkQueuePresentKHR").unwrap();
+    let unknown = CString::new("vkNoSuchCommand").unwrap();
+    assert_eq!(vulkan().intercept_vulkan_api(&present, hook_function).map(|f| f as usize), Some(original_function as usize));
+    assert!(vulkan().intercept_vulkan_api(&unknown, hook_function).is_none());
+    assert!(state().intercepted.iter().any(|n| n == "vkNoSuchCommand"));
+}
+
+#[test]
+fn access_queue_runs_closure_once_with_event_id()
+{
+    let seen = std::sync::Arc::new(Mutex::new(Vec::new()));
+    let s = seen.clone();
+    vulkan().access_queue(move |event_id| s.lock().unwrap().push(event_id), 7, true);
+
+    let (callback, event_id, data, flush) = state().queue_access.take().unwrap();
+    assert!(flush);
+    callback(event_id, data as _);
+    assert_eq!(*seen.lock().unwrap(), [7]);
+}
+
+#[test]
+fn accessors_return_none_when_unity_refuses()
+{
+    let v = vulkan();
+    let texture = v.access_texture(TEXTURE as _, None, VK_IMAGE_LAYOUT_TRANSFER_SRC_OPTIMAL,
+        VK_PIPELINE_STAGE_TRANSFER_BIT, VK_ACCESS_TRANSFER_READ_BIT, UnityVulkanResourceAccessMode::PipelineBarrier);
+    assert_eq!(texture.map(|i| (i.image as usize, i.layout)), Some((TEXTURE, VK_IMAGE_LAYOUT_TRANSFER_SRC_OPTIMAL)));
+    assert!(v.access_texture(std::ptr::null_mut(), None, VK_IMAGE_LAYOUT_TRANSFER_SRC_OPTIMAL,
+        VK_PIPELINE_STAGE_TRANSFER_BIT, VK_ACCESS_TRANSFER_READ_BIT, UnityVulkanResourceAccessMode::PipelineBarrier).is_none());
+
+    let subresource = VkImageSubresource { aspectMask: VK_IMAGE_ASPECT_COLOR_BIT, arrayLayer: 0, mipLevel: 0 };
+    let resolved = v.access_render_buffer_resolve_texture(0x40 as _, Some(&subresource), VK_IMAGE_LAYOUT_GENERAL,
+        VK_PIPELINE_STAGE_TRANSFER_BIT, VK_ACCESS_TRANSFER_READ_BIT, UnityVulkanResourceAccessMode::ObserveOnly);
+    assert_eq!(resolved.map(|i| (i.image as usize, i.layout)), Some((0x41, VK_IMAGE_LAYOUT_GENERAL)));
+
+    let buffer = v.access_buffer(BUFFER as _, VK_PIPELINE_STAGE_TRANSFER_BIT, VK_ACCESS_TRANSFER_READ_BIT, UnityVulkanResourceAccessMode::PipelineBarrier);
+    assert_eq!(buffer.map(|b| b.size_in_bytes), Some(1024));
+    assert!(v.access_buffer(std::ptr::null_mut(), 0, 0, UnityVulkanResourceAccessMode::ObserveOnly).is_none());
+
+    assert_eq!(v.command_recording_state(UnityVulkanGraphicsQueueAccess::Allow).map(|s| s.current_frame_number), Some(42));
+    assert!(v.command_recording_state(UnityVulkanGraphicsQueueAccess::DontCare).is_none());
+}
+
+#[test]
+fn configure_swapchain_passes_mode()
+{
+    assert!(vulkan().configure_swapchain(&UnityVulkanSwapchainConfiguration { mode: UnityVulkanSwapchainMode::Offscreen }));
+    assert!(!vulkan().configure_swapchain(&UnityVulkanSwapchainConfiguration { mode: UnityVulkanSwapchainMode::Default }));
+    assert_eq!(state().swapchain_mode, Some(UnityVulkanSwapchainMode::Default));
+}